@@ -0,0 +1,213 @@
+use std::fmt;
+
+use crate::model::{Account, AccountType, Cash, Item, Lot, Portfolio, Transaction};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum AccountError {
+    UnknownAccount(String),
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The names of all accounts, those declared in the portfolio file followed by any only
+/// named by a holding or lot.
+pub fn account_names(portfolio: &Portfolio) -> Vec<String> {
+    let mut names: Vec<String> = portfolio.accounts.iter().map(|a| a.name.to_string()).collect();
+    let named = portfolio
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(_, holding) => holding.classification.account.as_ref(),
+            Item::Watch(_) => None,
+        })
+        .chain(portfolio.lots.iter().filter_map(|lot| lot.classification.account.as_ref()));
+    for name in named {
+        if !names.iter().any(|n| same_name(n, name)) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+pub fn find_account<'a>(portfolio: &'a Portfolio, name: &str) -> Option<&'a Account> {
+    portfolio.accounts.iter().find(|account| same_name(&account.name, name))
+}
+
+/// The account name with its type, for example "IRA (tax-deferred)"; holdings without
+/// an account are labelled "(none)".
+pub fn account_label(portfolio: &Portfolio, name: Option<&str>) -> String {
+    match name {
+        Some(name) => match find_account(portfolio, name) {
+            Some(account) => format!("{} ({})", account.name, account.account_type),
+            None => format!("{} ({})", name, AccountType::Taxable),
+        },
+        None => NO_ACCOUNT.to_string(),
+    }
+}
+
+/// A portfolio with only the holdings, lots, cash, transactions and realized gains of one
+/// account; watched symbols are always included. The portfolio's own transactions are
+/// not in any account so are not included.
+pub fn account_portfolio(portfolio: &Portfolio, name: &str) -> Result<Portfolio, AccountError> {
+    if !account_names(portfolio).iter().any(|n| same_name(n, name)) {
+        return Err(AccountError::UnknownAccount(name.to_string()));
+    }
+    Ok(Portfolio {
+        items: portfolio.items.iter().filter(|item| item_in(item, name)).cloned().collect(),
+        lots: portfolio.lots.iter().filter(|lot| lot_in(lot, name)).cloned().collect(),
+        transactions: Vec::new(),
+        cash: portfolio.cash.iter().filter(|cash| in_account(&cash.account, name)).cloned().collect(),
+        accounts: find_account(portfolio, name).into_iter().cloned().collect(),
+        realized: portfolio
+            .realized
+            .iter()
+            .filter(|gain| in_account(&gain.account, name))
+            .cloned()
+            .collect(),
+        ..portfolio.clone()
+    })
+}
+
+/// Append a transaction to the log of the named account, declaring the account as
+/// taxable if it is not already, or to the portfolio's own log.
+pub fn add_transaction(portfolio: &mut Portfolio, name: Option<&str>, transaction: Transaction) {
+    match name {
+        Some(name) => match portfolio.accounts.iter_mut().find(|account| same_name(&account.name, name)) {
+            Some(account) => account.transactions.push(transaction),
+            None => portfolio.accounts.push(Account {
+                name: name.to_string(),
+                account_type: AccountType::Taxable,
+                transactions: vec![transaction],
+            }),
+        },
+        None => portfolio.transactions.push(transaction),
+    }
+}
+
+/// Merge a portfolio returned by `account_portfolio`, and since changed, back into the
+/// stored portfolio. The account's holdings, lots and cash replace its previous ones,
+/// with those unchanged kept in place, so the order of the file is preserved; a watched
+/// symbol removed from the account's portfolio is removed from the stored one.
+/// Corporate actions are taken from the account's portfolio.
+pub fn merge(stored: &Portfolio, name: &str, updated: &Portfolio) -> Portfolio {
+    let mut accounts = stored.accounts.clone();
+    if let Some(changed) = find_account(updated, name) {
+        match accounts.iter_mut().find(|account| same_name(&account.name, name)) {
+            Some(account) => *account = changed.clone(),
+            None => accounts.push(changed.clone()),
+        }
+    }
+    // gains are only ever appended, so those not already stored are new.
+    let stored_gains = stored.realized.iter().filter(|gain| in_account(&gain.account, name)).count();
+    let holdings: Vec<Item> = updated.items.iter().filter(|item| holding_in(item, name)).cloned().collect();
+    let items: Vec<Item> = splice(&stored.items, &holdings, |item| holding_in(item, name))
+        .into_iter()
+        .filter(|item| match item {
+            Item::Watch(symbol) => updated.items.iter().any(|i| match i {
+                Item::Watch(s) => s == symbol,
+                Item::Price(_, _) => false,
+            }),
+            Item::Price(_, _) => true,
+        })
+        .collect();
+    Portfolio {
+        items,
+        lots: splice(&stored.lots, &updated.lots, |lot| lot_in(lot, name)),
+        cash: splice(&stored.cash, &updated.cash, |cash: &Cash| in_account(&cash.account, name)),
+        realized: stored
+            .realized
+            .iter()
+            .chain(updated.realized.iter().skip(stored_gains))
+            .cloned()
+            .collect(),
+        accounts,
+        // actions apply to every account, and the account's view includes them all.
+        actions: updated.actions.clone(),
+        ..stored.clone()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const NO_ACCOUNT: &str = "(none)";
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountError::UnknownAccount(name) => write!(f, "No account named {}", name),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+fn in_account(account: &Option<String>, name: &str) -> bool {
+    match account {
+        Some(account) => same_name(account, name),
+        None => false,
+    }
+}
+
+/// Watched symbols belong to every account.
+fn item_in(item: &Item, name: &str) -> bool {
+    match item {
+        Item::Price(_, _) => holding_in(item, name),
+        Item::Watch(_) => true,
+    }
+}
+
+fn holding_in(item: &Item, name: &str) -> bool {
+    match item {
+        Item::Price(_, holding) => in_account(&holding.classification.account, name),
+        Item::Watch(_) => false,
+    }
+}
+
+fn lot_in(lot: &Lot, name: &str) -> bool {
+    in_account(&lot.classification.account, name)
+}
+
+/// Replace the entries of `stored` matching `is_member` with `updated`, keeping the
+/// place of each entry that is unchanged; the updated entries before it are inserted
+/// ahead of it, and any after the last are inserted where the last member was, or at the
+/// end.
+fn splice<T: Clone + PartialEq, F: Fn(&T) -> bool>(stored: &[T], updated: &[T], is_member: F) -> Vec<T> {
+    let last = stored.iter().rposition(&is_member);
+    let mut merged: Vec<T> = Vec::new();
+    let mut next = 0;
+    for (index, entry) in stored.iter().enumerate() {
+        if !is_member(entry) {
+            merged.push(entry.clone());
+            continue;
+        }
+        // a member that is changed, or removed, is dropped from its place.
+        if let Some(found) = updated[next..].iter().position(|u| u == entry) {
+            merged.extend(updated[next..=next + found].iter().cloned());
+            next += found + 1;
+        }
+        if Some(index) == last {
+            merged.extend(updated[next..].iter().cloned());
+            next = updated.len();
+        }
+    }
+    merged.extend(updated[next..].iter().cloned());
+    merged
+}
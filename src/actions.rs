@@ -0,0 +1,255 @@
+use fin_model::prelude::*;
+
+use crate::display::{money_from_minor, money_times};
+use crate::ledger;
+use crate::model::{CorporateAction, Holding, Item, Lot, Portfolio, Trade, Transaction};
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Apply a single action to `quantity` shares of `symbol` bought for a total of `cost`,
+/// returning the resulting shares, and their cost, of each symbol; the first is always
+/// what became of the original shares. Shares of other symbols are unchanged.
+pub fn apply(action: &CorporateAction, symbol: &Symbol, quantity: u32, cost: Money) -> Vec<(Symbol, u32, Money)> {
+    if !same_symbol(action.symbol(), symbol) {
+        return vec![(symbol.to_string(), quantity, cost)];
+    }
+    match action {
+        CorporateAction::Split(_, _, ratio) => vec![(symbol.to_string(), ratio.apply(quantity), cost)],
+        CorporateAction::SymbolChange(_, _, new_symbol) => vec![(new_symbol.to_string(), quantity, cost)],
+        CorporateAction::SpinOff(_, _, new_symbol, ratio, percent) => {
+            let moved = money_from_minor(cost.currency, (cost.minor_amount() as f64 * percent / 100.0).round() as i64);
+            vec![
+                (symbol.to_string(), quantity, cost - moved),
+                (new_symbol.to_string(), ratio.apply(quantity), moved),
+            ]
+        }
+        CorporateAction::Merger(_, _, acquirer, ratio, cash) => {
+            let cost = match cash {
+                Some(cash) if cash.currency == cost.currency => {
+                    let paid = cash.minor_amount() as i64 * i64::from(quantity);
+                    money_from_minor(cost.currency, (cost.minor_amount() as i64 - paid).max(0))
+                }
+                _ => cost,
+            };
+            vec![(acquirer.to_string(), ratio.apply(quantity), cost)]
+        }
+    }
+}
+
+/// The portfolio with every corporate action up to, and including, `until` applied to its
+/// holdings and lots; watched symbols follow symbol changes and mergers.
+pub fn adjusted(portfolio: &Portfolio, until: Date) -> Portfolio {
+    if portfolio.actions.is_empty() {
+        return portfolio.clone();
+    }
+    Portfolio {
+        items: adjusted_items(portfolio, until),
+        lots: adjusted_lots(portfolio, until),
+        ..portfolio.clone()
+    }
+}
+
+/// The portfolio as held at the close of `date`, with the actions up to `date` applied.
+/// A lot already adjusted for a later action is restored from its original quantity and
+/// price, and a lot created by a later action, such as a spin-off, is left out.
+pub fn adjusted_on(portfolio: &Portfolio, date: Date) -> Portfolio {
+    let lots: Vec<Lot> = portfolio
+        .lots
+        .iter()
+        .filter_map(|lot| match (lot.adjusted, &lot.original) {
+            (Some(adjusted), Some(original)) if adjusted > date => Some(Lot {
+                quantity: original.quantity,
+                purchase_price: original.price,
+                adjusted: None,
+                original: None,
+                ..lot.clone()
+            }),
+            (Some(adjusted), None) if adjusted > date => None,
+            _ => Some(lot.clone()),
+        })
+        .collect();
+    adjusted(&Portfolio { lots, ..portfolio.clone() }, date)
+}
+
+/// The portfolio's items with the actions up to `until` applied to each holding bought
+/// before the action; a holding without a purchase date is assumed to be held before
+/// every action. A spin-off adds a new holding after its parent.
+pub fn adjusted_items(portfolio: &Portfolio, until: Date) -> Vec<Item> {
+    let actions = sorted_actions(portfolio, until);
+    let mut items: Vec<Item> = Vec::new();
+    for item in &portfolio.items {
+        match item {
+            Item::Watch(symbol) => {
+                let renamed = actions.iter().fold(symbol.to_string(), |symbol, action| match action {
+                    CorporateAction::SymbolChange(_, s, new_symbol) | CorporateAction::Merger(_, s, new_symbol, _, _)
+                        if same_symbol(s, &symbol) => new_symbol.to_string(),
+                    _ => symbol,
+                });
+                items.push(Item::Watch(renamed));
+            }
+            Item::Price(symbol, holding) => {
+                let applies = |action: &CorporateAction| match holding.purchase_date {
+                    Some(purchase_date) => purchase_date < action.date(),
+                    None => true,
+                };
+                let cost = money_times(holding.purchase_price, holding.quantity);
+                for (symbol, quantity, cost) in apply_all(&actions, symbol, holding.quantity, cost, applies) {
+                    if quantity > 0 {
+                        items.push(Item::Price(symbol, Holding {
+                            quantity,
+                            purchase_price: per_share(cost, quantity),
+                            ..holding.clone()
+                        }));
+                    }
+                }
+            }
+        }
+    }
+    items
+}
+
+/// The portfolio's lots with the actions up to `until` applied to each lot bought before,
+/// and not already adjusted for, the action. Each adjusted lot records the date of the
+/// last action applied, and its quantity and price before the first; a spin-off adds a
+/// new lot, with a new identifier.
+pub fn adjusted_lots(portfolio: &Portfolio, until: Date) -> Vec<Lot> {
+    adjusted_lot_groups(portfolio, until).into_iter().flatten().collect()
+}
+
+/// As `adjusted_lots`, but with the lots each of the portfolio's lots became kept
+/// together, in the same order as the portfolio's lots.
+pub fn adjusted_lot_groups(portfolio: &Portfolio, until: Date) -> Vec<Vec<Lot>> {
+    let actions = sorted_actions(portfolio, until);
+    let mut next_id = portfolio.next_lot_id();
+    let mut groups: Vec<Vec<Lot>> = Vec::new();
+    for lot in &portfolio.lots {
+        let mut lots: Vec<Lot> = Vec::new();
+        let applies = |action: &CorporateAction| {
+            let bought = match lot.purchase_date {
+                Some(purchase_date) => purchase_date < action.date(),
+                None => true,
+            };
+            let pending = match lot.adjusted {
+                Some(adjusted) => adjusted < action.date(),
+                None => true,
+            };
+            bought && pending
+        };
+        let last = actions.iter().filter(|action| applies(action)).map(|action| action.date()).max();
+        let cost = money_times(lot.purchase_price, lot.quantity);
+        let results = apply_all(&actions, &lot.symbol, lot.quantity, cost, applies);
+        for (index, (symbol, quantity, cost)) in results.into_iter().enumerate() {
+            if quantity > 0 {
+                lots.push(Lot {
+                    id: if index == 0 { lot.id } else { next_id },
+                    symbol,
+                    quantity,
+                    purchase_price: per_share(cost, quantity),
+                    adjusted: last.or(lot.adjusted),
+                    original: match (index, last) {
+                        (0, Some(_)) => lot.original.clone().or(Some(Trade {
+                            quantity: lot.quantity,
+                            price: lot.purchase_price,
+                        })),
+                        (0, None) => lot.original.clone(),
+                        _ => None,
+                    },
+                    ..lot.clone()
+                });
+                if index > 0 {
+                    next_id += 1;
+                }
+            }
+        }
+        groups.push(lots);
+    }
+    groups
+}
+
+/// The symbols whose holdings become shares of `symbol` through the portfolio's actions,
+/// including `symbol` itself.
+pub fn source_symbols(portfolio: &Portfolio, symbol: &Symbol) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = vec![symbol.to_string()];
+    let mut index = 0;
+    while index < symbols.len() {
+        for action in &portfolio.actions {
+            let target = match action {
+                CorporateAction::Split(_, _, _) => None,
+                CorporateAction::SymbolChange(_, _, new_symbol)
+                | CorporateAction::SpinOff(_, _, new_symbol, _, _)
+                | CorporateAction::Merger(_, _, new_symbol, _, _) => Some(new_symbol),
+            };
+            if let Some(target) = target {
+                if same_symbol(target, &symbols[index])
+                    && !symbols.iter().any(|s| same_symbol(s, action.symbol()))
+                {
+                    symbols.push(action.symbol().to_string());
+                }
+            }
+        }
+        index += 1;
+    }
+    symbols
+}
+
+/// Whether a split of `symbol` on `date` is already recorded, either as an action or in
+/// any transaction log; recording it again would apply it twice.
+pub fn has_split(portfolio: &Portfolio, symbol: &Symbol, date: Date) -> bool {
+    let in_actions = portfolio.actions.iter().any(|action| match action {
+        CorporateAction::Split(d, s, _) => *d == date && same_symbol(s, symbol),
+        _ => false,
+    });
+    in_actions
+        || ledger::all_transactions(portfolio).iter().any(|transaction| match transaction {
+            Transaction::Split(d, s, _) => *d == date && same_symbol(s, symbol),
+            _ => false,
+        })
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn same_symbol(a: &str, b: &str) -> bool {
+    a.to_uppercase() == b.to_uppercase()
+}
+
+/// The actions on, or before, `until` in date order.
+fn sorted_actions(portfolio: &Portfolio, until: Date) -> Vec<CorporateAction> {
+    let mut actions: Vec<CorporateAction> = portfolio
+        .actions
+        .iter()
+        .filter(|action| action.date() <= until)
+        .cloned()
+        .collect();
+    actions.sort_by_key(|action| action.date());
+    actions
+}
+
+/// Apply each action, in order, to the shares resulting from the previous ones.
+fn apply_all<F: Fn(&CorporateAction) -> bool>(
+    actions: &[CorporateAction],
+    symbol: &Symbol,
+    quantity: u32,
+    cost: Money,
+    applies: F,
+) -> Vec<(Symbol, u32, Money)> {
+    let mut shares: Vec<(Symbol, u32, Money)> = vec![(symbol.to_string(), quantity, cost)];
+    for action in actions.iter().filter(|action| applies(action)) {
+        shares = shares
+            .into_iter()
+            .flat_map(|(symbol, quantity, cost)| apply(action, &symbol, quantity, cost))
+            .collect();
+    }
+    shares
+}
+
+fn per_share(cost: Money, quantity: u32) -> Money {
+    if quantity == 0 {
+        Money::zero(cost.currency)
+    } else {
+        money_from_minor(cost.currency, cost.minor_amount() as i64 / quantity as i64)
+    }
+}
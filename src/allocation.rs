@@ -0,0 +1,395 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use fin_model::prelude::*;
+use fin_model::quote::FetchPriceQuote;
+
+use crate::cash::{cash_balances, cash_symbol, CASH_ASSET_CLASS};
+use crate::display::{money_f64, money_times};
+use crate::fx::Converter;
+use crate::ledger;
+use crate::model::{Classification, Item, Portfolio};
+use crate::quotes::{fetch_quotes, partition, QuoteFailures, DEFAULT_WORKERS};
+use crate::report::{OutputFormat, Report, Value};
+use crate::show::print_rates;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The classification field that holdings are grouped by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grouping {
+    AssetClass,
+    Sector,
+    Region,
+    Tag,
+    Account,
+}
+
+/// The market value of one group of holdings, and its weight as a percentage of the
+/// portfolio's value.
+#[derive(Clone, Debug)]
+pub struct Breakdown {
+    pub group: String,
+    pub symbols: Vec<Symbol>,
+    pub value: Money,
+    pub weight: f64,
+}
+
+#[derive(Debug)]
+pub enum AllocationError {
+    /// Values are in more than one currency and there is no reporting currency to
+    /// convert them into, so there is no total to weigh them against.
+    MixedCurrencies(Vec<String>),
+}
+
+pub const GROUPING_NAMES: [&str; 5] = ["asset-class", "sector", "region", "tag", "account"];
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const UNCLASSIFIED: &str = "(none)";
+
+const BAR_WIDTH: usize = 40;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Show the allocation, returning the number of symbols whose quote could not be
+/// retrieved; each is shown as an error row after the groups.
+pub fn show_allocation<T: FetchPriceQuote + Sync>(portfolio: Portfolio, provider: T, by: Grouping, format: OutputFormat, converter: Option<&Converter>) -> usize {
+    let (breakdowns, failures) = match allocation(&portfolio, &provider, by, converter) {
+        Ok(result) => result,
+        Err(AllocationError::MixedCurrencies(codes)) => {
+            println!("Values are in {}, use --report-currency to show the allocation in one currency", codes.join(", "));
+            return 0;
+        }
+    };
+
+    let mut report = Report::new(&[by.title(), "Symbols", "Value", "Weight"]);
+    let largest = breakdowns.iter().map(|b| b.weight).fold(0.0, f64::max);
+    if format == OutputFormat::Table {
+        report.titles.push(String::new());
+    }
+    for breakdown in &breakdowns {
+        let mut row = vec![
+            Value::text(&breakdown.group),
+            Value::Text(breakdown.symbols.join(", ")),
+            Value::Money(breakdown.value),
+            Value::Percent(breakdown.weight),
+        ];
+        if format == OutputFormat::Table {
+            row.push(Value::Text(bar(breakdown.weight, largest)));
+        }
+        report.add_row(row);
+    }
+    for (symbol, err) in &failures {
+        let mut row = vec![Value::Empty, Value::text(symbol), Value::Error(format!("{:?}", err))];
+        row.resize(report.titles.len(), Value::Empty);
+        report.add_row(row);
+    }
+    report.print(format);
+    print_rates(converter, format);
+    if format == OutputFormat::Table {
+        if by == Grouping::Tag {
+            println!("A holding with more than one tag is counted in each of them");
+        }
+        if !failures.is_empty() {
+            println!("Weights leave out the holdings without a quote");
+        }
+    }
+    failures.len()
+}
+
+/// Group the current holdings by a classification field, valued at current quotes; groups
+/// are in order of decreasing value. Holdings without the field are grouped together and
+/// cash balances are in the cash asset class. Holdings whose quote could not be retrieved
+/// are left out, and returned with their error. Without a converter every value must be
+/// in the one currency.
+pub fn allocation<T: FetchPriceQuote + Sync>(
+    portfolio: &Portfolio,
+    provider: &T,
+    by: Grouping,
+    converter: Option<&Converter>,
+) -> Result<(Vec<Breakdown>, QuoteFailures), AllocationError> {
+    let items = ledger::current_items(portfolio);
+    let symbols: Vec<Symbol> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(symbol, _) => Some(symbol.to_string()),
+            _ => None,
+        })
+        .collect();
+    let (quotes, failures) = partition(fetch_quotes(provider, &symbols, DEFAULT_WORKERS));
+    let classifications = classifications(portfolio);
+
+    let convert = |symbol: &Symbol, value: Money| match converter {
+        Some(converter) => match converter.convert(value) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!("Could not convert value of {}: {}", symbol, err);
+                None
+            }
+        },
+        None => Some(value),
+    };
+    // each holding, and each cash balance, with its value and classification.
+    let mut values: Vec<(Symbol, Money, Classification)> = Vec::new();
+    for item in items {
+        if let Item::Price(symbol, holding) = item {
+            let price = match quotes.get(&symbol) {
+                Some(quote) => quote.data.latest.price,
+                None => continue,
+            };
+            if let Some(value) = convert(&symbol, money_times(price, holding.quantity)) {
+                let classification = match classifications.get(&symbol) {
+                    Some(fallback) => with_fallback(holding.classification, fallback),
+                    None => holding.classification,
+                };
+                values.push((symbol, value, classification));
+            }
+        }
+    }
+    // an overdrawn balance, such as from buys without a recorded deposit, is not allocated.
+    for cash in cash_balances(portfolio).into_iter().filter(|cash| cash.balance.minor_amount() > 0) {
+        let symbol = cash_symbol(&cash.balance);
+        if let Some(value) = convert(&symbol, cash.balance) {
+            let classification = Classification {
+                asset_class: Some(CASH_ASSET_CLASS.to_string()),
+                account: cash.account,
+                ..Default::default()
+            };
+            values.push((symbol, value, classification));
+        }
+    }
+
+    // weights are shares of one total, so values can't be summed across currencies.
+    let mut codes: Vec<String> = values.iter().map(|(_, value, _)| value.currency.code()).collect();
+    codes.sort();
+    codes.dedup();
+    if codes.len() > 1 {
+        return Err(AllocationError::MixedCurrencies(codes));
+    }
+
+    let mut order: Vec<String> = Vec::new();
+    let mut groups: HashMap<String, Breakdown> = HashMap::new();
+    let mut total: Option<Money> = None;
+    for (symbol, value, classification) in values {
+        total = Some(total.map_or(value, |total| total + value));
+        for group in by.groups(&classification) {
+            let breakdown = groups.entry(group.to_string()).or_insert_with(|| {
+                order.push(group.to_string());
+                Breakdown {
+                    group: group.to_string(),
+                    symbols: Vec::new(),
+                    value: Money::zero(value.currency),
+                    weight: 0.0,
+                }
+            });
+            breakdown.value = breakdown.value + value;
+            if !breakdown.symbols.contains(&symbol) {
+                breakdown.symbols.push(symbol.to_string());
+            }
+        }
+    }
+
+    let total = total.map(|total| money_f64(&total)).unwrap_or(0.0);
+    let mut breakdowns: Vec<Breakdown> = order
+        .iter()
+        .filter_map(|group| groups.remove(group))
+        .map(|breakdown| Breakdown {
+            weight: if total > 0.0 { money_f64(&breakdown.value) / total * 100.0 } else { 0.0 },
+            ..breakdown
+        })
+        .collect();
+    breakdowns.sort_by_key(|breakdown| Reverse(breakdown.value.minor_amount()));
+    Ok((breakdowns, failures))
+}
+
+/// The classification of each symbol, taken from the first holding, or lot, of that
+/// symbol that has one; used for positions, such as those from transactions, that have
+/// none of their own.
+pub fn classifications(portfolio: &Portfolio) -> HashMap<Symbol, Classification> {
+    let mut classifications: HashMap<Symbol, Classification> = HashMap::new();
+    let holdings = portfolio.items.iter().filter_map(|item| match item {
+        Item::Price(symbol, holding) => Some((symbol, &holding.classification)),
+        _ => None,
+    });
+    let lots = portfolio.lots.iter().map(|lot| (&lot.symbol, &lot.classification));
+    for (symbol, classification) in holdings.chain(lots) {
+        if *classification != Classification::default() && !classifications.contains_key(symbol) {
+            classifications.insert(symbol.to_string(), classification.clone());
+        }
+    }
+    classifications
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Grouping {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Grouping::AssetClass => "Asset Class",
+            Grouping::Sector => "Sector",
+            Grouping::Region => "Region",
+            Grouping::Tag => "Tag",
+            Grouping::Account => "Account",
+        }
+    }
+
+    /// The groups a holding belongs to; one for each tag, and one for any other field.
+    pub fn groups(&self, classification: &Classification) -> Vec<String> {
+        let field = match self {
+            Grouping::AssetClass => &classification.asset_class,
+            Grouping::Sector => &classification.sector,
+            Grouping::Region => &classification.region,
+            Grouping::Account => &classification.account,
+            Grouping::Tag => {
+                return if classification.tags.is_empty() {
+                    vec![UNCLASSIFIED.to_string()]
+                } else {
+                    classification.tags.clone()
+                };
+            }
+        };
+        match field {
+            Some(value) => vec![value.to_string()],
+            None => vec![UNCLASSIFIED.to_string()],
+        }
+    }
+}
+
+impl FromStr for Grouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asset-class" | "asset_class" => Ok(Grouping::AssetClass),
+            "sector" => Ok(Grouping::Sector),
+            "region" => Ok(Grouping::Region),
+            "tag" | "tags" => Ok(Grouping::Tag),
+            "account" => Ok(Grouping::Account),
+            other => Err(format!("unknown grouping {}, expecting asset-class, sector, region, tag, or account", other)),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Fill any field missing from a classification, such as that of a position from an
+/// account's transactions which only has the account, from `fallback`. The account is
+/// never filled, a position outside any account isn't held in another holding's account.
+fn with_fallback(classification: Classification, fallback: &Classification) -> Classification {
+    Classification {
+        tags: if classification.tags.is_empty() { fallback.tags.clone() } else { classification.tags },
+        asset_class: classification.asset_class.or(fallback.asset_class.clone()),
+        sector: classification.sector.or(fallback.sector.clone()),
+        region: classification.region.or(fallback.region.clone()),
+        account: classification.account,
+    }
+}
+
+/// A bar proportional to `weight`, the largest weight fills the full width.
+fn bar(weight: f64, largest: f64) -> String {
+    if largest <= 0.0 {
+        String::new()
+    } else {
+        "#".repeat((weight / largest * BAR_WIDTH as f64).round() as usize)
+    }
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fin_model::provider::Provider;
+    use fin_model::quote::{LatestPrice, Quote, QuoteData};
+    use steel_cent::currency::with_code;
+
+    use crate::model::Holding;
+
+    /// Quotes each symbol at ten dollars, or ten euros for symbols ending in `.DE`.
+    struct TenProvider;
+
+    impl Provider for TenProvider {
+        fn name(&self) -> &str {
+            "ten"
+        }
+    }
+
+    impl FetchPriceQuote for TenProvider {
+        fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+            let code = if for_symbol.ends_with(".DE") { "EUR" } else { "USD" };
+            Ok(Quote {
+                symbol: for_symbol,
+                data: QuoteData {
+                    latest: LatestPrice {
+                        price: Money::of_major(with_code(code).unwrap(), 10),
+                        change: None,
+                        percentage: None,
+                    },
+                    range: None,
+                },
+            })
+        }
+    }
+
+    fn holding(symbol: &str, quantity: u32, classification: Classification) -> Item {
+        Item::Price(
+            symbol.to_string(),
+            Holding {
+                quantity,
+                purchase_price: Money::of_major(with_code("USD").unwrap(), 10),
+                purchase_date: None,
+                classification,
+            },
+        )
+    }
+
+    #[test]
+    fn does_not_weigh_mixed_currencies() {
+        let portfolio = Portfolio {
+            items: vec![holding("VTI", 1, Classification::default()), holding("SAP.DE", 1, Classification::default())],
+            ..Default::default()
+        };
+        match allocation(&portfolio, &TenProvider, Grouping::AssetClass, None) {
+            Err(AllocationError::MixedCurrencies(codes)) => assert_eq!(codes, vec!["EUR", "USD"]),
+            other => panic!("expected mixed currencies, not {:?}", other.map(|(breakdowns, _)| breakdowns)),
+        }
+    }
+
+    #[test]
+    fn does_not_fall_back_to_another_account() {
+        let ira = Classification {
+            asset_class: Some("equity".to_string()),
+            account: Some("ira".to_string()),
+            ..Default::default()
+        };
+        let portfolio = Portfolio {
+            items: vec![holding("VTI", 3, ira), holding("VTI", 1, Classification::default())],
+            ..Default::default()
+        };
+
+        let (breakdowns, _) = allocation(&portfolio, &TenProvider, Grouping::Account, None).unwrap();
+        let groups: Vec<(&str, f64)> = breakdowns.iter().map(|b| (b.group.as_str(), b.weight)).collect();
+        assert_eq!(groups, vec![("ira", 75.0), (UNCLASSIFIED, 25.0)]);
+
+        // other fields still come from the classified holding.
+        let (breakdowns, _) = allocation(&portfolio, &TenProvider, Grouping::AssetClass, None).unwrap();
+        assert_eq!(breakdowns.len(), 1);
+        assert_eq!(breakdowns[0].group, "equity");
+    }
+}
@@ -2,151 +2,527 @@
 extern crate log;
 extern crate flexi_logger;
 
-use chrono::Local;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use chrono::{Datelike, Local};
 use fin_model::prelude::*;
-use fin_model::provider::Provider;
-use fin_iex::IEXProvider;
-use steel_cent::currency::with_code;
+use fin_model::quote::FetchPriceQuote;
+use steel_cent::currency::{Currency, with_code};
 
-use portfolio::display::DATE_FMT;
-use portfolio::holdings::show_holdings;
+use portfolio::accounts;
+use portfolio::actions;
+use portfolio::allocation::{show_allocation, Grouping, GROUPING_NAMES};
+use portfolio::cache::{command_ttl, CacheMode, CachingProvider};
+use portfolio::display::{money_f64, DATE_FMT};
+use portfolio::fx;
+use portfolio::fx::{Converter, FxError};
+use portfolio::gains::show_gains;
+use portfolio::history;
+use portfolio::history::{show_history, Interval, PriceFile, INTERVAL_NAMES};
+use portfolio::holdings::{add_holding, show_holdings, update_holding, HoldingUpdate};
+use portfolio::income::show_income;
+use portfolio::journal;
+use portfolio::journal::{EntryKind, JournalError};
+use portfolio::ledger;
+use portfolio::lots;
+use portfolio::lots::{Method, SaleError};
 use portfolio::model;
-use portfolio::model::{Holding, Item, ModelError, Portfolio};
-use portfolio::show::show_portfolio;
-use portfolio::watch::watch_portfolio;
+use portfolio::model::{
+    Classification, CorporateAction, Holding, Item, ModelError, Portfolio, ProviderConfig, Ratio, Transaction,
+    DEFAULT_CURRENCY,
+};
+use portfolio::performance::{benchmark_return, show_performance};
+use portfolio::providers::{parse_provider_arg, Registry, SymbolRouter, DEFAULT_PROVIDER_NAME};
+use portfolio::rebalance::{show_rebalance, RebalanceOptions};
+use portfolio::report::{OutputFormat, Report, Value, FORMAT_NAMES};
+use portfolio::risk;
+use portfolio::risk::{show_risk, RiskOptions};
+use portfolio::show::{show_portfolio, show_portfolio_with_age};
+use portfolio::storage;
+use portfolio::watch::{watch_portfolio, DEFAULT_REFRESH_DELAY};
+
+/// Exit status when the portfolio file has problems.
+const EXIT_INVALID_FILE: i32 = 2;
 
-const DEFAULT_CURRENCY: &'static str = "USD";
+/// Exit status when the report was shown but some quotes could not be retrieved.
+const EXIT_QUOTE_ERRORS: i32 = 3;
+
+/// Exit status when an argument is invalid, such as a value that can't be parsed or a
+/// symbol that isn't held, as for clap's own usage errors.
+const EXIT_INVALID_ARGUMENT: i32 = 1;
 
 #[derive(Debug)]
 enum Command {
-    Show,
-    Watch,
+    Show(bool),
+    Watch(Option<String>),
+    Gains,
+    Rebalance(Option<String>, Option<String>, bool, bool),
+    Allocation(Option<String>),
+    History(Option<String>, Option<String>, Option<String>),
+    Performance(Option<String>, Option<String>, Option<String>),
+    Risk(Option<String>, Option<String>, Option<String>, Option<String>, Option<String>),
+    Income(Option<String>),
 
     Holdings,
     Add(Symbol, Option<String>, Option<String>, Option<String>),
     Remove(Symbol),
+    Update(Symbol, Option<String>, Option<String>, Option<String>, Option<String>, bool, Option<String>, Option<String>),
+    Sell(Symbol, String, Option<String>, Option<String>, Option<String>),
+    Deposit(String, Option<String>, Option<String>),
+    Withdraw(String, Option<String>, Option<String>),
+    Dividend(Symbol, String, Option<String>, Option<String>),
+    Split(Symbol, String, Option<String>),
+
+    Migrate(Option<String>),
+    Check(Option<String>),
+    Restore(Option<String>),
+    Log(Option<String>),
+    Undo,
+    Redo,
 
     None,
 }
 
+#[derive(Debug, Default)]
+struct Options {
+    format: OutputFormat,
+    provider: Option<String>,
+    offline: bool,
+    refresh: bool,
+    report_currency: Option<String>,
+    fx_rates: Option<String>,
+    prices: Option<String>,
+    account: Option<String>,
+    /// The command as typed, recorded in the journal.
+    command_line: String,
+}
+
 fn main() {
     flexi_logger::Logger::with_env().start().unwrap();
     info!("folio::main started");
 
-    let cmd = handle_args();
+    let (cmd, options) = handle_args();
 
     if let Command::None = cmd {
         println!("Pick a [valid] command");
+    } else if let Command::Migrate(file_name) = cmd {
+        // the file is migrated before it is read, so it is never rewritten by a command.
+        migrate(file_name);
+    } else if let Command::Check(file_name) = cmd {
+        check(file_name);
+    } else if let Command::Restore(backup) = cmd {
+        restore(backup, &options);
+    } else if let Command::Log(limit) = cmd {
+        show_log(limit, &options);
+    } else if let Command::Undo = cmd {
+        undo(false, &options);
+    } else if let Command::Redo = cmd {
+        undo(true, &options);
     } else {
-        let default_currency = with_code(DEFAULT_CURRENCY).unwrap();
+        // the lock is held until the changed portfolio is written.
+        let _lock = if changes_portfolio(&cmd) {
+            match storage::lock(&model::default_file_name()) {
+                Ok(lock) => Some(lock),
+                Err(err) => {
+                    println!("{}", err);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
 
-        if let Some(portfolio) = get_portfolio() {
+        if let Some(stored) = get_portfolio() {
+            // with --account commands only see, and change, that account's holdings.
+            let portfolio = match &options.account {
+                Some(name) => match accounts::account_portfolio(&stored, name) {
+                    Ok(portfolio) => portfolio,
+                    Err(err) => {
+                        println!("{}", err);
+                        return;
+                    }
+                },
+                None => stored.clone(),
+            };
+            let currency = portfolio.currency();
+            let deposit = matches!(cmd, Command::Deposit(_, _, _));
             match cmd {
-                Command::Show | Command::Watch => {
-                    let provider = match IEXProvider::new() {
-                        Ok(provider) => provider,
-                        Err(RequestError::ConfigurationError(err)) => {
-                            println!("Error configuring provider: {}", err);
-                            return ();
-                        }
+                Command::Show(_) | Command::Watch(_) | Command::Gains | Command::Rebalance(_, _, _, _) | Command::Allocation(_) => {
+                    // quotes are never older than the watch's refresh delay.
+                    let delay = match &cmd {
+                        Command::Watch(Some(d)) => match parse_delay(d) {
+                            Ok(d) => Some(d),
+                            Err(err) => invalid_argument(err),
+                        },
+                        _ => None,
+                    };
+                    // the watch table is redrawn in place, other formats can't be.
+                    if matches!(cmd, Command::Watch(_)) && options.format != OutputFormat::Table {
+                        invalid_argument("watch redraws a table, --format can't be used");
+                    }
+                    let provider = match get_provider(&options, &portfolio, command_name(&cmd), delay) {
+                        Some(provider) => provider,
+                        None => return,
+                    };
+                    let converter = match get_converter(&options, &portfolio, &provider) {
+                        Ok(converter) => converter,
                         Err(err) => {
-                            println!("Unknown error from provider: {:?}", err);
-                            return ();
+                            println!("Error configuring currency conversion: {}", err);
+                            return;
                         }
                     };
 
-                    match cmd {
-                        Command::Show => show_portfolio(portfolio, provider),
-                        Command::Watch => watch_portfolio(portfolio),
-                        _ => (),
+                    // each report is shown in full, with an error for any symbol without a quote.
+                    let failures = match cmd {
+                        Command::Show(by_account) => {
+                            let portfolio = Portfolio { items: ledger::current_items(&portfolio), ..portfolio };
+                            if options.offline {
+                                show_portfolio_with_age(portfolio, provider, options.format, converter.as_ref(), by_account)
+                            } else {
+                                show_portfolio(portfolio, provider, options.format, converter.as_ref(), by_account)
+                            }
+                        },
+                        Command::Gains => show_gains(portfolio, provider, options.format, converter.as_ref()),
+                        Command::Rebalance(t, c, fractional, no_sells) => {
+                            let rebalance_options = RebalanceOptions {
+                                tolerance: match t.map(|t| parse_percent(&t)) {
+                                    Some(Ok(t)) => Some(t * 100.0),
+                                    Some(Err(err)) => invalid_argument(err),
+                                    None => None,
+                                },
+                                fractional,
+                                no_sells,
+                                cash: match c.map(|c| parse_price(&c, currency)) {
+                                    Some(Ok(c)) => money_f64(&c),
+                                    Some(Err(err)) => invalid_argument(err),
+                                    None => 0.0,
+                                },
+                            };
+                            show_rebalance(portfolio, provider, &rebalance_options, options.format, converter.as_ref())
+                        },
+                        Command::Allocation(b) => {
+                            let b = match b.map(|b| b.parse::<Grouping>()) {
+                                Some(Ok(b)) => b,
+                                Some(Err(err)) => invalid_argument(err),
+                                None => Grouping::AssetClass,
+                            };
+                            show_allocation(portfolio, provider, b, options.format, converter.as_ref())
+                        },
+                        Command::Watch(_) => {
+                            watch_portfolio(
+                                Portfolio { items: ledger::current_items(&portfolio), ..portfolio },
+                                provider,
+                                Duration::from_secs(delay.unwrap_or(DEFAULT_REFRESH_DELAY)),
+                                converter);
+                            0
+                        },
+                        _ => 0,
+                    };
+                    if failures > 0 {
+                        eprintln!("Could not retrieve quotes for {} symbol(s)", failures);
+                        process::exit(EXIT_QUOTE_ERRORS);
                     }
                 },
-                Command::Holdings | Command::Add(_, _, _, _) | Command::Remove(_) => {
+                Command::Holdings | Command::Add(_, _, _, _) | Command::Remove(_) | Command::Update(_, _, _, _, _, _, _, _) | Command::Deposit(_, _, _) | Command::Withdraw(_, _, _) | Command::Dividend(_, _, _, _) | Command::Split(_, _, _) => {
                     match cmd {
                         Command::Holdings =>
-                            show_holdings(portfolio),
+                            show_holdings(portfolio, options.format),
                         Command::Add(s, p, q, d) => {
-                            let p = match p {
-                                Some(p) => {
-                                    let ps: Vec<&str> = p.split(".").collect();
-                                    if ps.len() == 1 {
-                                        Money::of_major_minor(
-                                            portfolio.default_currency.unwrap_or(default_currency),
-                                            ps.get(0).unwrap().parse::<i32>().unwrap(),
-                                            0)
-                                    } else if ps.len() == 2 {
-                                        Money::of_major_minor(
-                                            portfolio.default_currency.unwrap_or(default_currency),
-                                            ps.get(0).unwrap().parse::<i32>().unwrap(),
-                                            ps.get(1).unwrap().parse::<i32>().unwrap())
-                                    } else {
-                                        warn!("Could not parse price {}, using zero", p);
-                                        Money::zero(default_currency)
-                                    }
-                                },
-                                None => Money::zero(default_currency)
+                            let p = match p.map(|p| parse_price(&p, currency)) {
+                                Some(Ok(p)) => p,
+                                Some(Err(err)) => invalid_argument(err),
+                                None => Money::zero(currency)
                             };
-                            let q = match q {
-                                Some(q) => match q.parse::<u32>() {
-                                    Ok(n) => n,
-                                    Err(_) => {
-                                        warn!("Could not parse quantity {}, using zero", q);
-                                        0
-                                    },
-                                }
+                            let q = match q.map(|q| parse_quantity(&q)) {
+                                Some(Ok(q)) => q,
+                                Some(Err(err)) => invalid_argument(err),
                                 None => 0,
                             };
-                            let d = match d {
-                                Some(d) => match Date::parse_from_str(&d, DATE_FMT) {
-                                    Ok(d) => Some(d),
-                                    Err(_) => {
-                                        warn!("Could not parse date {}, using today", d);
-                                        Some(Local::today().naive_local())
-                                    },
-                                }
+                            let d = match d.map(|d| parse_date(&d)) {
+                                Some(Ok(d)) => Some(d),
+                                Some(Err(err)) => invalid_argument(err),
                                 None => None,
                             };
-                            let new_item = Item::Price(
-                                s,
-                                Holding {
-                                    quantity: q,
-                                    purchase_price: p,
-                                    purchase_date: d,
-                                }
+                            let holding = Holding {
+                                quantity: q,
+                                purchase_price: p,
+                                purchase_date: d,
+                                classification: Classification {
+                                    account: options.account.clone(),
+                                    ..Default::default()
+                                },
+                            };
+                            let new_portfolio = add_holding(&portfolio, &s, holding);
+                            save_portfolio(&stored, &options, &new_portfolio);
+                        },
+                        Command::Update(s, q, a, p, d, w, t, e) => {
+                            if w && options.account.is_some() {
+                                invalid_argument("Watched symbols are not held in an account, --watch-only can't be used with --account");
+                            }
+                            // nothing is changed unless every value given can be parsed.
+                            let parsed = (
+                                q.map(|q| parse_quantity(&q)).transpose(),
+                                a.map(|a| parse_quantity(&a)).transpose(),
+                                p.map(|p| parse_price(&p, currency)).transpose(),
+                                d.map(|d| parse_date(&d)).transpose(),
+                                e.map(|e| parse_entry(&e)).transpose(),
                             );
-                            let new_portfolio = Portfolio {
-                                default_currency: portfolio.default_currency,
-                                items: portfolio.items.into_iter().chain(vec![new_item]).collect()
+                            let (q, a, p, d, e) = match parsed {
+                                (Ok(q), Ok(a), Ok(p), Ok(d), Ok(e)) => (q, a, p, d, e),
+                                (Err(err), _, _, _, _)
+                                | (_, Err(err), _, _, _)
+                                | (_, _, Err(err), _, _)
+                                | (_, _, _, Err(err), _)
+                                | (_, _, _, _, Err(err)) => {
+                                    invalid_argument(err)
+                                }
                             };
-                            match model::write_file(None, &new_portfolio) {
-                                Err(err) => {
-                                    println!("Failed to save portfolio file, error: {:?}", err);
-                                    ()
-                                },
-                                Ok(_) => (),
+                            let update = HoldingUpdate {
+                                entry: e,
+                                quantity: q,
+                                add_quantity: a,
+                                purchase_price: p,
+                                purchase_date: d,
+                                watch_only: w,
+                                tags: t.map(|t| {
+                                    t.split(',')
+                                        .map(|tag| tag.trim().to_string())
+                                        .filter(|tag| !tag.is_empty())
+                                        .collect()
+                                }),
+                                account: options.account.clone(),
+                            };
+                            match update_holding(&portfolio, &s, &update, currency) {
+                                Ok(new_portfolio) => save_portfolio(&stored, &options, &new_portfolio),
+                                Err(err) => invalid_argument(err),
                             }
                         },
                         Command::Remove(symbol) => {
                             let new_portfolio = Portfolio {
-                                default_currency: portfolio.default_currency,
                                 items: portfolio.items.iter().filter(|item|
                                     match item {
                                         Item::Watch(s) | Item::Price(s, _) => *s != symbol,
                                     }
-                                ).cloned().collect()
+                                ).cloned().collect(),
+                                ..portfolio
                             };
-                            match model::write_file(None, &new_portfolio) {
-                                Err(err) => {
-                                    println!("Failed to save portfolio file, error: {:?}", err);
-                                    ()
-                                },
-                                Ok(_) => (),
+                            save_portfolio(&stored, &options, &new_portfolio);
+                        },
+                        Command::Deposit(a, c, d) | Command::Withdraw(a, c, d) => {
+                            let transaction = match cash_transaction(&a, c, d, currency, deposit) {
+                                Ok(transaction) => transaction,
+                                Err(err) => invalid_argument(err),
+                            };
+                            let mut new_portfolio = portfolio;
+                            accounts::add_transaction(&mut new_portfolio, options.account.as_deref(), transaction);
+                            save_portfolio(&stored, &options, &new_portfolio);
+                        },
+                        Command::Dividend(s, a, d, r) => {
+                            let a = match parse_amount(&a, currency) {
+                                Ok(a) => a,
+                                Err(err) => invalid_argument(err),
+                            };
+                            let d = match d.map(|d| parse_date(&d)) {
+                                Some(Ok(d)) => d,
+                                Some(Err(err)) => invalid_argument(err),
+                                None => Local::now().date_naive(),
+                            };
+                            let r = match r.map(|r| parse_quantity(&r)) {
+                                Some(Ok(r)) => Some(r),
+                                Some(Err(err)) => invalid_argument(err),
+                                None => None,
+                            };
+                            // a reinvested dividend buys whole shares costing exactly the amount.
+                            let transactions = match r {
+                                Some(q) if q > 0 => ledger::reinvested_trades(a, q)
+                                    .into_iter()
+                                    .map(|trade| Transaction::Reinvest(d, s.to_uppercase(), trade))
+                                    .collect(),
+                                _ => vec![Transaction::Dividend(d, s.to_uppercase(), a)],
+                            };
+                            let mut new_portfolio = portfolio;
+                            for transaction in transactions {
+                                accounts::add_transaction(&mut new_portfolio, options.account.as_deref(), transaction);
                             }
+                            save_portfolio(&stored, &options, &new_portfolio);
+                        },
+                        Command::Split(s, r, d) => {
+                            let r = match Ratio::parse(&r) {
+                                Some(r) => r,
+                                None => invalid_argument(format!("Invalid split ratio '{}', expected TO:FROM, for example 4:1", r)),
+                            };
+                            let d = match d.map(|d| parse_date(&d)) {
+                                Some(Ok(d)) => d,
+                                Some(Err(err)) => invalid_argument(err),
+                                None => Local::now().date_naive(),
+                            };
+                            let s = s.to_uppercase();
+                            if actions::has_split(&portfolio, &s, d) {
+                                invalid_argument(format!("A split of {} on {} is already recorded", s, d.format(DATE_FMT)));
+                            }
+                            // actions apply to the whole portfolio, whichever account is shown.
+                            let mut new_portfolio = portfolio;
+                            new_portfolio.actions.push(CorporateAction::Split(d, s, r));
+                            save_portfolio(&stored, &options, &new_portfolio);
                         },
                         _ => (),
                     }
                 },
-                Command::None => (),
+                Command::History(f, t, i) => {
+                    let history = match get_price_history(&options) {
+                        Some(history) => history,
+                        None => return,
+                    };
+                    let (f, t) = match date_range(&portfolio, f, t) {
+                        Ok(range) => range,
+                        Err(err) => invalid_argument(err),
+                    };
+                    let i = match i.map(|i| i.parse::<Interval>()) {
+                        Some(Ok(i)) => i,
+                        Some(Err(err)) => invalid_argument(err),
+                        None => Interval::Month,
+                    };
+                    show_history(&portfolio, &history, f, t, i, options.format);
+                },
+                Command::Performance(f, t, b) => {
+                    let history = match get_price_history(&options) {
+                        Some(history) => history,
+                        None => return,
+                    };
+                    let (f, t) = match date_range(&portfolio, f, t) {
+                        Ok(range) => range,
+                        Err(err) => invalid_argument(err),
+                    };
+                    let benchmark = match b.or(portfolio.benchmark.clone()) {
+                        Some(symbol) => {
+                            // a range ending today compares with the benchmark's current quote.
+                            let provider = if t == Local::now().date_naive() {
+                                get_provider(&options, &portfolio, "performance", None)
+                            } else {
+                                None
+                            };
+                            Some(benchmark_return(&symbol.to_uppercase(), &history, provider.as_ref(), f, t))
+                        }
+                        None => None,
+                    };
+                    show_performance(&portfolio, &history, f, t, benchmark.as_ref(), options.format);
+                },
+                Command::Risk(f, t, b, r, c) => {
+                    let history = match get_price_history(&options) {
+                        Some(history) => history,
+                        None => return,
+                    };
+                    let t = match t.map(|t| parse_date(&t)) {
+                        Some(Ok(t)) => t,
+                        Some(Err(err)) => invalid_argument(err),
+                        None => Local::now().date_naive(),
+                    };
+                    let f = match f.map(|f| parse_date(&f)) {
+                        Some(Ok(f)) => f,
+                        Some(Err(err)) => invalid_argument(err),
+                        None => risk::default_from(t),
+                    };
+                    let risk_options = RiskOptions {
+                        risk_free_rate: match r.map(|r| parse_percent(&r)) {
+                            Some(Ok(r)) => r,
+                            Some(Err(err)) => invalid_argument(err),
+                            None => 0.0,
+                        },
+                        confidence: match c.map(|c| parse_percent(&c)) {
+                            Some(Ok(c)) => c,
+                            Some(Err(err)) => invalid_argument(err),
+                            None => risk::DEFAULT_CONFIDENCE,
+                        },
+                        benchmark: b.or(portfolio.benchmark.clone()).map(|s| s.to_uppercase()),
+                    };
+                    show_risk(&portfolio, &history, f, t, &risk_options, options.format);
+                },
+                Command::Income(y) => {
+                    let y = match y {
+                        Some(y) => match y.parse::<i32>() {
+                            Ok(y) => y,
+                            Err(_) => invalid_argument(format!("Could not parse year {}, expected a year such as 2020", y)),
+                        },
+                        None => Local::now().year(),
+                    };
+                    // dividend data is optional, without it recorded dividends are used.
+                    let history = match &options.prices {
+                        Some(_) => match get_price_history(&options) {
+                            Some(history) => Some(history),
+                            None => return,
+                        },
+                        None if Path::new(&history::default_price_file_name()).exists() => get_price_history(&options),
+                        None => None,
+                    };
+                    show_income(&portfolio, history.as_ref(), y, options.format);
+                },
+                Command::Sell(symbol, q, m, p, d) => {
+                    // a sale is only recorded, and the file rewritten, for a valid quantity.
+                    let q = match parse_quantity(&q) {
+                        Ok(q) if q > 0 => q,
+                        Ok(_) => invalid_argument("The quantity to sell must be more than zero"),
+                        Err(err) => invalid_argument(err),
+                    };
+                    let m = match m {
+                        Some(m) => match m.parse::<Method>() {
+                            Ok(m) => Some(m),
+                            Err(err) => invalid_argument(err),
+                        },
+                        None => None,
+                    };
+                    let p = match p {
+                        Some(p) => match parse_price(&p, held_currency(&portfolio, &symbol).unwrap_or(currency)) {
+                            Ok(p) => p,
+                            Err(err) => invalid_argument(err),
+                        },
+                        None => {
+                            let provider = match get_provider(&options, &portfolio, "sell", None) {
+                                Some(provider) => provider,
+                                None => return,
+                            };
+                            match provider.real_time(symbol.to_string()) {
+                                Ok(quote) => quote.data.latest.price,
+                                Err(err) => {
+                                    println!("Error retrieving quote for {}: {:?}", symbol, err);
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    let d = match d.map(|d| parse_date(&d)) {
+                        Some(Ok(d)) => d,
+                        Some(Err(err)) => invalid_argument(err),
+                        None => Local::now().date_naive(),
+                    };
+                    match lots::sell(portfolio, &symbol, q, p, d, m) {
+                        Ok((new_portfolio, realized)) => {
+                            lots::show_realized(&realized, options.format);
+                            save_portfolio(&stored, &options, &new_portfolio);
+                        },
+                        Err(err) => invalid_argument(match err {
+                            SaleError::NoLots(s) => format!("No holdings of {} to sell", s),
+                            SaleError::UnknownLot(id) => format!("No lot with identifier {}", id),
+                            SaleError::InsufficientQuantity(s, held, requested) =>
+                                format!("Cannot sell {} {}, only {} held", requested, s, held),
+                            SaleError::SeveralAccounts(s) =>
+                                format!("{} is held in more than one account, choose one with --account", s),
+                            SaleError::AverageCost(s) => format!(
+                                "{} is held through the transaction log and is sold at its average cost, --method can't be used",
+                                s
+                            ),
+                            SaleError::CurrencyMismatch(s, sale, purchase) =>
+                                format!("Cannot sell {} at a price in {}, it was purchased in {}", s, sale, purchase),
+                        }),
+                    }
+                },
+                // these don't read the portfolio, they are run above.
+                Command::Migrate(_) | Command::Check(_) | Command::Restore(_) | Command::Log(_) | Command::Undo | Command::Redo
+                | Command::None => unreachable!(),
             }
         }
     }
@@ -156,15 +532,124 @@ fn main() {
 
 extern crate clap;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-fn handle_args() -> Command {
+fn handle_args() -> (Command, Options) {
     let matches = App::new("folio")
         .about("Portfolio Manager")
         .version("v1.0-pre")
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&FORMAT_NAMES)
+                .global(true)
+                .help("The output format for reports"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .global(true)
+                .conflicts_with("refresh")
+                .help("Only use cached quotes, showing the age of each"),
+        )
+        .arg(
+            Arg::with_name("refresh")
+                .long("refresh")
+                .global(true)
+                .help("Ignore cached quotes, fetching new ones from the provider"),
+        )
+        .arg(
+            Arg::with_name("report_currency")
+                .long("report-currency")
+                .takes_value(true)
+                .global(true)
+                .help("Convert all values into this currency (e.g. USD) for reports"),
+        )
+        .arg(
+            Arg::with_name("fx_rates")
+                .long("fx-rates")
+                .takes_value(true)
+                .global(true)
+                .help("A file of exchange rates, defaults to ~/.config/folio/rates.toml"),
+        )
+        .arg(
+            Arg::with_name("prices")
+                .long("prices")
+                .takes_value(true)
+                .global(true)
+                .help("A file of historical daily prices, defaults to ~/.config/folio/prices.csv"),
+        )
+        .arg(
+            Arg::with_name("account")
+                .short("a")
+                .long("account")
+                .takes_value(true)
+                .global(true)
+                .help("Only use, or change, the holdings of this account"),
+        )
+        .arg(
+            Arg::with_name("provider")
+                .long("provider")
+                .takes_value(true)
+                .global(true)
+                .help("The quote provider, overriding any configured provider; iex, or file:PATH to read quotes from a local file"),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check a portfolio file, listing every problem found")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The portfolio file, defaults to ~/portfolio.toml")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Upgrade a portfolio file to the current layout, keeping a backup")
+                .arg(
+                    Arg::with_name("file")
+                        .help("The portfolio file, defaults to ~/portfolio.toml")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("List the backups of the portfolio file, or restore one")
+                .arg(
+                    Arg::with_name("backup")
+                        .help("The backup to restore, by its number in the list or its file name")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("List the changes made to the portfolio file, newest last")
+                .arg(
+                    Arg::with_name("limit")
+                        .short("n")
+                        .long("limit")
+                        .takes_value(true)
+                        .help("Only list this many of the latest changes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Reverse the latest change to the portfolio file"),
+        )
+        .subcommand(
+            SubCommand::with_name("redo")
+                .about("Make the latest undone change to the portfolio file again"),
+        )
         .subcommand(
             SubCommand::with_name("show")
                 .about("Show quotes for all portfolio symbols")
+                .arg(
+                    Arg::with_name("by_account")
+                        .long("by-account")
+                        .help("Group holdings by account, with a subtotal for each"),
+                )
         )
         .subcommand(
             SubCommand::with_name("watch")
@@ -174,7 +659,145 @@ fn handle_args() -> Command {
                         .short("d")
                         .long("refresh-delay")
                         .takes_value(true)
-                        .help("Delay between refreshes"),
+                        .help("Delay between refreshes, in seconds"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("gains")
+                .about("Show realized and unrealized gains for all holdings")
+        )
+        .subcommand(
+            SubCommand::with_name("allocation")
+                .about("Show the value and weight of holdings grouped by a classification")
+                .arg(
+                    Arg::with_name("by")
+                        .long("by")
+                        .takes_value(true)
+                        .possible_values(&GROUPING_NAMES)
+                        .help("The classification to group by, defaults to asset-class"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("rebalance")
+                .about("Suggest trades to bring holdings back to their allocation targets")
+                .arg(
+                    Arg::with_name("tolerance")
+                        .short("t")
+                        .long("tolerance")
+                        .takes_value(true)
+                        .help("The drift allowed from each target, in percentage points, defaults to 5"),
+                )
+                .arg(
+                    Arg::with_name("cash")
+                        .short("c")
+                        .long("cash")
+                        .takes_value(true)
+                        .help("New cash to invest"),
+                )
+                .arg(
+                    Arg::with_name("fractional")
+                        .long("fractional")
+                        .help("Suggest fractional, rather than whole, share quantities"),
+                )
+                .arg(
+                    Arg::with_name("no_sells")
+                        .long("no-sells")
+                        .help("Only suggest buys, using the new cash"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show the value, cost basis and gain of the portfolio over time")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("The first date (YYYY-MM-DD), defaults to the first purchase"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("The last date (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .short("i")
+                        .long("interval")
+                        .takes_value(true)
+                        .possible_values(&INTERVAL_NAMES)
+                        .help("The time between values, defaults to month"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("performance")
+                .about("Show time-weighted, money-weighted and annualized returns")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("The first date (YYYY-MM-DD), defaults to the first purchase"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("The last date (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("benchmark")
+                        .short("b")
+                        .long("benchmark")
+                        .takes_value(true)
+                        .help("Compare with this symbol, overriding the portfolio's benchmark"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("risk")
+                .about("Show volatility, beta, Sharpe and Sortino ratios, drawdown and value-at-risk")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("The first date of daily prices (YYYY-MM-DD), defaults to a year before --to"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("The last date of daily prices (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("benchmark")
+                        .short("b")
+                        .long("benchmark")
+                        .takes_value(true)
+                        .help("Measure beta against this symbol, overriding the portfolio's benchmark"),
+                )
+                .arg(
+                    Arg::with_name("risk_free_rate")
+                        .short("r")
+                        .long("risk-free-rate")
+                        .takes_value(true)
+                        .help("The annual risk-free rate as a percentage (e.g. 2.5), defaults to 0"),
+                )
+                .arg(
+                    Arg::with_name("confidence")
+                        .short("c")
+                        .long("confidence")
+                        .takes_value(true)
+                        .help("The value-at-risk confidence level as a percentage, defaults to 95"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("income")
+                .about("Show dividend income, yields and projected income for a year")
+                .arg(
+                    Arg::with_name("year")
+                        .short("y")
+                        .long("year")
+                        .takes_value(true)
+                        .help("The year (YYYY), defaults to this year"),
                 )
         )
         .subcommand(
@@ -212,6 +835,203 @@ fn handle_args() -> Command {
                         .index(1),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Change a holding, or watched symbol, in place")
+                .arg(
+                    Arg::with_name("quantity")
+                        .short("q")
+                        .long("quantity")
+                        .takes_value(true)
+                        .conflicts_with("add_quantity")
+                        .help("The quantity of this security you hold"),
+                )
+                .arg(
+                    Arg::with_name("add_quantity")
+                        .long("add-quantity")
+                        .takes_value(true)
+                        .conflicts_with("quantity")
+                        .help("A quantity bought in addition to that held, at --purchase-price on --purchase-date if given"),
+                )
+                .arg(
+                    Arg::with_name("price")
+                        .short("p")
+                        .long("purchase-price")
+                        .takes_value(true)
+                        .help("The purchase price of the security"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("purchase-date")
+                        .takes_value(true)
+                        .help("The purchase date of the security (YYYY-MM-DD)"),
+                )
+                .arg(
+                    Arg::with_name("watch_only")
+                        .long("watch-only")
+                        .conflicts_with_all(&["quantity", "add_quantity", "price", "date", "tags"])
+                        .help("Only watch the symbol, removing the holding"),
+                )
+                .arg(
+                    Arg::with_name("tags")
+                        .long("tags")
+                        .takes_value(true)
+                        .help("Comma-separated tags, replacing any the holding has"),
+                )
+                .arg(
+                    Arg::with_name("entry")
+                        .long("entry")
+                        .takes_value(true)
+                        .help("Which holding of the symbol to change, counting from 1, when it is held more than once"),
+                )
+                .arg(
+                    Arg::with_name("symbol")
+                        .help("The security symbol")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sell")
+                .about("Sell a quantity of a symbol, consuming purchase lots")
+                .arg(
+                    Arg::with_name("quantity")
+                        .short("q")
+                        .long("quantity")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The quantity of this security to sell"),
+                )
+                .arg(
+                    Arg::with_name("method")
+                        .short("m")
+                        .long("method")
+                        .takes_value(true)
+                        .help("How lots are chosen: fifo (default), lifo, hifo, or lot=ID"),
+                )
+                .arg(
+                    Arg::with_name("price")
+                        .short("p")
+                        .long("sale-price")
+                        .takes_value(true)
+                        .help("The sale price, in the currency the symbol was purchased in, defaults to the current quote"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("sale-date")
+                        .takes_value(true)
+                        .help("The sale date of the security (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("symbol")
+                        .help("The security symbol")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dividend")
+                .about("Record a dividend paid in cash, or reinvested")
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("date")
+                        .takes_value(true)
+                        .help("The payment date (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("reinvest")
+                        .short("r")
+                        .long("reinvest-quantity")
+                        .takes_value(true)
+                        .help("The dividend was reinvested in this quantity of whole shares"),
+                )
+                .arg(
+                    Arg::with_name("symbol")
+                        .help("The security symbol")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .help("The total amount paid")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("split")
+                .about("Record a stock split, applied to holdings bought before it")
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("date")
+                        .takes_value(true)
+                        .help("The date the split took effect (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("symbol")
+                        .help("The security symbol")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("ratio")
+                        .help("The new shares for old shares, 4:1 or, for a reverse split, 1:10")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("deposit")
+                .about("Record a deposit of cash")
+                .arg(
+                    Arg::with_name("currency")
+                        .short("c")
+                        .long("currency")
+                        .takes_value(true)
+                        .help("The currency of the amount, defaults to the portfolio's currency"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("date")
+                        .takes_value(true)
+                        .help("The date of the deposit (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .help("The amount deposited")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("withdraw")
+                .about("Record a withdrawal of cash")
+                .arg(
+                    Arg::with_name("currency")
+                        .short("c")
+                        .long("currency")
+                        .takes_value(true)
+                        .help("The currency of the amount, defaults to the portfolio's currency"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .short("d")
+                        .long("date")
+                        .takes_value(true)
+                        .help("The date of the withdrawal (YYYY-MM-DD), defaults to today"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .help("The amount withdrawn")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("delete")
                 .about("Delete a symbol from the portfolio")
@@ -224,33 +1044,194 @@ fn handle_args() -> Command {
         )
         .get_matches();
 
-    match matches.subcommand() {
-        ("show", Some(_)) => Command::Show,
-        ("watch", Some(_)) => Command::Watch,
+    let options = Options {
+        format: match global_value(&matches, "format") {
+            Some(s) => match s.parse::<OutputFormat>() {
+                Ok(format) => format,
+                Err(err) => invalid_argument(err),
+            },
+            None => OutputFormat::Table,
+        },
+        provider: global_value(&matches, "provider").map(|s| s.to_string()),
+        offline: global_flag(&matches, "offline"),
+        refresh: global_flag(&matches, "refresh"),
+        report_currency: global_value(&matches, "report_currency").map(|s| s.to_uppercase()),
+        fx_rates: global_value(&matches, "fx_rates").map(|s| s.to_string()),
+        prices: global_value(&matches, "prices").map(|s| s.to_string()),
+        account: global_value(&matches, "account").map(|s| s.to_string()),
+        command_line: env::args().skip(1).collect::<Vec<String>>().join(" "),
+    };
 
+    let command = match matches.subcommand() {
+        ("show", Some(sub_matches)) => Command::Show(sub_matches.is_present("by_account")),
+        ("watch", Some(matches)) => Command::Watch(
+            matches.value_of("delay").map(|s| s.to_string()),
+        ),
+
+        ("gains", Some(_)) => Command::Gains,
+        ("allocation", Some(matches)) => Command::Allocation(
+            matches.value_of("by").map(|s| s.to_string()),
+        ),
+        ("rebalance", Some(matches)) => Command::Rebalance(
+            matches.value_of("tolerance").map(|s| s.to_string()),
+            matches.value_of("cash").map(|s| s.to_string()),
+            matches.is_present("fractional"),
+            matches.is_present("no_sells"),
+        ),
+        ("history", Some(matches)) => Command::History(
+            matches.value_of("from").map(|s| s.to_string()),
+            matches.value_of("to").map(|s| s.to_string()),
+            matches.value_of("interval").map(|s| s.to_string()),
+        ),
+        ("performance", Some(matches)) => Command::Performance(
+            matches.value_of("from").map(|s| s.to_string()),
+            matches.value_of("to").map(|s| s.to_string()),
+            matches.value_of("benchmark").map(|s| s.to_string()),
+        ),
+        ("risk", Some(matches)) => Command::Risk(
+            matches.value_of("from").map(|s| s.to_string()),
+            matches.value_of("to").map(|s| s.to_string()),
+            matches.value_of("benchmark").map(|s| s.to_string()),
+            matches.value_of("risk_free_rate").map(|s| s.to_string()),
+            matches.value_of("confidence").map(|s| s.to_string()),
+        ),
         ("holdings", Some(_)) => Command::Holdings,
         ("add", Some(matches)) => Command::Add(
             matches.value_of("symbol").unwrap().to_string(),
-            match matches.value_of("price") {
-                Some(s) => Some(s.to_string()),
-                None => None,
-            },
-            match matches.value_of("quantity") {
-                Some(s) => Some(s.to_string()),
-                None => None,
-            },
-            match matches.value_of("date") {
-                Some(s) => Some(s.to_string()),
-                None => None,
-            },
+            matches.value_of("price").map(|s| s.to_string()),
+            matches.value_of("quantity").map(|s| s.to_string()),
+            matches.value_of("date").map(|s| s.to_string()),
+        ),
+        ("update", Some(matches)) => Command::Update(
+            matches.value_of("symbol").unwrap().to_string(),
+            matches.value_of("quantity").map(|s| s.to_string()),
+            matches.value_of("add_quantity").map(|s| s.to_string()),
+            matches.value_of("price").map(|s| s.to_string()),
+            matches.value_of("date").map(|s| s.to_string()),
+            matches.is_present("watch_only"),
+            matches.value_of("tags").map(|s| s.to_string()),
+            matches.value_of("entry").map(|s| s.to_string()),
         ),
         ("delete", Some(matches)) => Command::Remove(
             matches.value_of("symbol").unwrap().to_string()
         ),
+        ("sell", Some(matches)) => Command::Sell(
+            matches.value_of("symbol").unwrap().to_uppercase(),
+            matches.value_of("quantity").unwrap().to_string(),
+            matches.value_of("method").map(|s| s.to_string()),
+            matches.value_of("price").map(|s| s.to_string()),
+            matches.value_of("date").map(|s| s.to_string()),
+        ),
+        ("income", Some(matches)) => Command::Income(
+            matches.value_of("year").map(|s| s.to_string()),
+        ),
+        ("dividend", Some(matches)) => Command::Dividend(
+            matches.value_of("symbol").unwrap().to_string(),
+            matches.value_of("amount").unwrap().to_string(),
+            matches.value_of("date").map(|s| s.to_string()),
+            matches.value_of("reinvest").map(|s| s.to_string()),
+        ),
+        ("check", Some(matches)) => Command::Check(
+            matches.value_of("file").map(|s| shellexpand::tilde(s).to_string()),
+        ),
+        ("restore", Some(matches)) => Command::Restore(
+            matches.value_of("backup").map(|s| s.to_string()),
+        ),
+        ("log", Some(matches)) => Command::Log(
+            matches.value_of("limit").map(|s| s.to_string()),
+        ),
+        ("undo", Some(_)) => Command::Undo,
+        ("redo", Some(_)) => Command::Redo,
+        ("migrate", Some(matches)) => Command::Migrate(
+            matches.value_of("file").map(|s| shellexpand::tilde(s).to_string()),
+        ),
+        ("split", Some(matches)) => Command::Split(
+            matches.value_of("symbol").unwrap().to_string(),
+            matches.value_of("ratio").unwrap().to_string(),
+            matches.value_of("date").map(|s| s.to_string()),
+        ),
+        ("deposit", Some(matches)) => Command::Deposit(
+            matches.value_of("amount").unwrap().to_string(),
+            matches.value_of("currency").map(|s| s.to_string()),
+            matches.value_of("date").map(|s| s.to_string()),
+        ),
+        ("withdraw", Some(matches)) => Command::Withdraw(
+            matches.value_of("amount").unwrap().to_string(),
+            matches.value_of("currency").map(|s| s.to_string()),
+            matches.value_of("date").map(|s| s.to_string()),
+        ),
 
         _ => {
             Command::None
         }
+    };
+    (command, options)
+}
+
+fn command_name(cmd: &Command) -> &'static str {
+    match cmd {
+        Command::Show(_) => "show",
+        Command::Watch(_) => "watch",
+        Command::Gains => "gains",
+        Command::Rebalance(_, _, _, _) => "rebalance",
+        Command::Allocation(_) => "allocation",
+        Command::History(_, _, _) => "history",
+        Command::Performance(_, _, _) => "performance",
+        Command::Risk(_, _, _, _, _) => "risk",
+        Command::Holdings => "holdings",
+        Command::Add(_, _, _, _) => "add",
+        Command::Remove(_) => "delete",
+        Command::Update(_, _, _, _, _, _, _, _) => "update",
+        Command::Sell(_, _, _, _, _) => "sell",
+        Command::Income(_) => "income",
+        Command::Deposit(_, _, _) => "deposit",
+        Command::Dividend(_, _, _, _) => "dividend",
+        Command::Withdraw(_, _, _) => "withdraw",
+        Command::Split(_, _, _) => "split",
+        Command::Migrate(_) => "migrate",
+        Command::Check(_) => "check",
+        Command::Restore(_) => "restore",
+        Command::Log(_) => "log",
+        Command::Undo => "undo",
+        Command::Redo => "redo",
+        Command::None => "",
+    }
+}
+
+/// Print why an argument is invalid, and exit with `EXIT_INVALID_ARGUMENT`; nothing has
+/// been changed.
+fn invalid_argument<T: fmt::Display>(message: T) -> ! {
+    println!("{}", message);
+    process::exit(EXIT_INVALID_ARGUMENT)
+}
+
+/// Commands that change the portfolio file, and so hold its lock.
+fn changes_portfolio(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Add(_, _, _, _)
+            | Command::Remove(_)
+            | Command::Update(_, _, _, _, _, _, _, _)
+            | Command::Sell(_, _, _, _, _)
+            | Command::Deposit(_, _, _)
+            | Command::Withdraw(_, _, _)
+            | Command::Dividend(_, _, _, _)
+            | Command::Split(_, _, _)
+    )
+}
+
+fn global_flag(matches: &ArgMatches, name: &str) -> bool {
+    match matches.subcommand() {
+        (_, Some(sub_matches)) => sub_matches.is_present(name) || matches.is_present(name),
+        _ => matches.is_present(name),
+    }
+}
+
+/// Global arguments may appear before, or after, the subcommand name.
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    match matches.subcommand() {
+        (_, Some(sub_matches)) if sub_matches.is_present(name) => sub_matches.value_of(name),
+        _ => matches.value_of(name),
     }
 }
 
@@ -266,7 +1247,7 @@ fn get_portfolio() -> Option<Portfolio> {
                 model::default_file_name()
             );
             let example = Portfolio {
-                default_currency: with_code("USD"),
+                default_currency: with_code(DEFAULT_CURRENCY),
                 items: vec![
                     Item::Watch("AAPL".to_string()),
                     Item::Watch("MSFT".to_string()),
@@ -276,9 +1257,11 @@ fn get_portfolio() -> Option<Portfolio> {
                             with_code(DEFAULT_CURRENCY).unwrap(),
                             1800,
                             50),
-                        purchase_date: Some(Local::today().naive_local())
+                        purchase_date: Some(Local::now().date_naive()),
+                        classification: Classification::default(),
                     }),
                 ],
+                ..Default::default()
             };
             match model::write_file(None, &example) {
                 Err(err) => {
@@ -288,9 +1271,497 @@ fn get_portfolio() -> Option<Portfolio> {
                 Ok(_) => Some(example),
             }
         }
+        Err(ModelError::Invalid(problems)) => {
+            println!("The portfolio file has problems, run `folio check` for details:");
+            for problem in problems {
+                println!("    {}", problem);
+            }
+            None
+        }
+        Err(err) => {
+            println!(":( {}", err);
+            None
+        },
+    }
+}
+
+fn migrate(file_name: Option<String>) {
+    let display_name = file_name.clone().unwrap_or(model::default_file_name());
+    let _lock = match storage::lock(&display_name) {
+        Ok(lock) => lock,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    match model::migrate_file(file_name) {
+        Ok((version, None)) =>
+            println!("{} is already version {}, nothing to migrate", display_name, version),
+        Ok((version, Some(backup))) => println!(
+            "Migrated {} from version {} to {}, the original is saved as {}",
+            display_name, version, model::CURRENT_VERSION, backup
+        ),
+        Err(ModelError::PathError(name)) => println!("No portfolio file {}", name),
+        Err(err) => println!("Failed to migrate {}, error: {}", display_name, err),
+    }
+}
+
+/// List the backups of the portfolio file, newest first, or restore the named one once it
+/// has been checked.
+fn restore(backup: Option<String>, options: &Options) {
+    let file_name = model::default_file_name();
+    let backup = match backup {
+        Some(backup) => backup,
+        None => {
+            let backups = storage::backups(&file_name);
+            if backups.is_empty() {
+                println!("There are no backups of {}", file_name);
+            } else {
+                let mut report = Report::new(&["Backup", "Taken", "File"]);
+                for (index, backup) in backups.iter().enumerate() {
+                    report.add_row(vec![
+                        Value::Integer(index as i64 + 1),
+                        Value::Text(backup.taken.format("%Y-%m-%d %H:%M:%S").to_string()),
+                        Value::text(&backup.file_name),
+                    ]);
+                }
+                report.print(options.format);
+            }
+            return;
+        }
+    };
+    let _lock = match storage::lock(&file_name) {
+        Ok(lock) => lock,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let backup = match storage::find_backup(&file_name, &backup) {
+        Ok(backup) => backup,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    match model::read_file(Some(backup.file_name.to_string())) {
+        Ok(_) => (),
+        Err(err) => {
+            println!("Not restoring {}, it has problems:\n{}", backup.file_name, err);
+            return;
+        }
+    };
+    let before = fs::read_to_string(&file_name).ok();
+    match storage::restore(&file_name, &backup) {
+        Ok(()) => {
+            println!(
+                "Restored {} from the backup taken {}, the replaced file was itself backed up",
+                file_name,
+                backup.taken.format("%Y-%m-%d %H:%M:%S")
+            );
+            record_change(&file_name, before, options, EntryKind::Edit, None);
+        }
+        Err(err) => println!("Failed to restore {}, error: {}", file_name, err),
+    }
+}
+
+/// List the journal of changes to the portfolio file, oldest first.
+fn show_log(limit: Option<String>, options: &Options) {
+    let limit = match limit.map(|l| (l.trim().parse::<usize>(), l)) {
+        Some((Ok(limit), _)) => Some(limit),
+        Some((Err(_), l)) => invalid_argument(format!("Could not parse limit {}, expected a number of changes", l)),
+        None => None,
+    };
+    let file_name = model::default_file_name();
+    let entries = match journal::read_journal(&file_name) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    if entries.is_empty() {
+        println!("No changes to {} have been recorded", file_name);
+        return;
+    }
+    let skipped = match limit {
+        Some(limit) => entries.len().saturating_sub(limit),
+        None => 0,
+    };
+    let undone = journal::undone(&entries);
+    let mut report = Report::new(&["Change", "When", "Who", "Command", "Changes", "Undone"]);
+    for entry in entries.iter().skip(skipped) {
+        let mut changes: Vec<String> = entry.changes.iter().map(journal::describe).collect();
+        if let Some(target) = entry.target {
+            changes.insert(0, format!("change {}", target));
+        }
+        report.add_row(vec![
+            Value::Integer(entry.id as i64),
+            Value::Text(entry.time.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Value::text(&entry.user),
+            Value::text(&entry.command),
+            Value::Text(changes.join("; ")),
+            if undone.contains(&entry.id) { Value::text("yes") } else { Value::Empty },
+        ]);
+    }
+    report.print(options.format);
+}
+
+/// Reverse the latest change recorded in the journal, or with `redo` make the latest
+/// undone change again. The result is checked before the portfolio file is replaced.
+fn undo(redo: bool, options: &Options) {
+    let file_name = model::default_file_name();
+    let _lock = match storage::lock(&file_name) {
+        Ok(lock) => lock,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let entries = match journal::read_journal(&file_name) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let (entry, kind) = match (redo, journal::redoable(&entries), journal::undoable(&entries)) {
+        (true, Some(entry), _) => (entry, EntryKind::Redo),
+        (true, None, _) => {
+            println!("{}", JournalError::NothingToRedo);
+            return;
+        }
+        (false, _, Some(entry)) => (entry, EntryKind::Undo),
+        (false, _, None) => {
+            println!("{}", JournalError::NothingToUndo);
+            return;
+        }
+    };
+    let source = match fs::read_to_string(&file_name) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("Failed to read portfolio file, error: {}", err);
+            return;
+        }
+    };
+    let contents = match journal::apply(&source, entry, !redo) {
+        Ok(contents) => contents,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    match model::read_str(&contents) {
+        Ok(_) => (),
+        Err(err) => {
+            println!("Not changing {}, the result would have problems:\n{}", file_name, err);
+            return;
+        }
+    };
+    match storage::write_atomic(&file_name, contents.as_bytes()) {
+        Ok(()) => {
+            let changes: Vec<String> = entry.changes.iter().map(journal::describe).collect();
+            println!(
+                "{} change {}, `{}`: {}",
+                if redo { "Redid" } else { "Undid" },
+                entry.id,
+                entry.command,
+                changes.join("; ")
+            );
+            record_change(&file_name, Some(source), options, kind, Some(entry.id));
+        }
+        Err(err) => println!("Failed to save portfolio file, error: {}", err),
+    }
+}
+
+/// Record the change from `before` to the current portfolio file in its journal; the
+/// change has been made, so a failure to record it is only reported.
+fn record_change(file_name: &str, before: Option<String>, options: &Options, kind: EntryKind, target: Option<u32>) {
+    let recorded = fs::read_to_string(file_name)
+        .map_err(JournalError::FileError)
+        .and_then(|after| journal::changes(&before.unwrap_or_default(), &after))
+        .and_then(|changes| journal::record(file_name, &options.command_line, kind, target, changes));
+    match recorded {
+        Ok(_) => (),
+        Err(err) => println!("The change was made but not recorded in the journal, {}", err),
+    }
+}
+
+/// List every problem in the portfolio file, exiting with `EXIT_INVALID_FILE` if any.
+fn check(file_name: Option<String>) {
+    let display_name = file_name.clone().unwrap_or(model::default_file_name());
+    match model::read_file(file_name) {
+        Ok(portfolio) => println!(
+            "{}: no problems found, {} holdings, {} lots and {} transactions",
+            display_name,
+            portfolio.items.len(),
+            portfolio.lots.len(),
+            ledger::all_transactions(&portfolio).len()
+        ),
+        Err(ModelError::Invalid(problems)) => {
+            for problem in &problems {
+                println!("{}:{}", display_name, problem);
+            }
+            println!("{} problem(s) found", problems.len());
+            process::exit(EXIT_INVALID_FILE);
+        }
+        Err(ModelError::PathError(name)) => {
+            println!("No portfolio file {}", name);
+            process::exit(EXIT_INVALID_FILE);
+        }
         Err(err) => {
-            println!(":( {:?}", err);
+            println!("{}: {}", display_name, err);
+            process::exit(EXIT_INVALID_FILE);
+        }
+    }
+}
+
+/// Save a changed portfolio; with `--account` the changed account is merged back into
+/// the `stored` portfolio.
+fn save_portfolio(stored: &Portfolio, options: &Options, portfolio: &Portfolio) {
+    let portfolio = match &options.account {
+        Some(name) => accounts::merge(stored, name, portfolio),
+        None => portfolio.clone(),
+    };
+    let file_name = model::default_file_name();
+    let before = fs::read_to_string(&file_name).ok();
+    match model::write_file(None, &portfolio) {
+        Err(err) => {
+            println!("Failed to save portfolio file, error: {:?}", err);
+        },
+        Ok(_) => record_change(&file_name, before, options, EntryKind::Edit, None),
+    }
+}
+
+/// The provider for `command`, behind the quote cache; cached quotes are used for no
+/// longer than `max_ttl` seconds, if given, whatever the configured TTL.
+fn get_provider(options: &Options, portfolio: &Portfolio, command: &str, max_ttl: Option<u64>) -> Option<CachingProvider<SymbolRouter>> {
+    let user_config = match model::read_config_file(None) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Error reading configuration file, error: {:?}", err);
+            return None;
+        }
+    };
+    let cache_config = match &portfolio.cache {
+        Some(config) => Some(config.clone()),
+        None => user_config.cache,
+    };
+    let mode = if options.offline {
+        CacheMode::Offline
+    } else if options.refresh {
+        CacheMode::Refresh
+    } else {
+        let mode = CacheMode::Normal(command_ttl(&cache_config, command));
+        match max_ttl {
+            Some(seconds) => mode.limited(chrono::Duration::seconds(seconds as i64)),
+            None => mode,
+        }
+    };
+    if mode == CacheMode::Offline {
+        return Some(CachingProvider::new(None, &cache_config, mode));
+    }
+
+    // the command line overrides the portfolio file, which overrides the user's config.
+    let config = match &options.provider {
+        Some(provider) => parse_provider_arg(provider),
+        None => match &portfolio.provider {
+            Some(config) => config.clone(),
+            None => match user_config.provider {
+                Some(config) => config,
+                None => ProviderConfig {
+                    name: DEFAULT_PROVIDER_NAME.to_string(),
+                    ..Default::default()
+                },
+            },
+        },
+    };
+    match Registry::default().create_router(&config) {
+        Ok(provider) => Some(CachingProvider::new(Some(provider), &cache_config, mode)),
+        Err(RequestError::ConfigurationError(err)) => {
+            println!("Error configuring provider: {}", err);
+            None
+        }
+        Err(err) => {
+            println!("Unknown error from provider: {:?}", err);
             None
+        }
+    }
+}
+
+/// The `--from` and `--to` dates, defaulting to the first purchase and today.
+fn date_range(portfolio: &Portfolio, from: Option<String>, to: Option<String>) -> Result<(Date, Date), String> {
+    let to = match to {
+        Some(to) => parse_date(&to)?,
+        None => Local::now().date_naive(),
+    };
+    let from = match from {
+        Some(from) => parse_date(&from)?,
+        None => match history::first_date(portfolio) {
+            Some(from) => from,
+            None => to,
         },
+    };
+    Ok((from, to))
+}
+
+fn get_price_history(options: &Options) -> Option<PriceFile> {
+    let file_name = match &options.prices {
+        Some(file_name) => shellexpand::tilde(file_name).to_string(),
+        None => history::default_price_file_name(),
+    };
+    match PriceFile::open(&file_name) {
+        Ok(history) => Some(history),
+        Err(err) => {
+            println!("Error reading price history: {}", err);
+            None
+        }
+    }
+}
+
+fn get_converter<T: FetchPriceQuote>(options: &Options, portfolio: &Portfolio, provider: &T) -> Result<Option<Converter>, FxError> {
+    let target = match &options.report_currency {
+        Some(code) => match with_code(code) {
+            Some(currency) => currency,
+            None => return Err(FxError::CurrencyError(code.to_string())),
+        },
+        None => return Ok(None),
+    };
+    let mut rates = match &options.fx_rates {
+        Some(file_name) => fx::read_rates_file(&shellexpand::tilde(file_name))?,
+        None => {
+            let file_name = fx::default_rates_file_name();
+            if Path::new(&file_name).exists() {
+                fx::read_rates_file(&file_name)?
+            } else {
+                Vec::new()
+            }
+        }
+    };
+    // any currency in the portfolio without a local rate is looked up with the provider.
+    let mut currencies: Vec<Currency> = Vec::new();
+    for currency in ledger::current_items(portfolio)
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(_, h) => Some(h.purchase_price.currency),
+            _ => None,
+        })
+        .chain(portfolio.default_currency)
+    {
+        if !currencies.contains(&currency) {
+            currencies.push(currency);
+        }
+    }
+    for currency in currencies {
+        let known = Converter::new(target, rates.clone());
+        if currency != target && known.rate(currency).is_none() {
+            if let Some(rate) = fx::provider_rate(provider, currency, target) {
+                rates.push(rate);
+            }
+        }
     }
-}
\ No newline at end of file
+    Ok(Some(Converter::new(target, rates)))
+}
+
+/// A deposit, or withdrawal, of `amount` in the currency with code `currency`, on `date`.
+fn cash_transaction(amount: &str, currency: Option<String>, date: Option<String>, default_currency: Currency, deposit: bool) -> Result<Transaction, String> {
+    let currency = match currency {
+        Some(code) => match with_code(&code.to_uppercase()) {
+            Some(currency) => currency,
+            None => return Err(format!("Unknown currency {}", code)),
+        },
+        None => default_currency,
+    };
+    let amount = parse_amount(amount, currency)?;
+    let date = match date {
+        Some(date) => parse_date(&date)?,
+        None => Local::now().date_naive(),
+    };
+    if deposit {
+        Ok(Transaction::Deposit(date, amount))
+    } else {
+        Ok(Transaction::Withdrawal(date, amount))
+    }
+}
+
+/// The currency `symbol` was purchased in, from its first holding.
+fn held_currency(portfolio: &Portfolio, symbol: &Symbol) -> Option<Currency> {
+    ledger::current_items(portfolio)
+        .into_iter()
+        .find_map(|item| match item {
+            Item::Price(s, holding) if s.to_uppercase() == symbol.to_uppercase() =>
+                Some(holding.purchase_price.currency),
+            _ => None,
+        })
+}
+
+/// Parse a price in `currency`, such as `12.5` or `0`, which can't be negative; the
+/// fraction may have no more digits than the currency's minor unit.
+fn parse_price(p: &str, currency: Currency) -> Result<Money, String> {
+    let invalid = || format!("Could not parse amount {}, expected a number such as 12.50", p);
+    let places = currency.decimal_places() as usize;
+    let parts: Vec<&str> = p.trim().split('.').collect();
+    let (major, fraction) = match parts.as_slice() {
+        [major] => (*major, ""),
+        [major, fraction] if fraction.len() <= places => (*major, *fraction),
+        _ => return Err(invalid()),
+    };
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if (major.is_empty() && fraction.is_empty()) || !is_digits(major) || !is_digits(fraction) {
+        return Err(invalid());
+    }
+    // "12.5" is 12 and 50 hundredths, so the fraction is padded to the minor unit.
+    let minor = format!("{}{:0<width$}", major, fraction, width = places);
+    match minor.parse::<i32>() {
+        Ok(minor) => Ok(Money::of_minor(currency, minor)),
+        Err(_) => Err(invalid()),
+    }
+}
+
+/// Parse an amount of cash paid or received in `currency`, as `parse_price` but greater
+/// than zero.
+fn parse_amount(a: &str, currency: Currency) -> Result<Money, String> {
+    match parse_price(a, currency) {
+        Ok(amount) if amount.minor_amount() > 0 => Ok(amount),
+        Ok(_) => Err(format!("Could not parse amount {}, expected a positive number such as 12.50", a)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parse the `watch` refresh delay, a whole number of seconds.
+fn parse_delay(d: &str) -> Result<u64, String> {
+    match d.trim().parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Could not parse refresh delay {}, expected a number of seconds", d)),
+    }
+}
+
+fn parse_quantity(q: &str) -> Result<u32, String> {
+    match q.trim().parse::<u32>() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Could not parse quantity {}, expected a whole number", q)),
+    }
+}
+
+/// Parse which of a symbol's holdings to change, counting from 1.
+fn parse_entry(e: &str) -> Result<usize, String> {
+    match e.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Could not parse entry {}, expected a number from 1", e)),
+    }
+}
+
+/// Parse a percentage, such as `2.5`, as a fraction.
+fn parse_percent(p: &str) -> Result<f64, String> {
+    match p.trim_end_matches('%').parse::<f64>() {
+        Ok(n) => Ok(n / 100.0),
+        Err(_) => Err(format!("Could not parse percentage {}, expected a number such as 2.5", p)),
+    }
+}
+
+fn parse_date(d: &str) -> Result<Date, String> {
+    match Date::parse_from_str(d.trim(), DATE_FMT) {
+        Ok(d) => Ok(d),
+        Err(_) => Err(format!("Could not parse date {}, expected YYYY-MM-DD", d)),
+    }
+}
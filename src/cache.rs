@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use fin_model::prelude::*;
+use fin_model::provider::Provider;
+use fin_model::quote::{FetchPriceQuote, LatestPrice, PriceRange, Quote, QuoteData};
+use serde::{Deserialize, Serialize};
+use steel_cent::currency::with_code;
+
+use crate::display::money_from_minor;
+use crate::model::CacheConfig;
+use crate::storage;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// How the cache is used when a quote is requested.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheMode {
+    /// Use cached quotes younger than the TTL, otherwise fetch and cache a new quote.
+    Normal(Duration),
+    /// Only use cached quotes, of any age; the provider is never called.
+    Offline,
+    /// Always fetch and cache a new quote.
+    Refresh,
+}
+
+/// A provider that keeps quotes from another provider in a file, so that they may be
+/// reused by later commands. The cache file is replaced atomically when the provider is
+/// dropped.
+pub struct CachingProvider<T: FetchPriceQuote> {
+    provider: Option<T>,
+    mode: CacheMode,
+    file_name: String,
+    entries: Mutex<HashMap<Symbol, CacheEntry>>,
+    dirty: Mutex<bool>,
+}
+
+/// Provides the time at which a quote was retrieved.
+pub trait QuoteAge {
+    fn fetched(&self, symbol: &Symbol) -> Option<DateTime<Utc>>;
+}
+
+pub const DEFAULT_TTL: u64 = 60;
+
+// ------------------------------------------------------------------------------------------------
+// Private Types (serialization format)
+// ------------------------------------------------------------------------------------------------
+
+/// A cached quote, its prices are in minor units, such as cents, so they are kept exactly.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct CacheEntry {
+    pub fetched: String,
+    pub symbol: Symbol,
+    pub currency: String,
+    pub price_minor: i64,
+    pub change_minor: Option<i64>,
+    pub percentage: Option<f64>,
+    pub open_minor: Option<i64>,
+    pub low_minor: Option<i64>,
+    pub high_minor: Option<i64>,
+    pub close_minor: Option<i64>,
+    pub volume: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+struct CacheFile {
+    #[serde(default)]
+    pub quotes: Vec<CacheEntry>,
+}
+
+const CACHE_FILE_NAME: &str = "quotes.toml";
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn default_cache_directory() -> String {
+    shellexpand::tilde("~/.cache/folio").to_string()
+}
+
+/// The TTL for `command` from the configuration, or `DEFAULT_TTL`.
+pub fn command_ttl(config: &Option<CacheConfig>, command: &str) -> Duration {
+    let seconds = match config {
+        Some(config) => match config.ttl.get(command) {
+            Some(ttl) => *ttl,
+            None => config.default_ttl.unwrap_or(DEFAULT_TTL),
+        },
+        None => DEFAULT_TTL,
+    };
+    Duration::seconds(seconds as i64)
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl CacheMode {
+    /// The mode with its TTL no longer than `ttl`; a command that refreshes, such as
+    /// `watch`, must not redraw quotes older than its refresh delay.
+    pub fn limited(self, ttl: Duration) -> CacheMode {
+        match self {
+            CacheMode::Normal(current) if current > ttl => CacheMode::Normal(ttl),
+            mode => mode,
+        }
+    }
+}
+
+impl<T: FetchPriceQuote> CachingProvider<T> {
+    /// Create a caching provider; `provider` may be `None` only in offline mode.
+    pub fn new(provider: Option<T>, config: &Option<CacheConfig>, mode: CacheMode) -> Self {
+        let directory = match config.as_ref().and_then(|c| c.directory.as_ref()) {
+            Some(directory) => shellexpand::tilde(directory).to_string(),
+            None => default_cache_directory(),
+        };
+        let file_name = Path::new(&directory).join(CACHE_FILE_NAME).to_string_lossy().to_string();
+        let entries = match read_cache_file(&file_name) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Ignoring unreadable quote cache {}: {:?}", file_name, err);
+                HashMap::new()
+            }
+        };
+        CachingProvider {
+            provider,
+            mode,
+            file_name,
+            entries: Mutex::new(entries),
+            dirty: Mutex::new(false),
+        }
+    }
+
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut quotes: Vec<CacheEntry> = entries.values().cloned().collect();
+        quotes.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        let toml = match toml::to_string(&CacheFile { quotes }) {
+            Ok(data) => data,
+            Err(err) => return Err(io::Error::other(err)),
+        };
+        if let Some(directory) = Path::new(&self.file_name).parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // the cache is not worth backing up, but two commands may write it at once.
+        storage::replace(&self.file_name, toml.as_bytes())?;
+        *self.dirty.lock().unwrap() = false;
+        Ok(())
+    }
+
+    fn cached(&self, symbol: &Symbol, max_age: Option<Duration>) -> Option<Quote> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&symbol.to_uppercase()) {
+            Some(entry) => {
+                let fresh = match (max_age, parse_time(&entry.fetched)) {
+                    (None, _) => true,
+                    (Some(max_age), Some(fetched)) => Utc::now().signed_duration_since(fetched) < max_age,
+                    (Some(_), None) => false,
+                };
+                if fresh {
+                    debug!("cache::cached hit {}", symbol);
+                    entry.quote()
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, symbol: &Symbol, quote: &Quote) {
+        self.entries.lock().unwrap().insert(
+            symbol.to_uppercase(),
+            CacheEntry::new(&symbol.to_uppercase(), quote),
+        );
+        *self.dirty.lock().unwrap() = true;
+    }
+}
+
+impl CacheEntry {
+    fn new(symbol: &Symbol, quote: &Quote) -> Self {
+        let latest = &quote.data.latest;
+        let range = quote.data.range.as_ref();
+        CacheEntry {
+            fetched: Utc::now().to_rfc3339(),
+            symbol: symbol.to_string(),
+            currency: latest.price.currency.code(),
+            price_minor: latest.price.minor_amount() as i64,
+            change_minor: latest.change.map(|change| change.minor_amount() as i64),
+            percentage: latest.percentage,
+            open_minor: range.map(|r| r.open.minor_amount() as i64),
+            low_minor: range.map(|r| r.low.minor_amount() as i64),
+            high_minor: range.map(|r| r.high.minor_amount() as i64),
+            close_minor: range.map(|r| r.close.minor_amount() as i64),
+            volume: range.and_then(|r| r.volume),
+        }
+    }
+
+    /// The cached quote, `None` if its currency is not known.
+    fn quote(&self) -> Option<Quote> {
+        let currency = with_code(&self.currency)?;
+        let money = |minor: i64| money_from_minor(currency, minor);
+        Some(Quote {
+            symbol: self.symbol.to_string(),
+            data: QuoteData {
+                latest: LatestPrice {
+                    price: money(self.price_minor),
+                    change: self.change_minor.map(money),
+                    percentage: self.percentage,
+                },
+                range: match (self.open_minor, self.low_minor, self.high_minor, self.close_minor) {
+                    (Some(open), Some(low), Some(high), Some(close)) => Some(PriceRange {
+                        open: money(open),
+                        low: money(low),
+                        high: money(high),
+                        close: money(close),
+                        volume: self.volume,
+                    }),
+                    _ => None,
+                },
+            },
+        })
+    }
+}
+
+impl<T: FetchPriceQuote> QuoteAge for CachingProvider<T> {
+    fn fetched(&self, symbol: &Symbol) -> Option<DateTime<Utc>> {
+        match self.entries.lock().unwrap().get(&symbol.to_uppercase()) {
+            Some(entry) => parse_time(&entry.fetched),
+            None => None,
+        }
+    }
+}
+
+impl<T: FetchPriceQuote> Provider for CachingProvider<T> {
+    fn name(&self) -> &str {
+        match &self.provider {
+            Some(provider) => provider.name(),
+            None => "cache",
+        }
+    }
+}
+
+impl<T: FetchPriceQuote> FetchPriceQuote for CachingProvider<T> {
+    fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+        let max_age = match self.mode {
+            CacheMode::Normal(ttl) => Some(ttl),
+            CacheMode::Offline => None,
+            CacheMode::Refresh => Some(Duration::zero()),
+        };
+        if let Some(quote) = self.cached(&for_symbol, max_age) {
+            return Ok(quote);
+        }
+        match (&self.provider, self.mode) {
+            (_, CacheMode::Offline) | (None, _) => Err(RequestError::ConfigurationError(
+                format!("no cached quote for {}", for_symbol))),
+            (Some(provider), _) => {
+                let quote = provider.real_time(for_symbol.to_string())?;
+                self.store(&for_symbol, &quote);
+                Ok(quote)
+            }
+        }
+    }
+}
+
+impl<T: FetchPriceQuote> Drop for CachingProvider<T> {
+    fn drop(&mut self) {
+        if *self.dirty.lock().unwrap() {
+            match self.save() {
+                Ok(_) => (),
+                Err(err) => warn!("Could not write quote cache {}: {:?}", self.file_name, err),
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => Some(time.with_timezone(&Utc)),
+        Err(_) => None,
+    }
+}
+
+fn read_cache_file(file_name: &str) -> Result<HashMap<Symbol, CacheEntry>, String> {
+    let mut f = match File::open(file_name) {
+        Ok(handle) => handle,
+        Err(_) => return Ok(HashMap::new()),
+    };
+
+    let mut buffer = String::new();
+    match f.read_to_string(&mut buffer) {
+        Ok(_) => (),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    match toml::from_str::<CacheFile>(&buffer) {
+        Ok(cache) => Ok(cache
+            .quotes
+            .into_iter()
+            .filter(|entry| with_code(&entry.currency).is_some())
+            .map(|entry| (entry.symbol.to_uppercase(), entry))
+            .collect()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Quotes a price one cent higher on each request, starting at $123.46.
+    struct CountingProvider {
+        requests: AtomicU32,
+    }
+
+    impl Provider for CountingProvider {
+        fn name(&self) -> &str {
+            "counting"
+        }
+    }
+
+    impl FetchPriceQuote for CountingProvider {
+        fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+            let requests = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Quote {
+                symbol: for_symbol,
+                data: QuoteData {
+                    latest: LatestPrice {
+                        price: Money::of_minor(with_code("USD").unwrap(), 12_345 + requests as i32),
+                        change: None,
+                        percentage: None,
+                    },
+                    range: None,
+                },
+            })
+        }
+    }
+
+    fn counting() -> CountingProvider {
+        CountingProvider { requests: AtomicU32::new(0) }
+    }
+
+    fn config(name: &str) -> Option<CacheConfig> {
+        let directory = std::env::temp_dir().join(format!("folio-cache-{}-{}", name, std::process::id()));
+        Some(CacheConfig {
+            directory: Some(directory.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn remove(config: &Option<CacheConfig>) {
+        let _ = fs::remove_dir_all(config.as_ref().unwrap().directory.as_ref().unwrap());
+    }
+
+    fn price(provider: &dyn FetchPriceQuote, symbol: &str) -> Option<i32> {
+        provider.real_time(symbol.to_string()).ok().map(|quote| quote.data.latest.price.minor_amount())
+    }
+
+    #[test]
+    fn reuses_quotes_younger_than_the_ttl() {
+        let config = config("ttl");
+        let cache = CachingProvider::new(Some(counting()), &config, CacheMode::Normal(Duration::seconds(60)));
+        assert_eq!(price(&cache, "AAPL"), Some(12_346));
+        assert_eq!(price(&cache, "aapl"), Some(12_346));
+        assert_eq!(cache.provider.as_ref().unwrap().requests.load(Ordering::SeqCst), 1);
+
+        // a quote older than the TTL is fetched again.
+        let old = (Utc::now() - Duration::seconds(120)).to_rfc3339();
+        cache.entries.lock().unwrap().get_mut("AAPL").unwrap().fetched = old;
+        assert_eq!(price(&cache, "AAPL"), Some(12_347));
+
+        let refresh = CachingProvider::new(Some(counting()), &config, CacheMode::Refresh);
+        assert_eq!(price(&refresh, "AAPL"), Some(12_346));
+        assert_eq!(price(&refresh, "AAPL"), Some(12_347));
+        drop(refresh);
+        drop(cache);
+        remove(&config);
+    }
+
+    #[test]
+    fn limits_the_ttl() {
+        let ttl = Duration::seconds(5);
+        assert_eq!(CacheMode::Normal(Duration::seconds(60)).limited(ttl), CacheMode::Normal(ttl));
+        assert_eq!(CacheMode::Normal(Duration::seconds(1)).limited(ttl), CacheMode::Normal(Duration::seconds(1)));
+        assert_eq!(CacheMode::Offline.limited(ttl), CacheMode::Offline);
+        assert_eq!(CacheMode::Refresh.limited(ttl), CacheMode::Refresh);
+    }
+
+    #[test]
+    fn uses_only_cached_quotes_offline() {
+        let config = config("offline");
+        let cache = CachingProvider::new(Some(counting()), &config, CacheMode::Normal(Duration::seconds(60)));
+        assert_eq!(price(&cache, "AAPL"), Some(12_346));
+        let old = (Utc::now() - Duration::days(7)).to_rfc3339();
+        cache.entries.lock().unwrap().get_mut("AAPL").unwrap().fetched = old;
+        cache.save().unwrap();
+        drop(cache);
+
+        // quotes of any age are read back, exactly, from the file.
+        let offline: CachingProvider<CountingProvider> = CachingProvider::new(None, &config, CacheMode::Offline);
+        assert_eq!(price(&offline, "AAPL"), Some(12_346));
+        assert!(offline.fetched(&"AAPL".to_string()).is_some());
+        match offline.real_time("MSFT".to_string()) {
+            Err(RequestError::ConfigurationError(message)) => assert!(message.contains("MSFT"), "{}", message),
+            other => panic!("expected no cached quote, not {:?}", other.map(|quote| quote.symbol)),
+        }
+        drop(offline);
+        remove(&config);
+    }
+}
@@ -0,0 +1,92 @@
+use fin_model::prelude::*;
+
+use crate::ledger;
+use crate::model::{Cash, Portfolio, Transaction};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The asset class cash balances are counted in; money-market holdings classified with
+/// this asset class are grouped with them.
+pub const CASH_ASSET_CLASS: &str = "cash";
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The cash balance in each currency, for each account, combining the balances in the
+/// portfolio file with the cash effect of deposits, withdrawals, buys, sells, dividends
+/// and fees in the transaction logs. Balances are in the order they first appear.
+pub fn cash_balances(portfolio: &Portfolio) -> Vec<Cash> {
+    let mut balances: Vec<Cash> = Vec::new();
+    for cash in &portfolio.cash {
+        add_balance(&mut balances, cash.balance, &cash.account);
+    }
+    for balance in ledger::replay_with_actions(&portfolio.transactions, &portfolio.actions, None).cash {
+        add_balance(&mut balances, balance, &None);
+    }
+    for account in &portfolio.accounts {
+        let name = Some(account.name.to_string());
+        for balance in ledger::replay_with_actions(&account.transactions, &portfolio.actions, None).cash {
+            add_balance(&mut balances, balance, &name);
+        }
+    }
+    balances
+}
+
+/// Add `amount`, negative for a payment, to the portfolio file's cash balance in its
+/// currency for `account`.
+pub fn add_cash(portfolio: &mut Portfolio, amount: Money, account: &Option<String>) {
+    add_balance(&mut portfolio.cash, amount, account);
+}
+
+/// Whether cash is tracked for `account`, that is it has a `[[cash]]` balance or its
+/// transaction log records a deposit or withdrawal. A portfolio that only tracks its
+/// holdings has no cash to pay for shares from.
+pub fn tracks_cash(portfolio: &Portfolio, account: &Option<String>) -> bool {
+    let transactions = match account {
+        None => Some(&portfolio.transactions),
+        Some(name) => portfolio
+            .accounts
+            .iter()
+            .find(|a| a.name == *name)
+            .map(|a| &a.transactions),
+    };
+    portfolio.cash.iter().any(|cash| cash.account == *account)
+        || transactions.is_some_and(|transactions| {
+            transactions
+                .iter()
+                .any(|transaction| matches!(transaction, Transaction::Deposit(_, _) | Transaction::Withdrawal(_, _)))
+        })
+}
+
+/// Pay `amount` for shares bought from the cash balance of `account`, if it tracks cash;
+/// shares without a price, such as those given to a watched symbol, have nothing to pay.
+pub fn pay_for_shares(portfolio: &mut Portfolio, amount: Money, account: &Option<String>) {
+    if amount.minor_amount() != 0 && tracks_cash(portfolio, account) {
+        add_cash(portfolio, amount * -1, account);
+    }
+}
+
+/// The label used in place of a symbol for a cash balance, for example "USD cash".
+pub fn cash_symbol(balance: &Money) -> Symbol {
+    format!("{} cash", balance.currency.code())
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn add_balance(balances: &mut Vec<Cash>, amount: Money, account: &Option<String>) {
+    match balances
+        .iter_mut()
+        .find(|cash| cash.balance.currency == amount.currency && cash.account == *account)
+    {
+        Some(cash) => cash.balance = cash.balance + amount,
+        None => balances.push(Cash {
+            balance: amount,
+            account: account.clone(),
+        }),
+    }
+}
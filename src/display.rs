@@ -1,14 +1,17 @@
+use std::convert::TryFrom;
+
 use fin_model::prelude::*;
 use fin_model::quote::Quote;
 
 use num_format::{SystemLocale, ToFormattedString};
 use prettytable::{Attr, Cell, color};
 use prettytable::format::Alignment;
+use steel_cent::currency::Currency;
 use steel_cent::formatting::{format, us_style};
 
 use crate::model::Item;
 
-pub const DATE_FMT: &'static str = "%Y-%m-%d";
+pub const DATE_FMT: &str = "%Y-%m-%d";
 
 pub fn bold(cell: Cell) -> Cell {
     cell.with_style(Attr::Bold)
@@ -29,6 +32,41 @@ pub fn price_cell_or(value: Option<Money>, default: Cell) -> Cell {
     }
 }
 
+pub fn gain_cell(value: Money) -> Cell {
+    if value.minor_amount().is_negative() {
+        price_cell(value).with_style(Attr::ForegroundColor(color::RED))
+    } else {
+        price_cell(value).with_style(Attr::ForegroundColor(color::GREEN))
+    }
+}
+
+pub fn percent_cell(value: f64) -> Cell {
+    let cell = Cell::new_align(&format!("{:.2}%", value), Alignment::RIGHT);
+    if value < 0.0 {
+        cell.with_style(Attr::ForegroundColor(color::RED))
+    } else {
+        cell.with_style(Attr::ForegroundColor(color::GREEN))
+    }
+}
+
+pub fn percent_cell_or(value: Option<f64>, default: Cell) -> Cell {
+    match value {
+        Some(value) => percent_cell(value),
+        None => default,
+    }
+}
+
+pub fn date_cell(value: Date) -> Cell {
+    Cell::new_align(&value.format(DATE_FMT).to_string(), Alignment::CENTER)
+}
+
+pub fn date_cell_or(value: Option<Date>, default: Cell) -> Cell {
+    match value {
+        Some(value) => date_cell(value),
+        None => default,
+    }
+}
+
 pub fn number_cell(value: i64, locale: &SystemLocale) -> Cell {
     Cell::new_align(&value.to_formatted_string(locale), Alignment::RIGHT)
 }
@@ -40,6 +78,21 @@ pub fn number_cell_or(value: Option<u64>, locale: &SystemLocale, default: Cell)
     }
 }
 
+/// A short, human-readable, form of a duration such as `2d 3h` or `45m`.
+pub fn age_string(age: chrono::Duration) -> String {
+    let seconds = age.num_seconds().max(0);
+    let (days, hours, minutes) = (seconds / 86_400, (seconds % 86_400) / 3_600, (seconds % 3_600) / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
 pub fn item_symbol(item: &Item) -> String {
     match item {
         Item::Watch(s) | Item::Price(s, _) => s.to_string(),
@@ -62,17 +115,96 @@ pub fn change_string(change: &Money, percentage: &f64) -> String {
 
 pub fn change_cell(quote: &Quote) -> Cell {
     match (quote.data.latest.change, quote.data.latest.percentage) {
-        (Some(change), Some(percentage)) => {
-            let value = change_string(&change, &percentage);
-            if change.minor_amount().is_positive() {
-                Cell::new_align(&value, Alignment::RIGHT)
-                    .with_style(Attr::ForegroundColor(color::GREEN))
+        (Some(change), Some(percentage)) => change_value_cell(&change, &percentage),
+        (_, _) => default_cell()
+    }
+}
+
+pub fn change_value_cell(change: &Money, percentage: &f64) -> Cell {
+    let value = change_string(change, percentage);
+    if change.minor_amount().is_positive() {
+        Cell::new_align(&value, Alignment::RIGHT)
+            .with_style(Attr::ForegroundColor(color::GREEN))
+    } else {
+        Cell::new_align(&value, Alignment::RIGHT)
+            .with_style(Attr::ForegroundColor(color::RED))
+    }
+}
+
+/// Format a money value as a plain decimal string, without currency symbol or grouping,
+/// for example `-1234.50`.
+pub fn money_decimal(value: &Money) -> String {
+    let places = value.currency.decimal_places() as u32;
+    let minor = value.minor_amount() as i64;
+    if places == 0 {
+        minor.to_string()
+    } else {
+        let scale = 10i64.pow(places);
+        format!(
+            "{}{}.{:0width$}",
+            if minor < 0 { "-" } else { "" },
+            (minor / scale).abs(),
+            (minor % scale).abs(),
+            width = places as usize
+        )
+    }
+}
+
+pub fn money_f64(value: &Money) -> f64 {
+    value.minor_amount() as f64 / 10f64.powi(value.currency.decimal_places() as i32)
+}
+
+pub fn money_from_f64(currency: Currency, value: f64) -> Money {
+    money_from_minor(currency, (value * 10f64.powi(currency.decimal_places() as i32)).round() as i64)
+}
+
+/// A money value from an amount in minor units; `Money` holds an `i32`, an amount outside
+/// its range is limited to the smallest, or largest, value with a warning.
+pub fn money_from_minor(currency: Currency, minor: i64) -> Money {
+    match i32::try_from(minor) {
+        Ok(minor) => Money::of_minor(currency, minor),
+        Err(_) => {
+            warn!("Amount of {} minor units of {} is out of range", minor, currency.code());
+            if minor < 0 {
+                Money::min(currency)
             } else {
-                Cell::new_align(&value, Alignment::RIGHT)
-                    .with_style(Attr::ForegroundColor(color::RED))
+                Money::max(currency)
             }
-        },
-        (_, _) => default_cell()
+        }
     }
 }
 
+/// The value of `quantity` shares at `price`, computed without overflow and limited to the
+/// range of `Money` as by `money_from_minor`.
+pub fn money_times(price: Money, quantity: u32) -> Money {
+    money_from_minor(price.currency, price.minor_amount() as i64 * i64::from(quantity))
+}
+
+/// The change in value of `quantity` shares from `from` to `to`, both in the same
+/// currency, computed without overflow as `money_times` is.
+pub fn money_change_times(from: Money, to: Money, quantity: u32) -> Money {
+    let change = to.minor_amount() as i64 - from.minor_amount() as i64;
+    money_from_minor(to.currency, change * i64::from(quantity))
+}
+
+/// Add `amount` to a running total, computed without overflow as `money_times` is. There
+/// is no total of amounts in different currencies, so `None` is returned instead.
+pub fn add_money(total: Money, amount: Money) -> Option<Money> {
+    if total.currency == amount.currency {
+        Some(money_from_minor(total.currency, total.minor_amount() as i64 + amount.minor_amount() as i64))
+    } else {
+        None
+    }
+}
+
+/// The total of `amounts`, `None` if there are none; amounts in more than one currency
+/// have no total, and the error names two of them.
+pub fn sum_money<I: IntoIterator<Item = Money>>(amounts: I) -> Result<Option<Money>, String> {
+    amounts.into_iter().try_fold(None, |total, amount| match total {
+        Some(total) => add_money(total, amount)
+            .map(Some)
+            .ok_or_else(|| format!("amounts in both {} and {}", total.currency.code(), amount.currency.code())),
+        None => Ok(Some(amount)),
+    })
+}
+
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::io;
+
+use fin_model::prelude::*;
+use fin_model::provider::Provider;
+use fin_model::quote::{FetchPriceQuote, LatestPrice, PriceRange, Quote, QuoteData};
+use serde::Deserialize;
+use steel_cent::currency::{Currency, with_code};
+
+use crate::display::money_from_f64;
+use crate::model::DEFAULT_CURRENCY;
+use crate::storage::{self, DataFileError};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum FixtureError {
+    PathError(String),
+    FileError(io::Error),
+    FormatError(String),
+    CurrencyError(String),
+}
+
+/// A quote provider that reads fixed quotes from a local file rather than a remote
+/// service; the file may be TOML, JSON or CSV, chosen by its extension.
+#[derive(Debug)]
+pub struct FileProvider {
+    file_name: String,
+    quotes: HashMap<Symbol, FixtureQuote>,
+}
+
+pub const FILE_PROVIDER_NAME: &str = "file";
+
+// ------------------------------------------------------------------------------------------------
+// Private Types (serialization format)
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize, Clone, Debug)]
+struct FixtureQuote {
+    pub symbol: Symbol,
+    pub currency: Option<String>,
+    pub price: f64,
+    pub change: Option<f64>,
+    pub percentage: Option<f64>,
+    pub open: Option<f64>,
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    pub close: Option<f64>,
+    pub volume: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct FixtureFile {
+    pub currency: Option<String>,
+    pub quotes: Vec<FixtureQuote>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl FileProvider {
+    pub fn open(file_name: &str) -> Result<Self, FixtureError> {
+        info!("fixture::open {}", file_name);
+        let fixture = match storage::read_data_file(file_name, |quotes| FixtureFile { currency: None, quotes }) {
+            Ok(fixture) => fixture,
+            Err(DataFileError::PathError(path)) => return Err(FixtureError::PathError(path)),
+            Err(DataFileError::FileError(err)) => return Err(FixtureError::FileError(err)),
+            Err(DataFileError::FormatError(err)) => return Err(FixtureError::FormatError(err)),
+        };
+
+        let mut quotes: HashMap<Symbol, FixtureQuote> = HashMap::new();
+        for quote in fixture.quotes {
+            let currency = quote
+                .currency
+                .clone()
+                .or(fixture.currency.clone())
+                .unwrap_or(DEFAULT_CURRENCY.to_string());
+            if with_code(&currency).is_none() {
+                return Err(FixtureError::CurrencyError(currency));
+            }
+            quotes.insert(quote.symbol.to_uppercase(), FixtureQuote { currency: Some(currency), ..quote });
+        }
+
+        Ok(FileProvider {
+            file_name: file_name.to_string(),
+            quotes,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.quotes.keys().cloned().collect()
+    }
+}
+
+impl Provider for FileProvider {
+    fn name(&self) -> &str {
+        FILE_PROVIDER_NAME
+    }
+}
+
+impl FetchPriceQuote for FileProvider {
+    fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+        match self.quotes.get(&for_symbol.to_uppercase()) {
+            Some(fixture) => Ok(fixture.quote()),
+            None => Err(RequestError::ConfigurationError(
+                format!("no quote for {} in {}", for_symbol, self.file_name))),
+        }
+    }
+}
+
+impl FixtureQuote {
+    fn currency(&self) -> Currency {
+        // validated when the file is opened.
+        with_code(self.currency.as_ref().unwrap()).unwrap()
+    }
+
+    fn quote(&self) -> Quote {
+        let currency = self.currency();
+        let money = |value: f64| money_from_f64(currency, value);
+        Quote {
+            symbol: self.symbol.to_string(),
+            data: QuoteData {
+                latest: LatestPrice {
+                    price: money(self.price),
+                    change: self.change.map(money),
+                    percentage: self.percentage,
+                },
+                range: match (self.open, self.low, self.high, self.close) {
+                    (Some(open), Some(low), Some(high), Some(close)) => Some(PriceRange {
+                        open: money(open),
+                        low: money(low),
+                        high: money(high),
+                        close: money(close),
+                        volume: self.volume,
+                    }),
+                    _ => None,
+                },
+            },
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+use chrono::Local;
+use fin_model::prelude::*;
+use fin_model::quote::FetchPriceQuote;
+use serde::Deserialize;
+use steel_cent::currency::{Currency, with_code};
+
+use crate::display::{money_f64, money_from_f64, DATE_FMT};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum FxError {
+    PathError(String),
+    FormatError(String),
+    CurrencyError(String),
+    NoRate(String, String),
+}
+
+/// An exchange rate, one unit of `from` is `rate` units of `to`.
+#[derive(Clone, Debug)]
+pub struct Rate {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: f64,
+    pub date: Option<Date>,
+    pub source: String,
+}
+
+/// Converts money values into a single reporting currency, at the rates in effect on a
+/// valuation date, recording each rate used so that reports can say how values were
+/// converted.
+#[derive(Debug)]
+pub struct Converter {
+    target: Currency,
+    rates: Vec<Rate>,
+    date: Date,
+    used: Mutex<Vec<Rate>>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types (serialization format)
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize, Clone)]
+struct SerializedRate {
+    pub from: String,
+    pub to: String,
+    pub rate: f64,
+    pub date: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct SerializedRates {
+    #[serde(default)]
+    pub rates: Vec<SerializedRate>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn default_rates_file_name() -> String {
+    shellexpand::tilde("~/.config/folio/rates.toml").to_string()
+}
+
+/// Read exchange rates from a local TOML file of `[[rates]]` entries; each rate must be a
+/// finite, positive number.
+pub fn read_rates_file(file_name: &str) -> Result<Vec<Rate>, FxError> {
+    info!("fx::read_rates_file {}", file_name);
+    let mut f = match File::open(file_name) {
+        Ok(handle) => handle,
+        Err(_) => return Err(FxError::PathError(file_name.to_string())),
+    };
+
+    let mut buffer = String::new();
+    match f.read_to_string(&mut buffer) {
+        Ok(_) => (),
+        Err(err) => return Err(FxError::FormatError(err.to_string())),
+    };
+
+    let serialized: SerializedRates = match toml::from_str(&buffer) {
+        Ok(rates) => rates,
+        Err(err) => return Err(FxError::FormatError(err.to_string())),
+    };
+
+    serialized
+        .rates
+        .iter()
+        .map(|rate| {
+            if !rate.rate.is_finite() || rate.rate <= 0.0 {
+                return Err(FxError::FormatError(format!("rate from {} to {} is not positive, {}", rate.from, rate.to, rate.rate)));
+            }
+            Ok(Rate {
+                from: currency(&rate.from)?,
+                to: currency(&rate.to)?,
+                rate: rate.rate,
+                date: match &rate.date {
+                    Some(date) => match Date::parse_from_str(date, DATE_FMT) {
+                        Ok(d) => Some(d),
+                        Err(_) => return Err(FxError::FormatError(format!("could not parse date {}", date))),
+                    },
+                    None => None,
+                },
+                source: file_name.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Retrieve an exchange rate from a quote provider, using the symbol `FROMTO`, for
+/// example `EURUSD`.
+pub fn provider_rate<T: FetchPriceQuote>(provider: &T, from: Currency, to: Currency) -> Option<Rate> {
+    let symbol = format!("{}{}", from.code(), to.code());
+    match provider.real_time(symbol.to_string()) {
+        Ok(quote) => Some(Rate {
+            from,
+            to,
+            rate: money_f64(&quote.data.latest.price),
+            date: Some(Local::now().date_naive()),
+            source: format!("{} {}", provider.name(), symbol),
+        }),
+        Err(err) => {
+            warn!("Could not retrieve exchange rate {}: {:?}", symbol, err);
+            None
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Rate {
+    /// The rate in the opposite direction.
+    pub fn inverse(&self) -> Rate {
+        Rate {
+            from: self.to,
+            to: self.from,
+            rate: 1.0 / self.rate,
+            date: self.date,
+            source: self.source.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "1 {} = {} {} ({}, {})",
+            self.from.code(),
+            self.rate,
+            self.to.code(),
+            match self.date {
+                Some(date) => date.format(DATE_FMT).to_string(),
+                None => "undated".to_string(),
+            },
+            self.source
+        )
+    }
+}
+
+impl Converter {
+    /// A converter for values on the current date.
+    pub fn new(target: Currency, rates: Vec<Rate>) -> Self {
+        Converter::on(target, rates, Local::now().date_naive())
+    }
+
+    /// A converter for values on `date`.
+    pub fn on(target: Currency, rates: Vec<Rate>, date: Date) -> Self {
+        Converter {
+            target,
+            rates,
+            date,
+            used: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn target(&self) -> Currency {
+        self.target
+    }
+
+    /// Find the rate from `from` to the reporting currency, directly or as the inverse of
+    /// a rate in the other direction. Of the rates not dated after the valuation date, the
+    /// latest is used; an undated rate only when there is no dated one, and a direct rate
+    /// in preference to an inverse of the same date.
+    pub fn rate(&self, from: Currency) -> Option<Rate> {
+        let direct = self.rates.iter().filter(|r| r.from == from && r.to == self.target).cloned();
+        let inverse = self.rates.iter().filter(|r| r.from == self.target && r.to == from).map(|r| r.inverse());
+        let mut latest: Option<Rate> = None;
+        for rate in direct.chain(inverse).filter(|r| r.date.is_none_or(|date| date <= self.date)) {
+            if latest.as_ref().is_none_or(|latest| rate.date > latest.date) {
+                latest = Some(rate);
+            }
+        }
+        latest
+    }
+
+    pub fn convert(&self, value: Money) -> Result<Money, FxError> {
+        if value.currency == self.target {
+            return Ok(value);
+        }
+        match self.rate(value.currency) {
+            Some(rate) => {
+                let mut used = self.used.lock().unwrap();
+                if !used.iter().any(|r| r.from == rate.from) {
+                    used.push(rate.clone());
+                }
+                Ok(money_from_f64(self.target, money_f64(&value) * rate.rate))
+            }
+            None => Err(FxError::NoRate(value.currency.code(), self.target.code())),
+        }
+    }
+
+    /// The rates used so far, in the order they were first used.
+    pub fn used(&self) -> Vec<Rate> {
+        self.used.lock().unwrap().clone()
+    }
+
+    /// Print the rates used, after a report; written to stderr for formats other than
+    /// tables so as not to corrupt the output.
+    pub fn print_used(&self, to_stdout: bool) {
+        for rate in self.used() {
+            if to_stdout {
+                println!("Converted at {}", rate);
+            } else {
+                eprintln!("Converted at {}", rate);
+            }
+        }
+    }
+}
+
+impl fmt::Display for FxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FxError::PathError(path) => write!(f, "could not open {}", path),
+            FxError::FormatError(err) => write!(f, "{}", err),
+            FxError::CurrencyError(code) => write!(f, "unknown currency {}", code),
+            FxError::NoRate(from, to) => write!(f, "no exchange rate from {} to {}", from, to),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn currency(code: &str) -> Result<Currency, FxError> {
+    match with_code(code) {
+        Some(currency) => Ok(currency),
+        None => Err(FxError::CurrencyError(code.to_string())),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd() -> Currency {
+        with_code("USD").unwrap()
+    }
+
+    fn eur() -> Currency {
+        with_code("EUR").unwrap()
+    }
+
+    fn gbp() -> Currency {
+        with_code("GBP").unwrap()
+    }
+
+    /// One euro is 1.25 dollars, and one dollar is 0.8 pounds.
+    fn converter(target: Currency) -> Converter {
+        let rate = |from: Currency, to: Currency, rate: f64| Rate {
+            from,
+            to,
+            rate,
+            date: None,
+            source: "test".to_string(),
+        };
+        Converter::new(target, vec![rate(eur(), usd(), 1.25), rate(usd(), gbp(), 0.8)])
+    }
+
+    #[test]
+    fn finds_a_rate_and_its_inverse() {
+        let converter = converter(usd());
+        let direct = converter.rate(eur()).unwrap();
+        assert_eq!((direct.from, direct.to, direct.rate), (eur(), usd(), 1.25));
+
+        let inverse = converter.rate(gbp()).unwrap();
+        assert_eq!((inverse.from, inverse.to, inverse.rate), (gbp(), usd(), 1.25));
+        assert_eq!(inverse.source, "test");
+
+        assert!(converter.rate(with_code("JPY").unwrap()).is_none());
+    }
+
+    #[test]
+    fn uses_the_latest_rate_on_the_valuation_date() {
+        let date = |day: u32| Date::from_ymd_opt(2020, 3, day);
+        let rate = |rate: f64, date: Option<Date>| Rate {
+            from: eur(),
+            to: usd(),
+            rate,
+            date,
+            source: "test".to_string(),
+        };
+        let rates = vec![rate(1.10, None), rate(1.20, date(2)), rate(1.30, date(4)), rate(1.15, date(1))];
+
+        let rate_on = |day: u32| Converter::on(usd(), rates.clone(), date(day).unwrap()).rate(eur()).unwrap();
+        assert_eq!(rate_on(1).rate, 1.15);
+        assert_eq!(rate_on(3).rate, 1.20);
+        assert_eq!(rate_on(4).rate, 1.30);
+        assert_eq!(rate_on(5).date, date(4));
+
+        // before every dated rate only the undated one applies.
+        let early = Converter::on(usd(), rates.clone(), Date::from_ymd_opt(2020, 2, 1).unwrap());
+        assert_eq!(early.rate(eur()).unwrap().rate, 1.10);
+        let dated = Converter::on(usd(), rates[1..].to_vec(), Date::from_ymd_opt(2020, 2, 1).unwrap());
+        assert!(dated.rate(eur()).is_none());
+    }
+
+    #[test]
+    fn converts_and_records_each_rate_used() {
+        let converter = converter(usd());
+        let dollars = Money::of_major(usd(), 10);
+        assert_eq!(converter.convert(dollars).unwrap(), dollars);
+        assert_eq!(converter.convert(Money::of_major(eur(), 10)).unwrap(), Money::of_major_minor(usd(), 12, 50));
+        assert_eq!(converter.convert(Money::of_major(gbp(), 8)).unwrap(), dollars);
+        assert_eq!(converter.convert(Money::of_major(eur(), 2)).unwrap(), Money::of_major_minor(usd(), 2, 50));
+
+        let used: Vec<Currency> = converter.used().iter().map(|rate| rate.from).collect();
+        assert_eq!(used, vec![eur(), gbp()]);
+
+        match converter.convert(Money::of_major(with_code("JPY").unwrap(), 100)) {
+            Err(FxError::NoRate(from, to)) => assert_eq!((from.as_str(), to.as_str()), ("JPY", "USD")),
+            other => panic!("expected no rate, not {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use fin_model::prelude::*;
+use fin_model::quote::FetchPriceQuote;
+use crate::display::{add_money, money_times};
+use crate::fx::Converter;
+use crate::ledger;
+use crate::model::{Item, Portfolio};
+use crate::quotes::{fetch_quotes, partition, DEFAULT_WORKERS};
+use crate::report::{OutputFormat, Report, Value};
+use crate::show::print_rates;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The error of a total that would add amounts in more than one currency.
+pub const MIXED_CURRENCIES: &str = "mixed currencies, use --report-currency";
+
+/// Gains for a single symbol, or for the whole portfolio when `symbol` is `None`.
+#[derive(Clone, Debug)]
+pub struct Gain {
+    pub symbol: Option<Symbol>,
+    pub quantity: u32,
+    pub cost_basis: Money,
+    pub market_value: Money,
+    pub realized: Money,
+    /// Why the values could not all be determined, such as a missing exchange rate; the
+    /// row is shown as an error and the total has one too.
+    pub error: Option<String>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Show gains, returning the number of symbols whose quote could not be retrieved.
+pub fn show_gains<T: FetchPriceQuote + Sync>(portfolio: Portfolio, provider: T, format: OutputFormat, converter: Option<&Converter>) -> usize {
+    let (gains, failures) = portfolio_gains(&portfolio, &provider, converter);
+
+    let mut report = Report::new(&["Symbol", "Quantity", "Cost Basis", "Market Value", "Unrealized", "Unrealized %", "Realized"]);
+    for gain in &gains {
+        let label = Value::text(gain.symbol.as_ref().unwrap());
+        match &gain.error {
+            Some(error) => {
+                let mut row = vec![label, Value::Error(error.to_string())];
+                row.resize(report.titles.len(), Value::Empty);
+                report.add_row(row);
+            }
+            None => report.add_row(gain_row(gain, label)),
+        }
+    }
+    if let Some(total) = total_gain(&gains) {
+        let label = Value::text("Total");
+        let row = match &total.error {
+            Some(error) => {
+                let mut row = vec![label, Value::Error(error.to_string())];
+                row.resize(report.titles.len(), Value::Empty);
+                row
+            }
+            None => gain_row(&total, label),
+        };
+        report.add_row(row.into_iter().map(Value::bold).collect());
+    }
+    report.print(format);
+    print_rates(converter, format);
+    failures.len()
+}
+
+/// Calculate gains for each symbol held, or previously sold, in the portfolio; symbols
+/// are in the order they first appear in the portfolio. If a converter is provided all
+/// values are converted into its reporting currency at its current rates; a symbol with
+/// a value that can't be converted, or a sale in another currency than its purchase, has
+/// an error. A symbol whose quote could not be retrieved has an error and is also
+/// returned with its request error.
+pub fn portfolio_gains<T: FetchPriceQuote + Sync>(portfolio: &Portfolio, provider: &T, converter: Option<&Converter>) -> (Vec<Gain>, Vec<(Symbol, RequestError)>) {
+    let convert = |value: Money| match converter {
+        Some(converter) => match converter.convert(value) {
+            Ok(converted) => Ok(converted),
+            Err(err) => Err(err.to_string()),
+        },
+        None => Ok(value),
+    };
+    // each account's log is replayed separately, as its shares can't be sold from another.
+    let ledgers: Vec<ledger::Ledger> = std::iter::once(&portfolio.transactions)
+        .chain(portfolio.accounts.iter().map(|account| &account.transactions))
+        .map(|transactions| ledger::replay_with_actions(transactions, &portfolio.actions, None))
+        .collect();
+    let mut order: Vec<Symbol> = Vec::new();
+    let mut gains: HashMap<Symbol, Gain> = HashMap::new();
+
+    let items = ledger::current_items(portfolio);
+    let symbols: Vec<Symbol> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(symbol, _) => Some(symbol.to_string()),
+            _ => None,
+        })
+        .collect();
+    let (quotes, failures) = partition(fetch_quotes(provider, &symbols, DEFAULT_WORKERS));
+
+    for item in items {
+        if let Item::Price(symbol, holding) = item {
+            let cost = money_times(holding.purchase_price, holding.quantity);
+            let value = match quotes.get(&symbol) {
+                Some(quote) => Ok(money_times(quote.data.latest.price, holding.quantity)),
+                None => Err(match failures.iter().find(|(s, _)| *s == symbol) {
+                    Some((_, err)) => format!("{:?}", err),
+                    None => "no quote".to_string(),
+                }),
+            };
+            let (cost, value, error) = match (convert(cost), value.and_then(&convert)) {
+                (Ok(cost), Ok(value)) => (cost, value, None),
+                (Err(err), _) | (_, Err(err)) => (cost, Money::zero(cost.currency), Some(err)),
+            };
+            match gains.get_mut(&symbol) {
+                Some(gain) => {
+                    gain.quantity += holding.quantity;
+                    match (add_money(gain.cost_basis, cost), add_money(gain.market_value, value)) {
+                        (Some(cost), Some(value)) => {
+                            gain.cost_basis = cost;
+                            gain.market_value = value;
+                        }
+                        _ => gain.error = gain.error.take().or_else(|| Some(MIXED_CURRENCIES.to_string())),
+                    }
+                    gain.error = gain.error.take().or(error);
+                }
+                None => {
+                    order.push(symbol.to_string());
+                    gains.insert(symbol.to_string(), Gain {
+                        symbol: Some(symbol.to_string()),
+                        quantity: holding.quantity,
+                        cost_basis: cost,
+                        market_value: value,
+                        realized: Money::zero(cost.currency),
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    let realized = portfolio
+        .realized
+        .iter()
+        .map(|gain| {
+            let amount = gain.gain().ok_or_else(|| {
+                format!(
+                    "sold in {}, purchased in {}",
+                    gain.sale_price.currency.code(),
+                    gain.purchase_price.currency.code()
+                )
+            });
+            (gain.symbol.to_string(), gain.sale_price.currency, amount)
+        })
+        .chain(ledgers.into_iter().flat_map(|ledger| {
+            ledger.realized.into_iter().map(|(symbol, amount)| (symbol, amount.currency, Ok(amount)))
+        }));
+    for (symbol, currency, amount) in realized {
+        let (amount, error) = match amount.and_then(&convert) {
+            Ok(amount) => (amount, None),
+            Err(err) => (Money::zero(currency), Some(err)),
+        };
+        match gains.get_mut(&symbol) {
+            Some(gain) => {
+                match add_money(gain.realized, amount) {
+                    Some(realized) => gain.realized = realized,
+                    None => gain.error = gain.error.take().or_else(|| Some(MIXED_CURRENCIES.to_string())),
+                }
+                gain.error = gain.error.take().or(error);
+            }
+            None => {
+                order.push(symbol.to_string());
+                gains.insert(symbol.to_string(), Gain {
+                    symbol: Some(symbol.to_string()),
+                    quantity: 0,
+                    cost_basis: Money::zero(amount.currency),
+                    market_value: Money::zero(amount.currency),
+                    realized: amount,
+                    error,
+                });
+            }
+        }
+    }
+
+    (order.iter().filter_map(|symbol| gains.remove(symbol)).collect(), failures)
+}
+
+/// Sum a list of gains into a single portfolio-wide total. Gains with an error, or in
+/// more than one currency, can't be summed, so the total has an error instead; the
+/// error lists the symbols left out.
+pub fn total_gain(gains: &[Gain]) -> Option<Gain> {
+    match gains.first() {
+        Some(first) => {
+            let zero = Money::zero(first.cost_basis.currency);
+            let failed: Vec<&str> = gains
+                .iter()
+                .filter(|gain| gain.error.is_some())
+                .filter_map(|gain| gain.symbol.as_deref())
+                .collect();
+            let mixed = gains.iter().any(|gain| {
+                [gain.cost_basis, gain.market_value, gain.realized]
+                    .iter()
+                    .any(|amount| amount.currency != zero.currency)
+            });
+            let error = if !failed.is_empty() {
+                Some(format!("no total, {} could not be valued", failed.join(", ")))
+            } else if mixed {
+                Some(MIXED_CURRENCIES.to_string())
+            } else {
+                None
+            };
+            if error.is_some() {
+                return Some(Gain {
+                    symbol: None,
+                    quantity: 0,
+                    cost_basis: zero,
+                    market_value: zero,
+                    realized: zero,
+                    error,
+                });
+            }
+            Some(gains.iter().fold(
+                Gain {
+                    symbol: None,
+                    quantity: 0,
+                    cost_basis: zero,
+                    market_value: zero,
+                    realized: zero,
+                    error: None,
+                },
+                |total, gain| Gain {
+                    symbol: None,
+                    quantity: total.quantity + gain.quantity,
+                    cost_basis: total.cost_basis + gain.cost_basis,
+                    market_value: total.market_value + gain.market_value,
+                    realized: total.realized + gain.realized,
+                    error: None,
+                },
+            ))
+        }
+        None => None,
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Gain {
+    /// The market value less the cost basis, or why it can't be calculated when they are
+    /// in different currencies.
+    pub fn unrealized(&self) -> Result<Money, String> {
+        if self.market_value.currency == self.cost_basis.currency {
+            Ok(self.market_value - self.cost_basis)
+        } else {
+            Err(format!(
+                "{} quote for a {} holding, use --report-currency",
+                self.market_value.currency.code(),
+                self.cost_basis.currency.code()
+            ))
+        }
+    }
+
+    pub fn unrealized_percent(&self) -> Option<f64> {
+        match self.unrealized() {
+            Ok(unrealized) if self.cost_basis.minor_amount() != 0 =>
+                Some(unrealized.minor_amount() as f64 / self.cost_basis.minor_amount() as f64 * 100.0),
+            _ => None,
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn gain_row(gain: &Gain, label: Value) -> Vec<Value> {
+    vec![
+        label,
+        if gain.symbol.is_some() { Value::Integer(gain.quantity as i64) } else { Value::Empty },
+        Value::Money(gain.cost_basis),
+        Value::Money(gain.market_value),
+        match gain.unrealized() {
+            Ok(unrealized) => Value::Gain(unrealized),
+            Err(err) => Value::Error(err),
+        },
+        match gain.unrealized_percent() {
+            Some(percent) => Value::Percent(percent),
+            None => Value::Empty,
+        },
+        Value::Gain(gain.realized),
+    ]
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use steel_cent::currency::with_code;
+
+    fn gain(symbol: &str, code: &str, cost: i32, value: i32) -> Gain {
+        let currency = with_code(code).unwrap();
+        Gain {
+            symbol: Some(symbol.to_string()),
+            quantity: 1,
+            cost_basis: Money::of_major(currency, cost),
+            market_value: Money::of_major(currency, value),
+            realized: Money::zero(currency),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn totals_gains_in_one_currency() {
+        let total = total_gain(&[gain("AAPL", "USD", 100, 150), gain("MSFT", "USD", 200, 180)]).unwrap();
+        assert_eq!(total.error, None);
+        assert_eq!(total.quantity, 2);
+        assert_eq!(total.unrealized(), Ok(Money::of_major(with_code("USD").unwrap(), 30)));
+    }
+
+    #[test]
+    fn does_not_total_mixed_currencies() {
+        let total = total_gain(&[gain("AAPL", "USD", 100, 150), gain("SAP", "EUR", 200, 180)]).unwrap();
+        assert_eq!(total.error, Some(MIXED_CURRENCIES.to_string()));
+    }
+
+    #[test]
+    fn lists_gains_with_errors_on_the_total() {
+        let failed = |symbol| Gain {
+            error: Some("no quote".to_string()),
+            ..gain(symbol, "EUR", 200, 0)
+        };
+        let total = total_gain(&[gain("AAPL", "USD", 100, 150), failed("SAP"), failed("ASML")]).unwrap();
+        assert_eq!(total.error, Some("no total, SAP, ASML could not be valued".to_string()));
+        assert_eq!(total.quantity, 0);
+    }
+}
@@ -0,0 +1,631 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration};
+use fin_model::prelude::*;
+use serde::Deserialize;
+use steel_cent::currency::with_code;
+
+use crate::actions;
+use crate::display::{money_from_f64, money_times, sum_money, DATE_FMT};
+use crate::income::{DividendPerShare, FetchDividends};
+use crate::ledger;
+use crate::model::{Item, Portfolio, DEFAULT_CURRENCY};
+use crate::report::{OutputFormat, Report, Value};
+use crate::storage::{self, DataFileError};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum HistoryError {
+    PathError(String),
+    FileError(io::Error),
+    FormatError(String),
+    CurrencyError(String),
+}
+
+/// The spacing between the dates of a valuation time series.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+/// Provides historical daily closing prices.
+pub trait FetchPriceHistory {
+    /// The closing prices for `symbol` between `from` and `to` inclusive, in date order;
+    /// days without trading are simply absent.
+    fn daily_closes(&self, symbol: &Symbol, from: Date, to: Date) -> RequestResult<Vec<(Date, Money)>>;
+}
+
+/// Historical prices read from a local file; the file may be TOML, JSON or CSV, chosen
+/// by its extension, and each price has a date, symbol, close and optional currency. A
+/// price may also have the dividend per share with that date as its ex-dividend date.
+#[derive(Debug)]
+pub struct PriceFile {
+    file_name: String,
+    prices: HashMap<Symbol, Vec<(Date, Money)>>,
+    dividends: HashMap<Symbol, Vec<DividendPerShare>>,
+}
+
+/// The value of the portfolio at the close of a single date; `value` is `None` if the
+/// price of any symbol held (listed in `missing`) is not known on, or before, the date,
+/// or if the holdings are `mixed`, valued or purchased in more than one currency.
+#[derive(Clone, Debug)]
+pub struct Valuation {
+    pub date: Date,
+    pub value: Option<Money>,
+    pub cost_basis: Money,
+    pub missing: Vec<Symbol>,
+    pub mixed: bool,
+}
+
+/// A quantity of a symbol, and what it cost, held on some date.
+#[derive(Clone, Debug)]
+pub struct HeldPosition {
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub cost_basis: Money,
+}
+
+pub const INTERVAL_NAMES: [&str; 3] = ["day", "week", "month"];
+
+// ------------------------------------------------------------------------------------------------
+// Private Types (serialization format)
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize, Clone, Debug)]
+struct SerializedPrice {
+    pub date: String,
+    pub symbol: Symbol,
+    pub close: Option<f64>,
+    pub dividend: Option<f64>,
+    pub currency: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SerializedPriceFile {
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub prices: Vec<SerializedPrice>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn default_price_file_name() -> String {
+    shellexpand::tilde("~/.config/folio/prices.csv").to_string()
+}
+
+pub fn show_history<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date, interval: Interval, format: OutputFormat) {
+    let mut report = Report::new(&["Date", "Value", "Cost Basis", "Gain", "Gain %"]);
+    for valuation in valuations(portfolio, history, &period_dates(from, to, interval)) {
+        let mut row = vec![Value::Date(valuation.date)];
+        match (valuation.value, valuation.gain()) {
+            (Some(value), Some(gain)) => {
+                row.push(Value::Money(value));
+                row.push(Value::Money(valuation.cost_basis));
+                row.push(Value::Gain(gain));
+                row.push(match valuation.gain_percent() {
+                    Some(percent) => Value::Percent(percent),
+                    None => Value::Empty,
+                });
+            }
+            (Some(_), None) => {
+                row.push(Value::Error("holdings in more than one currency".to_string()));
+                row.push(Value::Money(valuation.cost_basis));
+                row.extend(vec![Value::Empty, Value::Empty]);
+            }
+            (None, _) if valuation.mixed => {
+                row.push(Value::Error("holdings in more than one currency".to_string()));
+                row.extend(vec![Value::Empty, Value::Empty, Value::Empty]);
+            }
+            (None, _) => {
+                row.push(Value::Error(format!("no price for {}", valuation.missing.join(", "))));
+                row.push(Value::Money(valuation.cost_basis));
+                row.extend(vec![Value::Empty, Value::Empty]);
+            }
+        }
+        report.add_row(row);
+    }
+    report.print(format);
+}
+
+/// The dates from `from` to `to`, spaced by `interval`; `to` is always the last date
+/// even if it falls part way through an interval.
+pub fn period_dates(from: Date, to: Date, interval: Interval) -> Vec<Date> {
+    let mut dates: Vec<Date> = Vec::new();
+    let mut date = from;
+    let mut step = 0;
+    while date < to {
+        dates.push(date);
+        step += 1;
+        date = match interval {
+            Interval::Day => from + Duration::days(step),
+            Interval::Week => from + Duration::weeks(step),
+            Interval::Month => add_months(from, step as u32),
+        };
+    }
+    if from <= to {
+        dates.push(to);
+    }
+    dates
+}
+
+/// The earliest purchase, or transaction, date in the portfolio.
+pub fn first_date(portfolio: &Portfolio) -> Option<Date> {
+    portfolio
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(_, holding) => holding.purchase_date,
+            _ => None,
+        })
+        .chain(portfolio.lots.iter().filter_map(|lot| lot.purchase_date))
+        .chain(portfolio.realized.iter().filter_map(|gain| gain.purchase_date))
+        .chain(ledger::all_transactions(portfolio).iter().map(|t| t.date()))
+        .min()
+}
+
+/// The positions held at the close of `date`. Holdings and lots count from their purchase
+/// date, or always if they have none; sold lots count until their sale date; positions
+/// from the transaction logs are replayed up to, and including, `date`. Corporate actions
+/// up to `date` are applied, to lots as they were on `date`.
+pub fn held_on(portfolio: &Portfolio, date: Date) -> Vec<HeldPosition> {
+    let adjusted = actions::adjusted_on(portfolio, date);
+    let mut held: Vec<HeldPosition> = Vec::new();
+    let mut add = |symbol: &Symbol, quantity: u32, cost: Money| {
+        match held.iter_mut().find(|h| h.symbol == *symbol && h.cost_basis.currency == cost.currency) {
+            Some(position) => {
+                position.quantity += quantity;
+                position.cost_basis = position.cost_basis + cost;
+            }
+            None => held.push(HeldPosition {
+                symbol: symbol.to_string(),
+                quantity,
+                cost_basis: cost,
+            }),
+        }
+    };
+    let bought = |purchase_date: Option<Date>| match purchase_date {
+        Some(purchase_date) => purchase_date <= date,
+        None => true,
+    };
+
+    for item in &adjusted.items {
+        if let Item::Price(symbol, holding) = item {
+            if bought(holding.purchase_date) {
+                add(symbol, holding.quantity, money_times(holding.purchase_price, holding.quantity));
+            }
+        }
+    }
+    for lot in &adjusted.lots {
+        if bought(lot.purchase_date) {
+            add(&lot.symbol, lot.quantity, money_times(lot.purchase_price, lot.quantity));
+        }
+    }
+    for gain in &portfolio.realized {
+        if bought(gain.purchase_date) && gain.sale_date > date {
+            add(&gain.symbol, gain.quantity, money_times(gain.purchase_price, gain.quantity));
+        }
+    }
+    let ledgers = std::iter::once(&portfolio.transactions)
+        .chain(portfolio.accounts.iter().map(|account| &account.transactions))
+        .map(|transactions| ledger::replay_with_actions(transactions, &portfolio.actions, Some(date)));
+    for replayed in ledgers {
+        // the replayed cost basis is exact, the average cost per share may be rounded.
+        for position in replayed.positions {
+            add(&position.symbol, position.quantity, position.cost_basis);
+        }
+    }
+    held
+}
+
+/// Value the portfolio at the close of each of `dates`, using the latest known price on,
+/// or before, each date.
+pub fn valuations<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, dates: &[Date]) -> Vec<Valuation> {
+    let (from, to) = match (dates.first(), dates.last()) {
+        (Some(from), Some(to)) => (*from, *to),
+        _ => return Vec::new(),
+    };
+    // prices before the first date are needed in case it is not a trading day.
+    let mut closes: HashMap<Symbol, Vec<(Date, Money)>> = HashMap::new();
+    for date in dates {
+        for position in held_on(portfolio, *date) {
+            if !closes.contains_key(&position.symbol) {
+                let prices = match history.daily_closes(&position.symbol, from - Duration::days(7), to) {
+                    Ok(prices) => prices,
+                    Err(err) => {
+                        warn!("Error retrieving prices for {}: {:?}", position.symbol, err);
+                        Vec::new()
+                    }
+                };
+                closes.insert(position.symbol.to_string(), prices);
+            }
+        }
+    }
+
+    dates
+        .iter()
+        .map(|date| {
+            let held = held_on(portfolio, *date);
+            let mut values: Vec<Money> = Vec::new();
+            let mut missing: Vec<Symbol> = Vec::new();
+            for position in &held {
+                match closes.get(&position.symbol).and_then(|prices| close_on(prices, *date)) {
+                    Some(price) => values.push(money_times(price, position.quantity)),
+                    None => missing.push(position.symbol.to_string()),
+                }
+            }
+            let cost_basis = sum_money(held.iter().map(|position| position.cost_basis));
+            let value = sum_money(values);
+            let mixed = cost_basis.is_err() || value.is_err();
+            let cost_basis = cost_basis
+                .unwrap_or(None)
+                .unwrap_or_else(|| Money::zero(portfolio.currency()));
+            Valuation {
+                date: *date,
+                value: match value {
+                    Ok(value) if missing.is_empty() && !mixed => Some(value.unwrap_or_else(|| Money::zero(cost_basis.currency))),
+                    _ => None,
+                },
+                cost_basis,
+                missing,
+                mixed,
+            }
+        })
+        .collect()
+}
+
+/// The latest close of `symbol` on, or within a week before, `date`.
+pub fn price_on<T: FetchPriceHistory>(history: &T, symbol: &Symbol, date: Date) -> Option<Money> {
+    match history.daily_closes(symbol, date - Duration::days(7), date) {
+        Ok(closes) => close_on(&closes, date),
+        Err(err) => {
+            warn!("Error retrieving prices for {}: {:?}", symbol, err);
+            None
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" | "daily" => Ok(Interval::Day),
+            "week" | "weekly" => Ok(Interval::Week),
+            "month" | "monthly" => Ok(Interval::Month),
+            other => Err(format!("unknown interval {}, expecting day, week, or month", other)),
+        }
+    }
+}
+
+impl Valuation {
+    /// The gain over cost basis, `None` if the value is unknown or the value and cost
+    /// basis are in different currencies.
+    pub fn gain(&self) -> Option<Money> {
+        match self.value {
+            Some(value) if value.currency == self.cost_basis.currency => Some(value - self.cost_basis),
+            _ => None,
+        }
+    }
+
+    pub fn gain_percent(&self) -> Option<f64> {
+        match self.gain() {
+            Some(gain) if self.cost_basis.minor_amount() != 0 =>
+                Some(gain.minor_amount() as f64 / self.cost_basis.minor_amount() as f64 * 100.0),
+            _ => None,
+        }
+    }
+}
+
+impl PriceFile {
+    pub fn open(file_name: &str) -> Result<Self, HistoryError> {
+        info!("history::open {}", file_name);
+        let serialized = match storage::read_data_file(file_name, |prices| SerializedPriceFile { currency: None, prices }) {
+            Ok(serialized) => serialized,
+            Err(DataFileError::PathError(path)) => return Err(HistoryError::PathError(path)),
+            Err(DataFileError::FileError(err)) => return Err(HistoryError::FileError(err)),
+            Err(DataFileError::FormatError(err)) => return Err(HistoryError::FormatError(err)),
+        };
+
+        let mut prices: HashMap<Symbol, Vec<(Date, Money)>> = HashMap::new();
+        let mut dividends: HashMap<Symbol, Vec<DividendPerShare>> = HashMap::new();
+        for price in serialized.prices {
+            let code = price
+                .currency
+                .clone()
+                .or(serialized.currency.clone())
+                .unwrap_or(DEFAULT_CURRENCY.to_string());
+            let currency = match with_code(&code) {
+                Some(currency) => currency,
+                None => return Err(HistoryError::CurrencyError(code)),
+            };
+            let date = match Date::parse_from_str(&price.date, DATE_FMT) {
+                Ok(date) => date,
+                Err(_) => return Err(HistoryError::FormatError(format!("could not parse date {}", price.date))),
+            };
+            if let Some(close) = price.close {
+                prices
+                    .entry(price.symbol.to_uppercase())
+                    .or_default()
+                    .push((date, money_from_f64(currency, close)));
+            }
+            if let Some(amount) = price.dividend {
+                dividends
+                    .entry(price.symbol.to_uppercase())
+                    .or_default()
+                    .push(DividendPerShare { ex_date: date, amount, currency });
+            }
+        }
+        for closes in prices.values_mut() {
+            closes.sort_by_key(|(date, _)| *date);
+        }
+        for paid in dividends.values_mut() {
+            paid.sort_by_key(|dividend| dividend.ex_date);
+        }
+
+        Ok(PriceFile {
+            file_name: file_name.to_string(),
+            prices,
+            dividends,
+        })
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+}
+
+impl FetchPriceHistory for PriceFile {
+    fn daily_closes(&self, symbol: &Symbol, from: Date, to: Date) -> RequestResult<Vec<(Date, Money)>> {
+        match self.prices.get(&symbol.to_uppercase()) {
+            Some(closes) => Ok(closes
+                .iter()
+                .filter(|(date, _)| *date >= from && *date <= to)
+                .cloned()
+                .collect()),
+            None => Err(RequestError::ConfigurationError(
+                format!("no prices for {} in {}", symbol, self.file_name))),
+        }
+    }
+}
+
+impl FetchDividends for PriceFile {
+    fn dividends_per_share(&self, symbol: &Symbol, from: Date, to: Date) -> RequestResult<Vec<DividendPerShare>> {
+        match self.dividends.get(&symbol.to_uppercase()) {
+            Some(paid) => Ok(paid
+                .iter()
+                .filter(|dividend| dividend.ex_date >= from && dividend.ex_date <= to)
+                .cloned()
+                .collect()),
+            None => Err(RequestError::ConfigurationError(
+                format!("no dividends for {} in {}", symbol, self.file_name))),
+        }
+    }
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::PathError(path) => write!(f, "could not open {}", path),
+            HistoryError::FileError(err) => write!(f, "{}", err),
+            HistoryError::FormatError(err) => write!(f, "{}", err),
+            HistoryError::CurrencyError(code) => write!(f, "unknown currency {}", code),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The latest close on, or before, `date`; `closes` is in date order.
+fn close_on(closes: &[(Date, Money)], date: Date) -> Option<Money> {
+    closes
+        .iter()
+        .take_while(|(d, _)| *d <= date)
+        .last()
+        .map(|(_, price)| *price)
+}
+
+/// Add the same number of months, clamping the day to the end of shorter months.
+fn add_months(date: Date, months: u32) -> Date {
+    let months = date.month0() + months;
+    let year = date.year() + (months / 12) as i32;
+    let month = months % 12 + 1;
+    let mut day = date.day();
+    loop {
+        match Date::from_ymd_opt(year, month, day) {
+            Some(date) => return date,
+            None => day -= 1,
+        }
+    }
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::model::{Classification, CorporateAction, Holding, Lot, Ratio, Trade};
+
+    /// Closes for AAPL only, on the dates given.
+    struct FixedHistory {
+        closes: Vec<(Date, Money)>,
+    }
+
+    impl FetchPriceHistory for FixedHistory {
+        fn daily_closes(&self, symbol: &Symbol, from: Date, to: Date) -> RequestResult<Vec<(Date, Money)>> {
+            if symbol == "AAPL" {
+                Ok(self.closes.iter().filter(|(date, _)| *date >= from && *date <= to).cloned().collect())
+            } else {
+                Err(RequestError::ConfigurationError(format!("no prices for {}", symbol)))
+            }
+        }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn dollars(amount: i32) -> Money {
+        Money::of_major(with_code(DEFAULT_CURRENCY).unwrap(), amount)
+    }
+
+    fn holding(symbol: &str, quantity: u32, price: i32, purchase_date: Date) -> Item {
+        Item::Price(
+            symbol.to_string(),
+            Holding {
+                quantity,
+                purchase_price: dollars(price),
+                purchase_date: Some(purchase_date),
+                classification: Classification::default(),
+            },
+        )
+    }
+
+    #[test]
+    fn clamps_to_the_end_of_the_month() {
+        assert_eq!(add_months(date(2020, 1, 31), 1), date(2020, 2, 29));
+        assert_eq!(add_months(date(2019, 1, 31), 1), date(2019, 2, 28));
+        assert_eq!(add_months(date(2020, 1, 31), 3), date(2020, 4, 30));
+        assert_eq!(add_months(date(2020, 11, 30), 3), date(2021, 2, 28));
+        assert_eq!(add_months(date(2020, 5, 15), 12), date(2021, 5, 15));
+    }
+
+    #[test]
+    fn spaces_dates_by_interval() {
+        assert_eq!(
+            period_dates(date(2020, 1, 1), date(2020, 1, 3), Interval::Day),
+            vec![date(2020, 1, 1), date(2020, 1, 2), date(2020, 1, 3)]
+        );
+        assert_eq!(
+            period_dates(date(2020, 1, 1), date(2020, 1, 20), Interval::Week),
+            vec![date(2020, 1, 1), date(2020, 1, 8), date(2020, 1, 15), date(2020, 1, 20)]
+        );
+        // each step counts from the first date, so the 31st isn't lost after February.
+        assert_eq!(
+            period_dates(date(2020, 1, 31), date(2020, 4, 30), Interval::Month),
+            vec![date(2020, 1, 31), date(2020, 2, 29), date(2020, 3, 31), date(2020, 4, 30)]
+        );
+        assert_eq!(period_dates(date(2020, 1, 1), date(2020, 1, 1), Interval::Month), vec![date(2020, 1, 1)]);
+        assert!(period_dates(date(2020, 1, 2), date(2020, 1, 1), Interval::Day).is_empty());
+    }
+
+    #[test]
+    fn values_holdings_at_the_latest_close() {
+        let portfolio = Portfolio {
+            items: vec![holding("AAPL", 10, 100, date(2020, 1, 2))],
+            ..Default::default()
+        };
+        let history = FixedHistory {
+            closes: vec![(date(2019, 12, 31), dollars(90)), (date(2020, 1, 3), dollars(110))],
+        };
+        let dates = [date(2020, 1, 1), date(2020, 1, 3), date(2020, 1, 5)];
+
+        let valued = valuations(&portfolio, &history, &dates);
+        assert_eq!(valued.len(), 3);
+        // not yet bought.
+        assert_eq!(valued[0].value, Some(dollars(0)));
+        assert_eq!(valued[0].cost_basis, dollars(0));
+        assert_eq!(valued[1].value, Some(dollars(1100)));
+        assert_eq!(valued[1].cost_basis, dollars(1000));
+        assert_eq!(valued[1].gain(), Some(dollars(100)));
+        // a weekend, the close from the 3rd is used.
+        assert_eq!(valued[2].value, Some(dollars(1100)));
+    }
+
+    #[test]
+    fn values_lots_across_a_split() {
+        // a lot sold from after the 4:1 split is stored adjusted for it.
+        let lot = Lot {
+            id: 1,
+            symbol: "AAPL".to_string(),
+            quantity: 40,
+            purchase_price: dollars(25),
+            purchase_date: Some(date(2020, 1, 2)),
+            classification: Classification::default(),
+            adjusted: Some(date(2020, 8, 31)),
+            original: Some(Trade {
+                quantity: 10,
+                price: dollars(100),
+            }),
+        };
+        let portfolio = Portfolio {
+            lots: vec![lot],
+            actions: vec![CorporateAction::Split(date(2020, 8, 31), "AAPL".to_string(), Ratio { to: 4, from: 1 })],
+            ..Default::default()
+        };
+        let history = FixedHistory {
+            closes: vec![(date(2020, 8, 28), dollars(500)), (date(2020, 8, 31), dollars(130))],
+        };
+
+        let valued = valuations(&portfolio, &history, &[date(2020, 8, 28), date(2020, 8, 31)]);
+        assert_eq!(valued[0].value, Some(dollars(5000)));
+        assert_eq!(valued[0].cost_basis, dollars(1000));
+        assert_eq!(valued[1].value, Some(dollars(5200)));
+        assert_eq!(valued[1].cost_basis, dollars(1000));
+
+        let held = held_on(&portfolio, date(2020, 8, 28));
+        assert_eq!(held.len(), 1);
+        assert_eq!(held[0].quantity, 10);
+    }
+
+    #[test]
+    fn reports_symbols_without_a_price() {
+        let portfolio = Portfolio {
+            items: vec![holding("AAPL", 10, 100, date(2020, 1, 2)), holding("MSFT", 5, 150, date(2020, 1, 2))],
+            ..Default::default()
+        };
+        let history = FixedHistory {
+            closes: vec![(date(2020, 1, 3), dollars(110))],
+        };
+
+        let valued = valuations(&portfolio, &history, &[date(2020, 1, 3)]);
+        assert_eq!(valued[0].value, None);
+        assert_eq!(valued[0].missing, vec!["MSFT".to_string()]);
+        assert_eq!(valued[0].cost_basis, dollars(1750));
+        assert!(!valued[0].mixed);
+    }
+
+    #[test]
+    fn has_no_value_in_more_than_one_currency() {
+        let mut portfolio = Portfolio {
+            items: vec![holding("AAPL", 10, 100, date(2020, 1, 2))],
+            ..Default::default()
+        };
+        portfolio.items.push(Item::Price(
+            "AAPL".to_string(),
+            Holding {
+                quantity: 5,
+                purchase_price: Money::of_major(with_code("EUR").unwrap(), 90),
+                purchase_date: Some(date(2020, 1, 2)),
+                classification: Classification::default(),
+            },
+        ));
+        let history = FixedHistory {
+            closes: vec![(date(2020, 1, 3), dollars(110))],
+        };
+
+        let valued = valuations(&portfolio, &history, &[date(2020, 1, 3)]);
+        assert_eq!(valued[0].value, None);
+        assert!(valued[0].missing.is_empty());
+        assert!(valued[0].mixed);
+    }
+}
@@ -1,38 +1,308 @@
-use num_format::SystemLocale;
-use prettytable::Table;
+use std::fmt;
 
+use chrono::Local;
+use fin_model::prelude::*;
+use steel_cent::currency::Currency;
+
+use crate::actions;
+use crate::cash::{cash_balances, cash_symbol, pay_for_shares};
 use crate::display::*;
+use crate::ledger;
 use crate::model::*;
+use crate::report::{OutputFormat, Report, Value};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The changes `folio update` makes to a holding; anything not given is left unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct HoldingUpdate {
+    /// Which of the symbol's holdings to change, counting from 1 in file order, required
+    /// when the symbol is held more than once.
+    pub entry: Option<usize>,
+    pub quantity: Option<u32>,
+    /// Shares bought in addition to those held, at `purchase_price` on `purchase_date`
+    /// if given; they are a new holding, so that each purchase keeps its own date, unless
+    /// bought on the same date as the holding at a price that averages exactly with it.
+    pub add_quantity: Option<u32>,
+    pub purchase_price: Option<Money>,
+    pub purchase_date: Option<Date>,
+    pub watch_only: bool,
+    pub tags: Option<Vec<String>>,
+    /// The account of a watched symbol that becomes a holding.
+    pub account: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    UnknownSymbol(Symbol),
+    /// The symbol is held more than once, and the number of holdings.
+    Ambiguous(Symbol, usize),
+    /// The entry chosen, and the number of holdings of the symbol.
+    UnknownEntry(Symbol, usize, usize),
+    /// Shares added at a price in a different currency to the holding's.
+    CurrencyMismatch(Symbol),
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
 
-pub fn show_holdings(portfolio : Portfolio) {
-    let locale: SystemLocale = SystemLocale::default().unwrap();
-    let mut table = Table::new();
-    table.set_titles(row!["Symbol", "Purchase Date", "Purchase Price", "Quantity"]);
-    for item in &portfolio.items {
-        match item {
-            Item::Price(s, h) => {
-                table.add_row(row![
-                    s,
-                    default_cell(),
-                    price_cell(h.purchase_price),
-                    number_cell(h.quantity as i64, &locale),
-                ]);
-                ()
-            },
-            _ => (),
+pub fn show_holdings(portfolio : Portfolio, format: OutputFormat) {
+    // quantities and prices are shown after any corporate actions.
+    let portfolio = actions::adjusted(&portfolio, Local::now().date_naive());
+    let mut report = Report::new(&["Symbol", "Lot", "Purchase Date", "Purchase Price", "Quantity", "Cost"]);
+    let mut positions = ledger::replay_with_actions(&portfolio.transactions, &portfolio.actions, None).positions;
+    for account in &portfolio.accounts {
+        positions.extend(ledger::replay_with_actions(&account.transactions, &portfolio.actions, None).positions);
+    }
+    let items: Vec<Item> = portfolio.items
+        .iter()
+        .cloned()
+        .chain(positions.iter().map(ledger::position_item))
+        .collect();
+    for item in &items {
+        if let Item::Price(s, h) = item {
+            report.add_row(vec![
+                Value::text(s),
+                Value::Empty,
+                Value::date_or(h.purchase_date),
+                Value::Money(h.purchase_price),
+                Value::Integer(h.quantity as i64),
+                Value::Money(money_times(h.purchase_price, h.quantity)),
+            ]);
         }
     }
-    table.printstd();
+    for lot in &portfolio.lots {
+        report.add_row(vec![
+            Value::text(&lot.symbol),
+            Value::Integer(lot.id as i64),
+            Value::date_or(lot.purchase_date),
+            Value::Money(lot.purchase_price),
+            Value::Integer(lot.quantity as i64),
+            Value::Money(money_times(lot.purchase_price, lot.quantity)),
+        ]);
+    }
+    // cash balances only have a value.
+    for cash in cash_balances(&portfolio) {
+        report.add_row(vec![
+            Value::Text(cash_symbol(&cash.balance)),
+            Value::Empty,
+            Value::Empty,
+            Value::Empty,
+            Value::Empty,
+            Value::Money(cash.balance),
+        ]);
+    }
 
     let watching: Vec<String> = portfolio.items
         .iter()
-        .filter(|item| match item { Item::Watch(_) => true, _ => false } )
-        .map(|item| match item {
-            Item::Watch(s) => s.to_string(),
-            _ => "#error#".to_string()
-        })
+        .filter(|item| matches!(item, Item::Watch(_)))
+        .map(item_symbol)
         .collect();
 
-    println!("Also watching: {}", watching.join(", "));
+    if format == OutputFormat::Table {
+        report.print(format);
+        println!("Also watching: {}", watching.join(", "));
+    } else {
+        // machine-readable formats include watched symbols as rows without holding data.
+        for symbol in watching {
+            report.add_row(vec![Value::Text(symbol), Value::Empty, Value::Empty, Value::Empty, Value::Empty, Value::Empty]);
+        }
+        report.print(format);
+    }
 }
 
+/// Add a new holding of `symbol`, paid for from the cash balance of its account if it
+/// tracks cash.
+pub fn add_holding(portfolio: &Portfolio, symbol: &Symbol, holding: Holding) -> Portfolio {
+    let paid = money_times(holding.purchase_price, holding.quantity);
+    let account = holding.classification.account.clone();
+    let mut new_portfolio = Portfolio {
+        items: portfolio
+            .items
+            .iter()
+            .cloned()
+            .chain(vec![Item::Price(symbol.to_string(), holding)])
+            .collect(),
+        ..portfolio.clone()
+    };
+    pay_for_shares(&mut new_portfolio, paid, &account);
+    new_portfolio
+}
+
+/// Change the one item for `symbol`, or the `entry` chosen when it has more than one, in
+/// place, keeping its position in the portfolio and any fields not changed. A watched
+/// symbol becomes a holding when given a quantity or price, with a zero price in
+/// `currency` if none is given; a holding made `watch_only` loses its purchase and
+/// classification fields, as watched symbols have none.
+///
+/// Shares added to a symbol already held are instead a new holding, following the
+/// holding changed and with its classification, dated today unless a purchase date
+/// is given; added on the same date as that holding they are merged into it at the
+/// average price. Added shares are paid for from the cash balance of their account, if it
+/// tracks cash.
+pub fn update_holding(
+    portfolio: &Portfolio,
+    symbol: &Symbol,
+    update: &HoldingUpdate,
+    currency: Currency,
+) -> Result<Portfolio, UpdateError> {
+    let matching: Vec<usize> = portfolio
+        .items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item_symbol(item).to_uppercase() == symbol.to_uppercase())
+        .map(|(index, _)| index)
+        .collect();
+    let index = match (update.entry, matching.len()) {
+        (_, 0) => return Err(UpdateError::UnknownSymbol(symbol.to_string())),
+        (Some(entry), found) => match entry.checked_sub(1).and_then(|entry| matching.get(entry)) {
+            Some(index) => *index,
+            None => return Err(UpdateError::UnknownEntry(symbol.to_string(), entry, found)),
+        },
+        (None, 1) => matching[0],
+        (None, found) => return Err(UpdateError::Ambiguous(symbol.to_string(), found)),
+    };
+    if let (Some(added), Item::Price(s, holding)) = (update.add_quantity, &portfolio.items[index]) {
+        if holding.quantity > 0 && !update.watch_only {
+            return add_shares(portfolio, index, s, holding, added, update);
+        }
+    }
+
+    let updated = match &portfolio.items[index] {
+        _ if update.watch_only => Item::Watch(item_symbol(&portfolio.items[index])),
+        Item::Watch(s) => {
+            let holding = Holding {
+                quantity: 0,
+                purchase_price: Money::zero(currency),
+                purchase_date: None,
+                classification: Classification {
+                    account: update.account.clone(),
+                    ..Default::default()
+                },
+            };
+            if update.quantity.is_none() && update.add_quantity.is_none() && update.purchase_price.is_none() {
+                // only tags, or a date, can't make a watched symbol a holding.
+                Item::Watch(s.to_string())
+            } else {
+                Item::Price(s.to_string(), updated_holding(&holding, update))
+            }
+        }
+        Item::Price(s, holding) => Item::Price(s.to_string(), updated_holding(holding, update)),
+    };
+    let paid = match (update.add_quantity, &updated) {
+        (Some(added), Item::Price(_, holding)) => Some((money_times(holding.purchase_price, added), holding.classification.account.clone())),
+        _ => None,
+    };
+    let mut items = portfolio.items.clone();
+    items[index] = updated;
+    let mut new_portfolio = Portfolio { items, ..portfolio.clone() };
+    if let Some((paid, account)) = paid {
+        pay_for_shares(&mut new_portfolio, paid, &account);
+    }
+    Ok(new_portfolio)
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateError::UnknownSymbol(symbol) => write!(f, "No holding, or watched symbol, {} to update", symbol),
+            UpdateError::Ambiguous(symbol, found) => write!(
+                f,
+                "{} is held {} times, choose one with --entry 1 to {}",
+                symbol, found, found
+            ),
+            UpdateError::UnknownEntry(symbol, entry, found) => write!(
+                f,
+                "{} is held {} times, there is no entry {}",
+                symbol, found, entry
+            ),
+            UpdateError::CurrencyMismatch(symbol) => write!(
+                f,
+                "The purchase price of {} is in a different currency to the shares added",
+                symbol
+            ),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Add `added` shares of the holding at `index`, as a new holding unless they were bought
+/// on the same date and the average price of all the shares is a whole number of minor
+/// units, so merging them keeps their exact cost; any new tags apply to both.
+fn add_shares(
+    portfolio: &Portfolio,
+    index: usize,
+    symbol: &Symbol,
+    holding: &Holding,
+    added: u32,
+    update: &HoldingUpdate,
+) -> Result<Portfolio, UpdateError> {
+    let price = update.purchase_price.unwrap_or(holding.purchase_price);
+    if price.currency != holding.purchase_price.currency {
+        return Err(UpdateError::CurrencyMismatch(symbol.to_string()));
+    }
+    let date = update.purchase_date.unwrap_or_else(|| Local::now().date_naive());
+    let classification = Classification {
+        tags: update.tags.clone().unwrap_or_else(|| holding.classification.tags.clone()),
+        ..holding.classification.clone()
+    };
+    let cost = holding.purchase_price.minor_amount() as i64 * i64::from(holding.quantity)
+        + price.minor_amount() as i64 * i64::from(added);
+    let merged = match holding.quantity.checked_add(added) {
+        Some(quantity) if quantity > 0 && holding.purchase_date == Some(date) && cost % i64::from(quantity) == 0 => Some(quantity),
+        _ => None,
+    };
+    let mut items = portfolio.items.clone();
+    if let Some(quantity) = merged {
+        items[index] = Item::Price(symbol.to_string(), Holding {
+            quantity,
+            purchase_price: money_from_minor(price.currency, cost / i64::from(quantity)),
+            purchase_date: Some(date),
+            classification,
+        });
+    } else {
+        items[index] = Item::Price(symbol.to_string(), Holding {
+            classification: classification.clone(),
+            ..holding.clone()
+        });
+        items.insert(index + 1, Item::Price(symbol.to_string(), Holding {
+            quantity: added,
+            purchase_price: price,
+            purchase_date: Some(date),
+            classification,
+        }));
+    }
+    let mut new_portfolio = Portfolio { items, ..portfolio.clone() };
+    pay_for_shares(&mut new_portfolio, money_times(price, added), &holding.classification.account);
+    Ok(new_portfolio)
+}
+
+fn updated_holding(holding: &Holding, update: &HoldingUpdate) -> Holding {
+    let (quantity, purchase_price) = match (update.add_quantity, update.purchase_price) {
+        // shares added to a watched symbol, or an empty holding, are all it holds.
+        (Some(added), price) => (holding.quantity + added, price.unwrap_or(holding.purchase_price)),
+        (None, price) => (
+            update.quantity.unwrap_or(holding.quantity),
+            price.unwrap_or(holding.purchase_price),
+        ),
+    };
+    Holding {
+        quantity,
+        purchase_price,
+        purchase_date: update.purchase_date.or(holding.purchase_date),
+        classification: Classification {
+            tags: update.tags.clone().unwrap_or_else(|| holding.classification.tags.clone()),
+            ..holding.classification.clone()
+        },
+    }
+}
@@ -0,0 +1,226 @@
+use fin_model::prelude::*;
+use fin_model::provider::Provider;
+use fin_model::quote::{FetchPriceQuote, LatestPrice, PriceRange, Quote, QuoteData};
+use serde::Deserialize;
+use steel_cent::currency::with_code;
+
+use crate::display::money_from_f64;
+use crate::model::DEFAULT_CURRENCY;
+use crate::providers::IEX_PROVIDER_NAME;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// A quote provider for the IEX Cloud API, with its token and endpoint given explicitly
+/// rather than read from the environment; an endpoint, such as a local proxy, may need
+/// no token.
+#[derive(Clone, Debug)]
+pub struct IexClient {
+    token: Option<String>,
+    endpoint: String,
+}
+
+/// The IEX Cloud API used when the configuration names no `endpoint`.
+pub const DEFAULT_IEX_ENDPOINT: &str = "https://cloud.iexapis.com/stable";
+
+// ------------------------------------------------------------------------------------------------
+// Private Types (serialization format)
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IexQuote {
+    pub symbol: Symbol,
+    pub currency: Option<String>,
+    pub latest_price: f64,
+    pub change: Option<f64>,
+    /// A fraction, 0.01 is one percent.
+    pub change_percent: Option<f64>,
+    pub open: Option<f64>,
+    pub low: Option<f64>,
+    pub high: Option<f64>,
+    pub close: Option<f64>,
+    pub latest_volume: Option<u64>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl IexClient {
+    /// A client for `endpoint`, `DEFAULT_IEX_ENDPOINT` if `None`, using `token` if given.
+    pub fn new(token: Option<&str>, endpoint: Option<&str>) -> Self {
+        IexClient {
+            token: token.map(|token| token.to_string()),
+            endpoint: endpoint.unwrap_or(DEFAULT_IEX_ENDPOINT).trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+}
+
+impl Provider for IexClient {
+    fn name(&self) -> &str {
+        IEX_PROVIDER_NAME
+    }
+}
+
+impl FetchPriceQuote for IexClient {
+    fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+        let url = format!("{}/stock/{}/quote", self.endpoint, for_symbol.to_lowercase());
+        debug!("IexClient::real_time {}", url);
+        let request = match &self.token {
+            Some(token) => ureq::get(&url).query("token", token),
+            None => ureq::get(&url),
+        };
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(err) => {
+                warn!("Request for {} failed: {}", for_symbol, err);
+                return Err(RequestError::CommunicationError);
+            }
+        };
+        match response.into_json::<IexQuote>() {
+            Ok(quote) => quote.quote(),
+            Err(err) => {
+                warn!("Could not read the quote for {}: {}", for_symbol, err);
+                Err(RequestError::BadResponseError)
+            }
+        }
+    }
+}
+
+impl IexQuote {
+    fn quote(&self) -> RequestResult<Quote> {
+        let code = self.currency.clone().unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+        let currency = match with_code(&code) {
+            Some(currency) => currency,
+            None => {
+                warn!("Unknown currency {} for {}", code, self.symbol);
+                return Err(RequestError::BadResponseError);
+            }
+        };
+        let money = |value: f64| money_from_f64(currency, value);
+        Ok(Quote {
+            symbol: self.symbol.to_string(),
+            data: QuoteData {
+                latest: LatestPrice {
+                    price: money(self.latest_price),
+                    change: self.change.map(money),
+                    percentage: self.change_percent.map(|fraction| fraction * 100.0),
+                },
+                range: match (self.open, self.low, self.high, self.close) {
+                    (Some(open), Some(low), Some(high), Some(close)) => Some(PriceRange {
+                        open: money(open),
+                        low: money(low),
+                        high: money(high),
+                        close: money(close),
+                        volume: self.latest_volume,
+                    }),
+                    _ => None,
+                },
+            },
+        })
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::model::ProviderConfig;
+    use crate::providers::Registry;
+
+    #[test]
+    fn quotes_from_a_configured_endpoint() {
+        let (endpoint, requested) = stand_in(r#"{"symbol":"AAPL","latestPrice":123.45,"change":-1.5,"changePercent":-0.012}"#);
+        let client = IexClient::new(Some("pk_test"), Some(&format!("{}/", endpoint)));
+        assert_eq!(client.endpoint(), endpoint);
+
+        let quote = client.real_time("AAPL".to_string()).unwrap();
+        let usd = with_code("USD").unwrap();
+        assert_eq!(quote.data.latest.price, Money::of_major_minor(usd, 123, 45));
+        assert_eq!(quote.data.latest.change, Some(Money::of_major_minor(usd, -1, -50)));
+        assert_eq!(requested.join().unwrap(), "GET /stock/aapl/quote?token=pk_test HTTP/1.1");
+    }
+
+    #[test]
+    fn calls_an_endpoint_without_a_token() {
+        let (endpoint, requested) = stand_in(r#"{"symbol":"VTI","latestPrice":200.0}"#);
+        let client = IexClient::new(None, Some(&endpoint));
+        assert!(client.real_time("VTI".to_string()).is_ok());
+        assert_eq!(requested.join().unwrap(), "GET /stock/vti/quote HTTP/1.1");
+    }
+
+    #[test]
+    fn reports_a_response_that_is_not_a_quote() {
+        let (endpoint, requested) = stand_in("not a quote");
+        let client = IexClient::new(None, Some(&endpoint));
+        match client.real_time("VTI".to_string()) {
+            Err(RequestError::BadResponseError) => (),
+            other => panic!("expected a bad response, not {:?}", other.map(|q| q.symbol)),
+        }
+        requested.join().unwrap();
+    }
+
+    #[test]
+    fn keeps_the_token_of_each_configured_provider() {
+        let (default_endpoint, default_requested) = stand_in(r#"{"symbol":"AAPL","latestPrice":1.0}"#);
+        let (fund_endpoint, fund_requested) = stand_in(r#"{"symbol":"VTSAX","latestPrice":2.0}"#);
+        let iex = |token: &str, endpoint: &str| ProviderConfig {
+            name: IEX_PROVIDER_NAME.to_string(),
+            token: Some(token.to_string()),
+            endpoint: Some(endpoint.to_string()),
+            ..Default::default()
+        };
+        let mut config = iex("one", &default_endpoint);
+        config.symbols.insert("VTSAX".to_string(), iex("two", &fund_endpoint));
+        let router = Registry::default().create_router(&config).unwrap();
+
+        assert!(router.real_time("VTSAX".to_string()).is_ok());
+        assert!(router.real_time("AAPL".to_string()).is_ok());
+        assert_eq!(fund_requested.join().unwrap(), "GET /stock/vtsax/quote?token=two HTTP/1.1");
+        assert_eq!(default_requested.join().unwrap(), "GET /stock/aapl/quote?token=one HTTP/1.1");
+    }
+
+    /// A local server answering one request with `body`; returns its URL and the request
+    /// line it received.
+    fn stand_in(body: &str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let body = body.to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            request_line.trim().to_string()
+        });
+        (endpoint, handle)
+    }
+}
@@ -0,0 +1,339 @@
+use chrono::{Datelike, Duration, Local};
+use fin_model::prelude::*;
+use steel_cent::currency::Currency;
+
+use crate::display::{add_money, money_f64, money_from_f64, money_times, sum_money};
+use crate::history::{held_on, price_on, FetchPriceHistory};
+use crate::ledger;
+use crate::model::{Portfolio, Transaction};
+use crate::report::{OutputFormat, Report, Value};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// A dividend, or distribution, paid per share to holders on its ex-dividend date.
+#[derive(Clone, Debug)]
+pub struct DividendPerShare {
+    pub ex_date: Date,
+    pub amount: f64,
+    pub currency: Currency,
+}
+
+/// Provides the history of dividends paid per share.
+pub trait FetchDividends {
+    /// The dividends paid per share of `symbol` with an ex-dividend date between `from`
+    /// and `to` inclusive, in date order.
+    fn dividends_per_share(&self, symbol: &Symbol, from: Date, to: Date) -> RequestResult<Vec<DividendPerShare>>;
+}
+
+/// Where the income of a symbol was determined from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncomeSource {
+    /// Dividends per share from the provider, multiplied by the shares held.
+    Provider,
+    /// Dividends, and reinvested dividends, recorded in the transaction log.
+    Recorded,
+}
+
+/// The dividend income of one symbol over a year; `trailing` is the dividend per share
+/// over the twelve months to the end of the year, or to today.
+#[derive(Clone, Debug)]
+pub struct Income {
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub cash: Money,
+    pub reinvested: Money,
+    pub cost_basis: Money,
+    pub trailing: Option<f64>,
+    pub price: Option<Money>,
+    pub source: IncomeSource,
+    /// Why the income could not be totalled, as it is in more than one currency; the row
+    /// is shown as an error and so is the total.
+    pub error: Option<String>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn show_income<T: FetchPriceHistory + FetchDividends>(portfolio: &Portfolio, data: Option<&T>, year: i32, format: OutputFormat) {
+    let incomes = match income(portfolio, data, year) {
+        Some(incomes) => incomes,
+        None => {
+            println!("No income for {}, it has not started", year);
+            return;
+        }
+    };
+
+    let mut report = Report::new(&["Symbol", "Quantity", "Cash", "Reinvested", "Income", "Yield on Cost", "TTM Yield", "Forward Income", "Source"]);
+    let mut total: Option<(Money, Money, Money, Option<Money>)> = None;
+    // a total of income with an error, or in more than one currency, can't be shown.
+    let mut mixed = false;
+    for income in &incomes {
+        match &income.error {
+            Some(error) => {
+                let mut row = vec![Value::text(&income.symbol), Value::Integer(income.quantity as i64), Value::Error(error.to_string())];
+                row.resize(report.titles.len() - 1, Value::Empty);
+                row.push(Value::text(income.source.name()));
+                report.add_row(row);
+            }
+            None => report.add_row(vec![
+                Value::text(&income.symbol),
+                Value::Integer(income.quantity as i64),
+                Value::Money(income.cash),
+                Value::Money(income.reinvested),
+                Value::money_or(income.total()),
+                Value::percent_or(income.yield_on_cost()),
+                Value::percent_or(income.trailing_yield()),
+                Value::money_or(income.forward()),
+                Value::text(income.source.name()),
+            ]),
+        }
+        mixed = mixed || income.error.is_some();
+        total = match total {
+            Some((cash, reinvested, cost_basis, forward)) => {
+                let amounts = (
+                    add_money(cash, income.cash),
+                    add_money(reinvested, income.reinvested),
+                    add_money(cost_basis, income.cost_basis),
+                    match (forward, income.forward()) {
+                        (Some(forward), Some(amount)) => add_money(forward, amount).map(Some),
+                        (forward, amount) => Some(forward.or(amount)),
+                    },
+                );
+                match amounts {
+                    (Some(cash), Some(reinvested), Some(cost_basis), Some(forward)) => Some((cash, reinvested, cost_basis, forward)),
+                    _ => {
+                        mixed = true;
+                        Some((cash, reinvested, cost_basis, forward))
+                    }
+                }
+            }
+            None => Some((income.cash, income.reinvested, income.cost_basis, income.forward())),
+        };
+    }
+    match total {
+        Some(_) if mixed => {
+            let mut row = vec![Value::text("Total"), Value::Empty, Value::Error(MIXED_INCOME.to_string())];
+            row.resize(report.titles.len(), Value::Empty);
+            report.add_row(row.into_iter().map(Value::bold).collect());
+        }
+        Some((cash, reinvested, cost_basis, forward)) => {
+            let income = add_money(cash, reinvested);
+            report.add_row(
+                vec![
+                    Value::text("Total"),
+                    Value::Empty,
+                    Value::Money(cash),
+                    Value::Money(reinvested),
+                    Value::money_or(income),
+                    Value::percent_or(income.and_then(|income| percent_of(income, cost_basis))),
+                    Value::Empty,
+                    Value::money_or(forward),
+                    Value::Empty,
+                ]
+                .into_iter()
+                .map(Value::bold)
+                .collect(),
+            );
+        }
+        None => (),
+    }
+    report.print(format);
+}
+
+/// The dividend income of each symbol held at the end of `year`, or paying a recorded
+/// dividend during it. Where `data` has dividends per share for a symbol they are used,
+/// with the shares held the day before each ex-dividend date; otherwise the dividends
+/// recorded in the transaction log are. Returns `None` for a year that has not started.
+pub fn income<T: FetchPriceHistory + FetchDividends>(portfolio: &Portfolio, data: Option<&T>, year: i32) -> Option<Vec<Income>> {
+    let today = Local::now().date_naive();
+    let from = Date::from_ymd_opt(year, 1, 1).unwrap();
+    if from > today {
+        return None;
+    }
+    let to = std::cmp::min(Date::from_ymd_opt(year, 12, 31).unwrap(), today);
+    let trailing_from = to - Duration::days(DAYS_PER_YEAR) + Duration::days(1);
+
+    let held = held_on(portfolio, to);
+    let recorded = recorded_dividends(portfolio);
+    let mut symbols: Vec<Symbol> = held.iter().map(|position| position.symbol.to_uppercase()).collect();
+    for (date, symbol, _, _) in &recorded {
+        if date.year() == year && !symbols.contains(&symbol.to_uppercase()) {
+            symbols.push(symbol.to_uppercase());
+        }
+    }
+
+    let incomes = symbols
+        .iter()
+        .map(|symbol| {
+            let positions = held.iter().filter(|position| position.symbol.to_uppercase() == *symbol);
+            let quantity: u32 = positions.clone().map(|position| position.quantity).sum();
+            let cost_basis = sum_money(positions.map(|position| position.cost_basis));
+            let recorded_in = |from: Date, only_reinvested: bool| -> Result<Option<Money>, String> {
+                sum_money(
+                    recorded
+                        .iter()
+                        .filter(|(date, s, _, reinvested)| {
+                            s.to_uppercase() == *symbol && *date >= from && *date <= to && (*reinvested || !only_reinvested)
+                        })
+                        .map(|(_, _, amount, _)| *amount),
+                )
+            };
+            let reinvested = recorded_in(from, true);
+            let per_share = match data {
+                Some(data) => match data.dividends_per_share(symbol, trailing_from.min(from), to) {
+                    Ok(paid) if !paid.is_empty() => Some(paid),
+                    Ok(_) => None,
+                    Err(err) => {
+                        debug!("No dividend data for {}: {:?}", symbol, err);
+                        None
+                    }
+                },
+                None => None,
+            };
+            let (source, total, trailing) = match per_share {
+                Some(paid) => {
+                    let currency = paid[0].currency;
+                    let earned: f64 = paid
+                        .iter()
+                        .filter(|dividend| dividend.ex_date >= from)
+                        .map(|dividend| dividend.amount * shares_before(portfolio, symbol, dividend.ex_date) as f64)
+                        .sum();
+                    let trailing: f64 = paid
+                        .iter()
+                        .filter(|dividend| dividend.ex_date >= trailing_from)
+                        .map(|dividend| dividend.amount)
+                        .sum();
+                    (IncomeSource::Provider, Ok(Some(money_from_f64(currency, earned))), Some(trailing))
+                }
+                None => {
+                    let trailing = match recorded_in(trailing_from, false) {
+                        Ok(Some(amount)) if quantity > 0 => Some(money_f64(&amount) / quantity as f64),
+                        _ => None,
+                    };
+                    (IncomeSource::Recorded, recorded_in(from, false), trailing)
+                }
+            };
+            // amounts in more than one currency have no total, so the income is an error.
+            let error = [&total, &reinvested, &cost_basis].iter().find_map(|amounts| amounts.as_ref().err().cloned());
+            let (total, reinvested, cost_basis) = (total.unwrap_or(None), reinvested.unwrap_or(None), cost_basis.unwrap_or(None));
+            let currency = total
+                .or(reinvested)
+                .or(cost_basis)
+                .map(|money| money.currency)
+                .unwrap_or_else(|| portfolio.currency());
+            let total = total.unwrap_or_else(|| Money::zero(currency));
+            let reinvested = reinvested.unwrap_or_else(|| Money::zero(currency));
+            let error = error.or_else(|| sum_money(vec![total, reinvested]).err());
+            Income {
+                symbol: symbol.to_string(),
+                quantity,
+                cash: if total.currency == reinvested.currency && total.minor_amount() > reinvested.minor_amount() {
+                    total - reinvested
+                } else {
+                    Money::zero(currency)
+                },
+                reinvested,
+                cost_basis: cost_basis.unwrap_or_else(|| Money::zero(currency)),
+                trailing,
+                price: match data {
+                    Some(data) if quantity > 0 => price_on(data, symbol, to),
+                    _ => None,
+                },
+                source,
+                error,
+            }
+        })
+        .collect();
+    Some(incomes)
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl IncomeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IncomeSource::Provider => "provider",
+            IncomeSource::Recorded => "recorded",
+        }
+    }
+}
+
+impl Income {
+    /// The cash and reinvested income, `None` if they are in different currencies.
+    pub fn total(&self) -> Option<Money> {
+        add_money(self.cash, self.reinvested)
+    }
+
+    /// The income as a percentage of the cost basis of the shares held.
+    pub fn yield_on_cost(&self) -> Option<f64> {
+        self.total().and_then(|total| percent_of(total, self.cost_basis))
+    }
+
+    /// The trailing twelve month dividend per share as a percentage of the price.
+    pub fn trailing_yield(&self) -> Option<f64> {
+        match (self.trailing, self.price) {
+            (Some(trailing), Some(price)) if price.minor_amount() > 0 => Some(trailing / money_f64(&price) * 100.0),
+            _ => None,
+        }
+    }
+
+    /// The income expected over the next year if the trailing dividends per share are
+    /// paid again on the shares held now.
+    pub fn forward(&self) -> Option<Money> {
+        match self.trailing {
+            Some(trailing) if self.quantity > 0 =>
+                Some(money_from_f64(self.cash.currency, trailing * self.quantity as f64)),
+            _ => None,
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const DAYS_PER_YEAR: i64 = 365;
+
+/// The error of a total of income in more than one currency.
+const MIXED_INCOME: &str = "income in more than one currency";
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Every dividend recorded in the transaction logs, as the date, symbol, amount and
+/// whether it was reinvested.
+fn recorded_dividends(portfolio: &Portfolio) -> Vec<(Date, Symbol, Money, bool)> {
+    ledger::all_transactions(portfolio)
+        .into_iter()
+        .filter_map(|transaction| match transaction {
+            Transaction::Dividend(date, symbol, amount) => Some((date, symbol, amount, false)),
+            Transaction::Reinvest(date, symbol, trade) => Some((date, symbol, money_times(trade.price, trade.quantity), true)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The shares of `symbol` held at the close of the day before `ex_date`, those entitled
+/// to the dividend.
+fn shares_before(portfolio: &Portfolio, symbol: &Symbol, ex_date: Date) -> u32 {
+    held_on(portfolio, ex_date - Duration::days(1))
+        .iter()
+        .filter(|position| position.symbol.to_uppercase() == *symbol)
+        .map(|position| position.quantity)
+        .sum()
+}
+
+fn percent_of(amount: Money, of: Money) -> Option<f64> {
+    if of.minor_amount() == 0 || amount.currency != of.currency {
+        None
+    } else {
+        Some(amount.minor_amount() as f64 / of.minor_amount() as f64 * 100.0)
+    }
+}
+
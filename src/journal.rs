@@ -0,0 +1,473 @@
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum JournalError {
+    FileError(io::Error),
+    /// A journal entry, or a version of the portfolio file, that could not be read.
+    FormatError(String),
+    NothingToUndo,
+    NothingToRedo,
+    /// The portfolio file has been changed, other than by folio, since the entry was
+    /// recorded; the entry's identifier and the section of the file changed.
+    Conflict(u32, String),
+}
+
+/// What an entry in the journal records.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A change made by a command, such as `add` or `sell`.
+    Edit,
+    /// The reversal of an earlier entry.
+    Undo,
+    /// The change of an undone entry made again.
+    Redo,
+}
+
+/// A change to one entry of a section of the portfolio file, such as one of its holdings,
+/// at `index` in the section; an entry added has no `before`, and one removed has no
+/// `after`. The index is where the entry is once the changes before it in the same
+/// journal entry are made. Without an `index` the change is to the whole section, one
+/// that is not an array of entries, or that was added or removed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Change {
+    pub section: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<toml::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<toml::Value>,
+}
+
+/// One change to the portfolio file, made by one command; for `Undo` and `Redo` entries
+/// `target` is the entry undone, or redone.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub id: u32,
+    pub time: DateTime<Local>,
+    pub user: String,
+    pub command: String,
+    pub kind: EntryKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<u32>,
+    pub changes: Vec<Change>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The journal is kept beside the portfolio file, as `FILE.journal`, with one entry, in
+/// JSON, on each line. Entries are only ever appended.
+pub fn journal_file_name(file_name: &str) -> String {
+    format!("{}.journal", file_name)
+}
+
+/// Every entry in the journal of `file_name`, oldest first; there are none if the
+/// journal does not exist.
+pub fn read_journal(file_name: &str) -> Result<Vec<Entry>, JournalError> {
+    let journal_name = journal_file_name(file_name);
+    info!("journal::read_journal {}", journal_name);
+    let f = match File::open(&journal_name) {
+        Ok(handle) => handle,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(JournalError::FileError(err)),
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    for (number, line) in BufReader::new(f).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => return Err(JournalError::FileError(err)),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Entry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => return Err(JournalError::FormatError(
+                format!("{} line {}: {}", journal_name, number + 1, err))),
+        }
+    }
+    Ok(entries)
+}
+
+/// The changes between two versions of a portfolio file, by entry for sections that are
+/// arrays of entries in both, otherwise by top-level section. The `version` key is not a
+/// change to the portfolio so is ignored.
+pub fn changes(before: &str, after: &str) -> Result<Vec<Change>, JournalError> {
+    let before = parse(before)?;
+    let after = parse(after)?;
+    let mut sections: Vec<&String> = before.keys().chain(after.keys()).collect();
+    sections.sort();
+    sections.dedup();
+    let mut changes: Vec<Change> = Vec::new();
+    for section in sections {
+        if section.as_str() == VERSION_KEY || before.get(section) == after.get(section) {
+            continue;
+        }
+        match (before.get(section), after.get(section)) {
+            (Some(toml::Value::Array(before)), Some(toml::Value::Array(after))) => {
+                changes.extend(entry_changes(section, before, after))
+            }
+            (before, after) => changes.push(Change {
+                section: section.to_string(),
+                index: None,
+                before: before.cloned(),
+                after: after.cloned(),
+            }),
+        }
+    }
+    Ok(changes)
+}
+
+/// Append an entry recording `changes` made by `command`, by the current user now. Nothing
+/// is recorded if there are no changes.
+pub fn record(
+    file_name: &str,
+    command: &str,
+    kind: EntryKind,
+    target: Option<u32>,
+    changes: Vec<Change>,
+) -> Result<Option<Entry>, JournalError> {
+    if changes.is_empty() {
+        return Ok(None);
+    }
+    let entries = read_journal(file_name)?;
+    let entry = Entry {
+        id: entries.last().map(|entry| entry.id + 1).unwrap_or(1),
+        time: Local::now(),
+        user: current_user(),
+        command: command.to_string(),
+        kind,
+        target,
+        changes,
+    };
+    let line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(err) => return Err(JournalError::FormatError(err.to_string())),
+    };
+    let journal_name = journal_file_name(file_name);
+    info!("journal::record {} in {}", entry.id, journal_name);
+    let written = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&journal_name)
+        .and_then(|mut f| {
+            f.write_all(format!("{}\n", line).as_bytes())?;
+            f.sync_data()
+        });
+    match written {
+        Ok(()) => Ok(Some(entry)),
+        Err(err) => Err(JournalError::FileError(err)),
+    }
+}
+
+/// The entry `folio undo` would reverse: the latest change, made or redone, that has not
+/// been undone.
+pub fn undoable(entries: &[Entry]) -> Option<&Entry> {
+    let (done, _) = stacks(entries);
+    done.last().cloned()
+}
+
+/// The entry `folio redo` would make again: the latest undone, if nothing has been
+/// changed since.
+pub fn redoable(entries: &[Entry]) -> Option<&Entry> {
+    let (_, undone) = stacks(entries);
+    undone.last().cloned()
+}
+
+/// The identifiers of the entries whose changes are currently undone.
+pub fn undone(entries: &[Entry]) -> Vec<u32> {
+    let (_, undone) = stacks(entries);
+    undone.iter().map(|entry| entry.id).collect()
+}
+
+/// Make the changes of `entry` to the contents of a portfolio file, or reverse them,
+/// returning the new contents. Each entry, or section, changed must be as the journal
+/// entry left it, when reversing, or found it, otherwise the file has since been changed
+/// by hand.
+pub fn apply(source: &str, entry: &Entry, reverse: bool) -> Result<String, JournalError> {
+    let mut table = parse(source)?;
+    // the index of each change assumes those before it are made, so they are reversed in
+    // the opposite order.
+    let changes: Vec<&Change> = if reverse {
+        entry.changes.iter().rev().collect()
+    } else {
+        entry.changes.iter().collect()
+    };
+    let conflict = |change: &Change| JournalError::Conflict(entry.id, change.section.to_string());
+    for change in changes {
+        let (expected, replacement) = if reverse {
+            (&change.after, &change.before)
+        } else {
+            (&change.before, &change.after)
+        };
+        let index = match change.index {
+            Some(index) => index,
+            None => {
+                if table.get(&change.section) != expected.as_ref() {
+                    return Err(conflict(change));
+                }
+                match replacement {
+                    Some(value) => table.insert(change.section.to_string(), value.clone()),
+                    None => table.remove(&change.section),
+                };
+                continue;
+            }
+        };
+        let entries = match table.get_mut(&change.section) {
+            Some(toml::Value::Array(entries)) => entries,
+            _ => return Err(conflict(change)),
+        };
+        match (expected, replacement) {
+            (Some(expected), _) if entries.get(index) != Some(expected) => return Err(conflict(change)),
+            (Some(_), Some(value)) => entries[index] = value.clone(),
+            (Some(_), None) => {
+                entries.remove(index);
+            }
+            (None, Some(value)) if index <= entries.len() => entries.insert(index, value.clone()),
+            (None, _) => return Err(conflict(change)),
+        }
+    }
+    match toml::to_string(&toml::Value::Table(table)) {
+        Ok(contents) => Ok(contents),
+        Err(err) => Err(JournalError::FormatError(err.to_string())),
+    }
+}
+
+/// A short description of a change: an entry added or removed, for example
+/// "holdings +AMZN", or the fields of an entry changed with their values before and
+/// after, for example "holdings AAPL quantity 10 -> 15".
+pub fn describe(change: &Change) -> String {
+    if change.index.is_some() {
+        return match (&change.before, &change.after) {
+            (None, Some(after)) => format!("{} +{}", change.section, label(after)),
+            (Some(before), None) => format!("{} -{}", change.section, label(before)),
+            (Some(before), Some(after)) => format!("{} {} {}", change.section, label(before), differences(before, after)),
+            (None, None) => change.section.to_string(),
+        };
+    }
+    match (&change.before, &change.after) {
+        (Some(toml::Value::Array(before)), Some(toml::Value::Array(after))) => {
+            let mut parts: Vec<String> = vec![change.section.to_string()];
+            parts.extend(after.iter().filter(|entry| !before.contains(entry)).map(|entry| format!("+{}", label(entry))));
+            parts.extend(before.iter().filter(|entry| !after.contains(entry)).map(|entry| format!("-{}", label(entry))));
+            if parts.len() == 1 {
+                parts.push("reordered".to_string());
+            }
+            parts.join(" ")
+        }
+        (None, Some(toml::Value::Array(after))) => format!(
+            "{} {}",
+            change.section,
+            after.iter().map(|entry| format!("+{}", label(entry))).collect::<Vec<String>>().join(" ")
+        ),
+        (Some(toml::Value::Array(before)), None) => format!(
+            "{} {}",
+            change.section,
+            before.iter().map(|entry| format!("-{}", label(entry))).collect::<Vec<String>>().join(" ")
+        ),
+        (Some(toml::Value::Table(_)), _) | (_, Some(toml::Value::Table(_))) => format!("{} changed", change.section),
+        (before, after) => format!(
+            "{} {} -> {}",
+            change.section,
+            before.as_ref().map(|v| v.to_string()).unwrap_or_else(|| NONE.to_string()),
+            after.as_ref().map(|v| v.to_string()).unwrap_or_else(|| NONE.to_string())
+        ),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const VERSION_KEY: &str = "version";
+
+const NONE: &str = "(none)";
+
+/// The keys used, in order, to describe an entry of a section.
+const LABEL_KEYS: [&str; 5] = ["action", "symbol", "name", "currency_code", "date"];
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalError::FileError(err) => write!(f, "journal file error: {}", err),
+            JournalError::FormatError(message) => write!(f, "could not read the journal: {}", message),
+            JournalError::NothingToUndo => f.write_str("Nothing to undo"),
+            JournalError::NothingToRedo => f.write_str("Nothing to redo"),
+            JournalError::Conflict(id, section) => write!(
+                f,
+                "The {} of the portfolio file have changed since change {} was made, it can't be reversed",
+                section, id
+            ),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn parse(source: &str) -> Result<toml::value::Table, JournalError> {
+    match toml::from_str::<toml::Value>(source) {
+        Ok(toml::Value::Table(table)) => Ok(table),
+        Ok(_) => Err(JournalError::FormatError("the portfolio file is not a table".to_string())),
+        Err(err) => Err(JournalError::FormatError(err.to_string())),
+    }
+}
+
+/// Replay the undo and redo entries, returning the changes currently made and those
+/// currently undone, each newest last. A new change can't follow those undone, so
+/// clears them.
+fn stacks(entries: &[Entry]) -> (Vec<&Entry>, Vec<&Entry>) {
+    let mut done: Vec<&Entry> = Vec::new();
+    let mut undone: Vec<&Entry> = Vec::new();
+    for entry in entries {
+        match entry.kind {
+            EntryKind::Edit => {
+                done.push(entry);
+                undone.clear();
+            }
+            EntryKind::Undo => {
+                if let Some(entry) = done.pop() {
+                    undone.push(entry);
+                }
+            }
+            EntryKind::Redo => {
+                if let Some(entry) = undone.pop() {
+                    done.push(entry);
+                }
+            }
+        }
+    }
+    (done, undone)
+}
+
+/// The entry changes that make `before` into `after`: the entries between those common
+/// to the start and end of both are matched by their longest common subsequence, and
+/// each entry not matched is added, removed or, where one of each meet, changed.
+fn entry_changes(section: &str, before: &[toml::Value], after: &[toml::Value]) -> Vec<Change> {
+    let prefix = before.iter().zip(after.iter()).take_while(|(b, a)| b == a).count();
+    let suffix = before[prefix..]
+        .iter()
+        .rev()
+        .zip(after[prefix..].iter().rev())
+        .take_while(|(b, a)| b == a)
+        .count();
+    let before = &before[prefix..before.len() - suffix];
+    let after = &after[prefix..after.len() - suffix];
+
+    // common[i][j] is the length of the longest common subsequence of before[i..] and after[j..].
+    let mut common = vec![vec![0usize; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let change = |index: usize, before: Option<&toml::Value>, after: Option<&toml::Value>| Change {
+        section: section.to_string(),
+        index: Some(index),
+        before: before.cloned(),
+        after: after.cloned(),
+    };
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut i, mut j, mut index) = (0, 0, prefix);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() {
+            if before[i] == after[j] {
+                i += 1;
+                j += 1;
+                index += 1;
+            } else if common[i + 1][j + 1] == common[i][j] {
+                changes.push(change(index, Some(&before[i]), Some(&after[j])));
+                i += 1;
+                j += 1;
+                index += 1;
+            } else if common[i + 1][j] >= common[i][j + 1] {
+                changes.push(change(index, Some(&before[i]), None));
+                i += 1;
+            } else {
+                changes.push(change(index, None, Some(&after[j])));
+                j += 1;
+                index += 1;
+            }
+        } else if i < before.len() {
+            changes.push(change(index, Some(&before[i]), None));
+            i += 1;
+        } else {
+            changes.push(change(index, None, Some(&after[j])));
+            j += 1;
+            index += 1;
+        }
+    }
+    changes
+}
+
+/// The fields of an entry that differ, each as "key before -> after".
+fn differences(before: &toml::Value, after: &toml::Value) -> String {
+    match (before.as_table(), after.as_table()) {
+        (Some(before), Some(after)) => {
+            let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            keys.into_iter()
+                .filter(|key| before.get(*key) != after.get(*key))
+                .map(|key| {
+                    format!(
+                        "{} {} -> {}",
+                        key,
+                        before.get(key).map(|v| v.to_string()).unwrap_or_else(|| NONE.to_string()),
+                        after.get(key).map(|v| v.to_string()).unwrap_or_else(|| NONE.to_string())
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        }
+        _ => format!("{} -> {}", before, after),
+    }
+}
+
+fn label(entry: &toml::Value) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if let Some(id) = entry.get("id").and_then(|id| id.as_integer()) {
+        parts.push(format!("lot {}", id));
+    }
+    for key in LABEL_KEYS.iter() {
+        if let Some(value) = entry.get(*key).and_then(|value| value.as_str()) {
+            parts.push(value.to_string());
+        }
+    }
+    if parts.is_empty() {
+        "entry".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+fn current_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
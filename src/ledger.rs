@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use fin_model::prelude::*;
+
+use chrono::Local;
+
+use crate::display::{money_from_minor, money_times};
+use crate::actions::{adjusted, apply};
+use crate::lots::lot_item;
+use crate::model::{Classification, CorporateAction, Holding, Item, Portfolio, Trade, Transaction};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The holding of a single symbol derived from the transaction log.
+#[derive(Clone, Debug)]
+pub struct Position {
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub cost_basis: Money,
+    pub first_purchase: Option<Date>,
+}
+
+/// The result of replaying the transaction log; positions are in order of first
+/// purchase and cash has one entry per currency seen. The realized gain, on an average
+/// cost basis, is recorded per symbol for every sale. A sale of more shares than held
+/// is recorded in `oversold`, by its index in the transactions replayed and with the
+/// quantity held, and only the shares held are sold.
+#[derive(Clone, Debug)]
+pub struct Ledger {
+    pub positions: Vec<Position>,
+    pub cash: Vec<Money>,
+    pub realized: HashMap<Symbol, Money>,
+    pub oversold: Vec<(usize, u32)>,
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Replay all transactions, in date order, to determine current positions and cash.
+pub fn replay(transactions: &[Transaction]) -> Ledger {
+    replay_until(transactions, None)
+}
+
+/// Replay all transactions on, or before, `until` (all transactions if `None`).
+pub fn replay_until(transactions: &[Transaction], until: Option<Date>) -> Ledger {
+    replay_with_actions(transactions, &[], until)
+}
+
+/// Replay all transactions, and corporate actions, on, or before, `until`. An action
+/// applies to the positions held at the start of its date, before that day's trades.
+pub fn replay_with_actions(transactions: &[Transaction], actions: &[CorporateAction], until: Option<Date>) -> Ledger {
+    let included = |date: Date| match until {
+        Some(until) => date <= until,
+        None => true,
+    };
+    let mut sorted: Vec<(usize, &Transaction)> = transactions
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| included(t.date()))
+        .collect();
+    sorted.sort_by_key(|(_, t)| t.date());
+    let mut pending: Vec<&CorporateAction> = actions.iter().filter(|a| included(a.date())).collect();
+    pending.sort_by_key(|a| a.date());
+    let mut pending = pending.into_iter().peekable();
+
+    let mut order: Vec<Symbol> = Vec::new();
+    let mut positions: HashMap<Symbol, Position> = HashMap::new();
+    let mut cash: Vec<Money> = Vec::new();
+    let mut realized: HashMap<Symbol, Money> = HashMap::new();
+    let mut oversold: Vec<(usize, u32)> = Vec::new();
+
+    for (index, transaction) in sorted {
+        while pending.peek().map(|a| a.date() <= transaction.date()).unwrap_or(false) {
+            apply_action(pending.next().unwrap(), &mut order, &mut positions);
+        }
+        debug!("ledger::replay {:?}", transaction);
+        match transaction {
+            Transaction::Buy(date, symbol, trade) | Transaction::Reinvest(date, symbol, trade) => {
+                let held_in = positions
+                    .get(symbol)
+                    .filter(|position| position.quantity > 0)
+                    .map(|position| position.cost_basis.currency);
+                if held_in.map(|currency| currency != trade.price.currency).unwrap_or(false) {
+                    warn!("Ignoring purchase of {} in {}, it is held in another currency", symbol, trade.price.currency.code());
+                    continue;
+                }
+                let cost = money_times(trade.price, trade.quantity);
+                let position = positions.entry(symbol.to_string()).or_insert_with(|| {
+                    order.push(symbol.to_string());
+                    Position {
+                        symbol: symbol.to_string(),
+                        quantity: 0,
+                        cost_basis: Money::zero(trade.price.currency),
+                        first_purchase: Some(*date),
+                    }
+                });
+                if position.quantity == 0 {
+                    position.cost_basis = Money::zero(trade.price.currency);
+                    position.first_purchase = Some(*date);
+                }
+                position.quantity += trade.quantity;
+                position.cost_basis = position.cost_basis + cost;
+                // a reinvested dividend is paid, and spent, without changing cash.
+                if let Transaction::Buy(_, _, _) = transaction {
+                    add_cash(&mut cash, cost * -1);
+                }
+            }
+            Transaction::Sell(_, symbol, trade) => {
+                // only the shares held are sold, and paid for.
+                let mut sold = 0;
+                match positions.get_mut(symbol) {
+                    Some(position) => {
+                        let quantity = if trade.quantity > position.quantity {
+                            warn!("Selling {} {} but only {} held", trade.quantity, symbol, position.quantity);
+                            oversold.push((index, position.quantity));
+                            position.quantity
+                        } else {
+                            trade.quantity
+                        };
+                        sold = quantity;
+                        if quantity > 0 {
+                            // average cost, the basis is reduced in proportion to the shares sold.
+                            let removed = position.cost_basis.minor_amount() as i64 * quantity as i64
+                                / position.quantity as i64;
+                            let removed = money_from_minor(position.cost_basis.currency, removed);
+                            position.cost_basis = position.cost_basis - removed;
+                            position.quantity -= quantity;
+                            if trade.price.currency == removed.currency {
+                                let gain = money_times(trade.price, quantity) - removed;
+                                let total = realized
+                                    .entry(symbol.to_string())
+                                    .or_insert_with(|| Money::zero(removed.currency));
+                                *total = *total + gain;
+                            }
+                        }
+                    }
+                    None => {
+                        warn!("Selling {} which has no position", symbol);
+                        oversold.push((index, 0));
+                    }
+                }
+                add_cash(&mut cash, money_times(trade.price, sold));
+            }
+            Transaction::Dividend(_, _, amount) => add_cash(&mut cash, *amount),
+            Transaction::Split(_, symbol, ratio) => {
+                if let Some(position) = positions.get_mut(symbol) {
+                    position.quantity = ratio.apply(position.quantity);
+                }
+            }
+            Transaction::Fee(_, symbol, amount) => {
+                if let Some(symbol) = symbol {
+                    if let Some(position) = positions.get_mut(symbol) {
+                        if position.cost_basis.currency == amount.currency {
+                            position.cost_basis = position.cost_basis + *amount;
+                        }
+                    }
+                }
+                add_cash(&mut cash, *amount * -1);
+            }
+            Transaction::Deposit(_, amount) => add_cash(&mut cash, *amount),
+            Transaction::Withdrawal(_, amount) => add_cash(&mut cash, *amount * -1),
+        }
+    }
+
+    for action in pending {
+        apply_action(action, &mut order, &mut positions);
+    }
+
+    Ledger {
+        positions: order
+            .iter()
+            .filter_map(|symbol| positions.remove(symbol))
+            .filter(|position| position.quantity > 0)
+            .collect(),
+        cash,
+        realized,
+        oversold,
+    }
+}
+
+/// The items of the portfolio, with any lots and the positions derived from the
+/// transaction log appended as `Item::Price` entries, all adjusted for corporate actions
+/// to date. Each account's transactions are replayed separately, and its positions are
+/// classified with the account's name.
+pub fn current_items(portfolio: &Portfolio) -> Vec<Item> {
+    let current = adjusted(portfolio, Local::now().date_naive());
+    let ledger = replay_with_actions(&portfolio.transactions, &portfolio.actions, None);
+    let mut items: Vec<Item> = current
+        .items
+        .iter()
+        .cloned()
+        .chain(current.lots.iter().map(lot_item))
+        .chain(ledger.positions.iter().map(position_item))
+        .collect();
+    for account in &portfolio.accounts {
+        for position in replay_with_actions(&account.transactions, &portfolio.actions, None).positions {
+            if let Item::Price(symbol, holding) = position_item(&position) {
+                let classification = Classification {
+                    account: Some(account.name.to_string()),
+                    ..holding.classification
+                };
+                items.push(Item::Price(symbol, Holding { classification, ..holding }));
+            }
+        }
+    }
+    items
+}
+
+/// All transactions, the portfolio's own followed by those of each account.
+pub fn all_transactions(portfolio: &Portfolio) -> Vec<Transaction> {
+    portfolio
+        .transactions
+        .iter()
+        .chain(portfolio.accounts.iter().flat_map(|account| account.transactions.iter()))
+        .cloned()
+        .collect()
+}
+
+/// The trades recording a dividend of `amount` reinvested in `quantity` whole shares. A
+/// price is in minor units, so when `amount` does not divide evenly the remainder is
+/// carried by paying one minor unit more for some of the shares; the trades together
+/// cost exactly `amount`.
+pub fn reinvested_trades(amount: Money, quantity: u32) -> Vec<Trade> {
+    let price = (amount.minor_amount() as i64).div_euclid(quantity as i64);
+    let remainder = (amount.minor_amount() as i64).rem_euclid(quantity as i64) as u32;
+    vec![
+        Trade { quantity: remainder, price: money_from_minor(amount.currency, price + 1) },
+        Trade { quantity: quantity - remainder, price: money_from_minor(amount.currency, price) },
+    ]
+    .into_iter()
+    .filter(|trade| trade.quantity > 0)
+    .collect()
+}
+
+/// Convert a position into an equivalent `Item`, the purchase price is the average cost
+/// per share.
+pub fn position_item(position: &Position) -> Item {
+    Item::Price(
+        position.symbol.to_string(),
+        Holding {
+            quantity: position.quantity,
+            purchase_price: average_cost(position),
+            purchase_date: position.first_purchase,
+            classification: Classification::default(),
+        },
+    )
+}
+
+pub fn average_cost(position: &Position) -> Money {
+    if position.quantity == 0 {
+        Money::zero(position.cost_basis.currency)
+    } else {
+        money_from_minor(
+            position.cost_basis.currency,
+            position.cost_basis.minor_amount() as i64 / position.quantity as i64,
+        )
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Apply a corporate action to the position in its symbol, if any.
+fn apply_action(action: &CorporateAction, order: &mut Vec<Symbol>, positions: &mut HashMap<Symbol, Position>) {
+    debug!("ledger::replay {:?}", action);
+    let symbol = match order.iter().find(|s| s.to_uppercase() == action.symbol().to_uppercase()) {
+        Some(symbol) => symbol.to_string(),
+        None => return,
+    };
+    let position = match positions.remove(&symbol) {
+        Some(position) if position.quantity > 0 => position,
+        Some(position) => {
+            positions.insert(symbol, position);
+            return;
+        }
+        None => return,
+    };
+    for (symbol, quantity, cost) in apply(action, &symbol, position.quantity, position.cost_basis) {
+        let merged = positions.entry(symbol.to_string()).or_insert_with(|| {
+            if !order.contains(&symbol) {
+                order.push(symbol.to_string());
+            }
+            Position {
+                symbol: symbol.to_string(),
+                quantity: 0,
+                cost_basis: Money::zero(cost.currency),
+                first_purchase: position.first_purchase,
+            }
+        });
+        if merged.quantity == 0 {
+            merged.cost_basis = Money::zero(cost.currency);
+            merged.first_purchase = position.first_purchase;
+        }
+        merged.quantity += quantity;
+        if merged.cost_basis.currency == cost.currency {
+            merged.cost_basis = merged.cost_basis + cost;
+        }
+    }
+}
+
+fn add_cash(cash: &mut Vec<Money>, amount: Money) {
+    match cash.iter_mut().find(|m| m.currency == amount.currency) {
+        Some(balance) => *balance = *balance + amount,
+        None => cash.push(amount),
+    }
+}
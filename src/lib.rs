@@ -1,24 +1,72 @@
+extern crate chrono;
+extern crate csv;
+extern crate ctrlc;
+extern crate fs2;
+extern crate fin_iex;
 extern crate fin_model;
 #[macro_use]
 extern crate log;
 extern crate num_format;
-#[macro_use]
 extern crate prettytable;
 extern crate serde;
+extern crate serde_json;
 extern crate shellexpand;
 extern crate steel_cent;
 extern crate toml;
+extern crate ureq;
 
 // ------------------------------------------------------------------------------------------------
 // Public Modules/Exports
 // ------------------------------------------------------------------------------------------------
 
+pub mod accounts;
+
+pub mod actions;
+
+pub mod allocation;
+
+pub mod cache;
+
+pub mod cash;
+
 pub mod display;
 
+pub mod fixture;
+
+pub mod fx;
+
+pub mod gains;
+
+pub mod history;
+
 pub mod holdings;
 
+pub mod iex;
+
+pub mod income;
+
+pub mod journal;
+
+pub mod ledger;
+
+pub mod lots;
+
 pub mod model;
 
+pub mod performance;
+
+pub mod providers;
+
+pub mod quotes;
+
+pub mod rebalance;
+
+pub mod report;
+
+pub mod risk;
+
 pub mod show;
 
+pub mod storage;
+
 pub mod watch;
@@ -0,0 +1,490 @@
+use std::str::FromStr;
+
+use fin_model::prelude::*;
+use crate::accounts;
+use crate::actions;
+use crate::cash::{add_cash, tracks_cash};
+use crate::display::money_times;
+use crate::ledger;
+use crate::model::{Holding, Item, Lot, Portfolio, RealizedGain, Trade, Transaction};
+use crate::report::{OutputFormat, Report, Value};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// How lots are chosen when selling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    /// First in, first out; the oldest lots are sold first.
+    Fifo,
+    /// Last in, first out; the newest lots are sold first.
+    Lifo,
+    /// Highest in, first out; the lots with the highest purchase price are sold first.
+    Hifo,
+    /// Only the identified lot is sold.
+    Lot(u32),
+}
+
+#[derive(Debug)]
+pub enum SaleError {
+    NoLots(Symbol),
+    UnknownLot(u32),
+    InsufficientQuantity(Symbol, u32, u32),
+    /// The symbol is held in the lots, or through the transaction logs, of more than one
+    /// account.
+    SeveralAccounts(Symbol),
+    /// A method was given for a symbol held through a transaction log, which is only
+    /// sold at its average cost.
+    AverageCost(Symbol),
+    /// The sale price is in a different currency, the second, than the shares being sold
+    /// were purchased in, the third.
+    CurrencyMismatch(Symbol, String, String),
+}
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Sell `quantity` shares of `symbol` at `price` on `date`, consuming lots according to
+/// `method`, FIFO by default. Any `Item::Price` holdings for `symbol`, or for a symbol that
+/// became `symbol` through a corporate action, are first converted into lots, and the lots
+/// are adjusted for the actions up to `date`. The returned portfolio holds the remaining
+/// lots, the newly realized gains are appended to `realized` and the proceeds are added
+/// to its cash, if it is tracked. Only the lots sold from are stored adjusted, keeping their original
+/// quantity and price; the others are stored as they were purchased.
+///
+/// A symbol only held through a transaction log is instead sold by appending a `Sell` to
+/// that log, see `sell_from_log`. As there, lots in more than one account are not sold
+/// from together; the portfolio should be limited to one account first.
+pub fn sell(
+    portfolio: Portfolio,
+    symbol: &Symbol,
+    quantity: u32,
+    price: Money,
+    date: Date,
+    method: Option<Method>,
+) -> Result<(Portfolio, Vec<RealizedGain>), SaleError> {
+    info!("lots::sell {} {} {:?}", quantity, symbol, method);
+    let symbols = actions::source_symbols(&portfolio, symbol);
+    let in_lots = portfolio.lots.iter().any(|lot| symbols.contains(&lot.symbol))
+        || portfolio.items.iter().any(|item| match item {
+            Item::Price(s, _) => symbols.contains(s),
+            Item::Watch(_) => false,
+        });
+    if !in_lots {
+        return match (method, sell_from_log(portfolio, symbol, quantity, price, date)) {
+            (Some(_), Ok(_)) => Err(SaleError::AverageCost(symbol.to_string())),
+            (_, result) => result,
+        };
+    }
+    let method = method.unwrap_or(Method::Fifo);
+    let portfolio = holdings_to_lots(portfolio, &symbols);
+    let groups = actions::adjusted_lot_groups(&portfolio, date);
+    let lots: Vec<Lot> = groups.iter().flatten().cloned().collect();
+
+    let mut candidates: Vec<usize> = lots
+        .iter()
+        .enumerate()
+        .filter(|(_, lot)| lot.symbol == *symbol && lot.quantity > 0)
+        .map(|(i, _)| i)
+        .collect();
+    if candidates.is_empty() {
+        return Err(SaleError::NoLots(symbol.to_string()));
+    }
+
+    match method {
+        Method::Fifo => candidates.sort_by_key(|i| lots[*i].purchase_date),
+        Method::Lifo => {
+            candidates.sort_by_key(|i| lots[*i].purchase_date);
+            candidates.reverse();
+        }
+        Method::Hifo => candidates.sort_by(|a, b| {
+            lots[*b].purchase_price.minor_amount().cmp(&lots[*a].purchase_price.minor_amount())
+        }),
+        Method::Lot(id) => {
+            candidates.retain(|i| lots[*i].id == id);
+            if candidates.is_empty() {
+                return Err(SaleError::UnknownLot(id));
+            }
+        }
+    }
+
+    let account = &lots[candidates[0]].classification.account;
+    if candidates.iter().any(|i| lots[*i].classification.account != *account) {
+        return Err(SaleError::SeveralAccounts(symbol.to_string()));
+    }
+
+    if let Some(i) = candidates.iter().find(|i| lots[**i].purchase_price.currency != price.currency) {
+        return Err(SaleError::CurrencyMismatch(
+            symbol.to_string(),
+            price.currency.code(),
+            lots[*i].purchase_price.currency.code(),
+        ));
+    }
+
+    let available: u32 = candidates.iter().map(|i| lots[*i].quantity).sum();
+    if available < quantity {
+        return Err(SaleError::InsufficientQuantity(symbol.to_string(), available, quantity));
+    }
+
+    let mut remaining = quantity;
+    let mut new_lots = lots.clone();
+    let mut sold_from: Vec<usize> = Vec::new();
+    let mut realized: Vec<RealizedGain> = Vec::new();
+    for i in candidates {
+        if remaining == 0 {
+            break;
+        }
+        let lot = &mut new_lots[i];
+        let sold = if lot.quantity < remaining { lot.quantity } else { remaining };
+        lot.quantity -= sold;
+        remaining -= sold;
+        sold_from.push(i);
+        realized.push(RealizedGain {
+            lot: lot.id,
+            symbol: lot.symbol.to_string(),
+            quantity: sold,
+            purchase_price: lot.purchase_price,
+            purchase_date: lot.purchase_date,
+            sale_price: price,
+            sale_date: date,
+            account: lot.classification.account.clone(),
+        });
+    }
+
+    let mut kept: Vec<Lot> = Vec::new();
+    let mut start = 0;
+    for (lot, group) in portfolio.lots.iter().zip(groups.iter()) {
+        let end = start + group.len();
+        if sold_from.iter().any(|i| *i >= start && *i < end) {
+            kept.extend(new_lots[start..end].iter().filter(|lot| lot.quantity > 0).cloned());
+        } else {
+            kept.push(lot.clone());
+        }
+        start = end;
+    }
+
+    let mut new_portfolio = Portfolio {
+        lots: kept,
+        realized: portfolio.realized.iter().cloned().chain(realized.iter().cloned()).collect(),
+        ..portfolio
+    };
+    // the proceeds are credited to the cash balance of each lot's account, if it has one.
+    for gain in &realized {
+        if tracks_cash(&new_portfolio, &gain.account) {
+            add_cash(&mut new_portfolio, money_times(gain.sale_price, gain.quantity), &gain.account);
+        }
+    }
+    Ok((new_portfolio, realized))
+}
+
+/// Sell `quantity` shares of `symbol` held through the portfolio's own transaction log, or
+/// one account's, by appending a `Sell` transaction to that log; the sale's cash, and its
+/// gain on an average cost basis, follow from the log. The gain returned, for display,
+/// has no lot and is not appended to `realized`.
+pub fn sell_from_log(
+    portfolio: Portfolio,
+    symbol: &Symbol,
+    quantity: u32,
+    price: Money,
+    date: Date,
+) -> Result<(Portfolio, Vec<RealizedGain>), SaleError> {
+    let held = |transactions: &[Transaction]| {
+        ledger::replay_with_actions(transactions, &portfolio.actions, Some(date))
+            .positions
+            .into_iter()
+            .find(|position| position.symbol.to_uppercase() == symbol.to_uppercase())
+    };
+    let mut holders: Vec<(Option<String>, ledger::Position)> = Vec::new();
+    if let Some(position) = held(&portfolio.transactions) {
+        holders.push((None, position));
+    }
+    for account in &portfolio.accounts {
+        if let Some(position) = held(&account.transactions) {
+            holders.push((Some(account.name.to_string()), position));
+        }
+    }
+    let (account, position) = match holders.len() {
+        0 => return Err(SaleError::NoLots(symbol.to_string())),
+        1 => holders.remove(0),
+        _ => return Err(SaleError::SeveralAccounts(symbol.to_string())),
+    };
+    if position.quantity < quantity {
+        return Err(SaleError::InsufficientQuantity(symbol.to_string(), position.quantity, quantity));
+    }
+    if position.cost_basis.currency != price.currency {
+        return Err(SaleError::CurrencyMismatch(
+            symbol.to_string(),
+            price.currency.code(),
+            position.cost_basis.currency.code(),
+        ));
+    }
+
+    let gain = RealizedGain {
+        lot: NO_LOT,
+        symbol: position.symbol.to_string(),
+        quantity,
+        purchase_price: ledger::average_cost(&position),
+        purchase_date: position.first_purchase,
+        sale_price: price,
+        sale_date: date,
+        account: account.clone(),
+    };
+    let mut new_portfolio = portfolio;
+    accounts::add_transaction(
+        &mut new_portfolio,
+        account.as_deref(),
+        Transaction::Sell(date, position.symbol.to_string(), Trade { quantity, price }),
+    );
+    Ok((new_portfolio, vec![gain]))
+}
+
+/// Convert a lot into an equivalent `Item`.
+pub fn lot_item(lot: &Lot) -> Item {
+    Item::Price(
+        lot.symbol.to_string(),
+        Holding {
+            quantity: lot.quantity,
+            purchase_price: lot.purchase_price,
+            purchase_date: lot.purchase_date,
+            classification: lot.classification.clone(),
+        },
+    )
+}
+
+pub fn show_realized(realized: &[RealizedGain], format: OutputFormat) {
+    let mut report = Report::new(&["Lot", "Symbol", "Purchased", "Purchase Price", "Sold", "Sale Price", "Quantity", "Gain", "Term"]);
+    for gain in realized {
+        report.add_row(vec![
+            if gain.lot == NO_LOT { Value::Empty } else { Value::Integer(gain.lot as i64) },
+            Value::text(&gain.symbol),
+            Value::date_or(gain.purchase_date),
+            Value::Money(gain.purchase_price),
+            Value::Date(gain.sale_date),
+            Value::Money(gain.sale_price),
+            Value::Integer(gain.quantity as i64),
+            match gain.gain() {
+                Some(amount) => Value::Gain(amount),
+                None => Value::Error(format!(
+                    "sold in {}, purchased in {}",
+                    gain.sale_price.currency.code(),
+                    gain.purchase_price.currency.code()
+                )),
+            },
+            Value::Text(gain.term().to_string()),
+        ]);
+    }
+    report.print(format);
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+/// The lot of a gain on a sale from a transaction log, lot identifiers start at 1.
+const NO_LOT: u32 = 0;
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" => Ok(Method::Fifo),
+            "lifo" => Ok(Method::Lifo),
+            "hifo" => Ok(Method::Hifo),
+            other if other.starts_with("lot=") => match other[4..].parse::<u32>() {
+                Ok(id) => Ok(Method::Lot(id)),
+                Err(_) => Err(format!("invalid lot identifier {}", &other[4..])),
+            },
+            other => Err(format!("unknown lot selection method {}", other)),
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn holdings_to_lots(portfolio: Portfolio, symbols: &[Symbol]) -> Portfolio {
+    let mut next_id = portfolio.next_lot_id();
+    let mut lots = portfolio.lots.clone();
+    let mut items: Vec<Item> = Vec::new();
+    for item in portfolio.items.iter() {
+        match item {
+            Item::Price(s, h) if symbols.contains(s) => {
+                lots.push(Lot {
+                    id: next_id,
+                    symbol: s.to_string(),
+                    quantity: h.quantity,
+                    purchase_price: h.purchase_price,
+                    purchase_date: h.purchase_date,
+                    classification: h.classification.clone(),
+                    adjusted: None,
+                    original: None,
+                });
+                next_id += 1;
+            }
+            _ => items.push(item.clone()),
+        }
+    }
+    Portfolio { items, lots, ..portfolio }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use steel_cent::currency::with_code;
+
+    use crate::model::{Cash, Classification, Term};
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn dollars(amount: i32) -> Money {
+        Money::of_major(with_code("USD").unwrap(), amount)
+    }
+
+    fn lot(id: u32, price: i32, purchase_date: Date) -> Lot {
+        Lot {
+            id,
+            symbol: "AAPL".to_string(),
+            quantity: 10,
+            purchase_price: dollars(price),
+            purchase_date: Some(purchase_date),
+            classification: Classification::default(),
+            adjusted: None,
+            original: None,
+        }
+    }
+
+    /// Three lots of 10 shares; the second is the most expensive.
+    fn portfolio() -> Portfolio {
+        Portfolio {
+            lots: vec![
+                lot(1, 100, date(2019, 1, 2)),
+                lot(2, 150, date(2019, 6, 3)),
+                lot(3, 120, date(2020, 3, 2)),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn sold(quantity: u32, method: Method) -> (Portfolio, Vec<RealizedGain>) {
+        sell(portfolio(), &"AAPL".to_string(), quantity, dollars(130), date(2020, 6, 1), Some(method)).unwrap()
+    }
+
+    fn lots_sold(realized: &[RealizedGain]) -> Vec<(u32, u32)> {
+        realized.iter().map(|gain| (gain.lot, gain.quantity)).collect()
+    }
+
+    fn lots_kept(portfolio: &Portfolio) -> Vec<(u32, u32)> {
+        portfolio.lots.iter().map(|lot| (lot.id, lot.quantity)).collect()
+    }
+
+    #[test]
+    fn sells_oldest_lots_first() {
+        let (portfolio, realized) = sold(15, Method::Fifo);
+        assert_eq!(lots_sold(&realized), vec![(1, 10), (2, 5)]);
+        assert_eq!(lots_kept(&portfolio), vec![(2, 5), (3, 10)]);
+        assert_eq!(portfolio.realized.len(), 2);
+        // no cash is tracked, so none is credited.
+        assert!(portfolio.cash.is_empty());
+    }
+
+    #[test]
+    fn credits_proceeds_to_tracked_cash() {
+        let mut portfolio = portfolio();
+        portfolio.cash.push(Cash { balance: dollars(100), account: None });
+        let (portfolio, _) = sell(portfolio, &"AAPL".to_string(), 15, dollars(130), date(2020, 6, 1), None).unwrap();
+        assert_eq!(portfolio.cash.len(), 1);
+        assert_eq!(portfolio.cash[0].balance, dollars(100 + 15 * 130));
+    }
+
+    #[test]
+    fn sells_newest_lots_first() {
+        let (portfolio, realized) = sold(15, Method::Lifo);
+        assert_eq!(lots_sold(&realized), vec![(3, 10), (2, 5)]);
+        assert_eq!(lots_kept(&portfolio), vec![(1, 10), (2, 5)]);
+    }
+
+    #[test]
+    fn sells_highest_priced_lots_first() {
+        let (portfolio, realized) = sold(15, Method::Hifo);
+        assert_eq!(lots_sold(&realized), vec![(2, 10), (3, 5)]);
+        assert_eq!(lots_kept(&portfolio), vec![(1, 10), (3, 5)]);
+        assert_eq!(realized[0].gain(), Some(dollars(-200)));
+        assert_eq!(realized[1].gain(), Some(dollars(50)));
+    }
+
+    #[test]
+    fn sells_part_of_an_identified_lot() {
+        let (portfolio, realized) = sold(4, Method::Lot(3));
+        assert_eq!(lots_sold(&realized), vec![(3, 4)]);
+        assert_eq!(lots_kept(&portfolio), vec![(1, 10), (2, 10), (3, 6)]);
+        assert_eq!(realized[0].purchase_price, dollars(120));
+    }
+
+    #[test]
+    fn rejects_an_unknown_lot() {
+        match sell(portfolio(), &"AAPL".to_string(), 4, dollars(130), date(2020, 6, 1), Some(Method::Lot(9))) {
+            Err(SaleError::UnknownLot(9)) => (),
+            other => panic!("expected an unknown lot, not {:?}", other.map(|(_, realized)| realized)),
+        }
+    }
+
+    #[test]
+    fn rejects_selling_more_than_held() {
+        match sell(portfolio(), &"AAPL".to_string(), 31, dollars(130), date(2020, 6, 1), None) {
+            Err(SaleError::InsufficientQuantity(symbol, 30, 31)) => assert_eq!(symbol, "AAPL"),
+            other => panic!("expected insufficient quantity, not {:?}", other.map(|(_, realized)| realized)),
+        }
+        match sell(portfolio(), &"AAPL".to_string(), 11, dollars(130), date(2020, 6, 1), Some(Method::Lot(1))) {
+            Err(SaleError::InsufficientQuantity(_, 10, 11)) => (),
+            other => panic!("expected insufficient quantity, not {:?}", other.map(|(_, realized)| realized)),
+        }
+    }
+
+    #[test]
+    fn rejects_a_price_in_another_currency() {
+        let price = Money::of_major(with_code("EUR").unwrap(), 130);
+        match sell(portfolio(), &"AAPL".to_string(), 5, price, date(2020, 6, 1), None) {
+            Err(SaleError::CurrencyMismatch(_, sold_in, bought_in)) => {
+                assert_eq!(sold_in, "EUR");
+                assert_eq!(bought_in, "USD");
+            }
+            other => panic!("expected a currency mismatch, not {:?}", other.map(|(_, realized)| realized)),
+        }
+    }
+
+    #[test]
+    fn rejects_lots_in_several_accounts() {
+        let mut portfolio = portfolio();
+        portfolio.lots[1].classification.account = Some("IRA".to_string());
+        match sell(portfolio.clone(), &"AAPL".to_string(), 5, dollars(130), date(2020, 6, 1), None) {
+            Err(SaleError::SeveralAccounts(symbol)) => assert_eq!(symbol, "AAPL"),
+            other => panic!("expected several accounts, not {:?}", other.map(|(_, realized)| realized)),
+        }
+        // an identified lot is only in one account.
+        let (_, realized) = sell(portfolio, &"AAPL".to_string(), 5, dollars(130), date(2020, 6, 1), Some(Method::Lot(2))).unwrap();
+        assert_eq!(realized[0].account, Some("IRA".to_string()));
+    }
+
+    #[test]
+    fn classifies_short_and_long_term_gains() {
+        let (_, realized) = sold(15, Method::Fifo);
+        // held from 2019-01-02, and from 2019-06-03, to 2020-06-01.
+        assert_eq!(realized[0].term(), Term::Long);
+        assert_eq!(realized[1].term(), Term::Short);
+    }
+}
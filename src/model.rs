@@ -1,7 +1,12 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 
+use chrono::{Datelike, Duration};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use shellexpand;
 use steel_cent::currency::{Currency, with_code};
@@ -9,7 +14,9 @@ use toml;
 
 use fin_model::prelude::*;
 
-use crate::display::DATE_FMT;
+use crate::display::{money_change_times, DATE_FMT};
+use crate::ledger;
+use crate::storage;
 
 // ------------------------------------------------------------------------------------------------
 // Public Types
@@ -21,24 +28,250 @@ pub enum ModelError {
     FileError(io::Error),
     ParseError(toml::de::Error),
     WriteError(toml::ser::Error),
+    CurrencyError(String),
+    TransactionError(String),
+    LotError(String),
+    AccountError(String),
+    ActionError(String),
+    VersionError(String),
+    /// A currency code that is not known.
+    UnknownCurrency(String, Location),
+    /// A holding, not watch-only, without a purchase price.
+    MissingPrice(Symbol, Location),
+    /// A holding, not watch-only, without a quantity.
+    MissingQuantity(Symbol, Location),
+    /// A date that is not in the form YYYY-MM-DD.
+    InvalidDate(String, Location),
+    /// A symbol listed more than once, for the same account and purchase date.
+    DuplicateSymbol(Symbol, Location),
+    /// Any other problem with an entry, such as a missing field or a value of the wrong
+    /// type.
+    InvalidEntry(String, Location),
+    /// Every problem found when validating a file.
+    Invalid(Vec<ModelError>),
 }
 
+/// Where in a portfolio file a problem was found; the line and column count from 1 and
+/// `entry` describes the entry, for example "holding AMZN".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    pub entry: String,
+}
+
+/// The version of the portfolio file layout written by this release. `read_file` also
+/// reads every earlier layout:
+///
+/// * 0, the layout first documented, with each purchase price in a nested
+///   `[holdings.purchase_price]` table of `major`, `minor` and `currency_code`.
+/// * 1, the layout written before files were versioned, with the purchase price in the
+///   holding itself as `currency_major`, `currency_minor` and `currency_code`.
+/// * 2, the version 1 layout with a `version` key.
+pub const CURRENT_VERSION: u32 = 2;
+
+/// The currency used when the portfolio file, or a price file, does not name one.
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+#[derive(Clone, Default)]
 pub struct Portfolio {
     pub default_currency: Option<Currency>,
+    /// The symbol, for example an index ETF, that performance is compared with.
+    pub benchmark: Option<Symbol>,
     pub items: Vec<Item>,
+    pub transactions: Vec<Transaction>,
+    pub lots: Vec<Lot>,
+    pub realized: Vec<RealizedGain>,
+    pub provider: Option<ProviderConfig>,
+    pub cache: Option<CacheConfig>,
+    pub allocation: Option<Allocation>,
+    pub accounts: Vec<Account>,
+    pub cash: Vec<Cash>,
+    pub actions: Vec<CorporateAction>,
+}
+
+/// A cash balance, held outside the transaction log, in the named account or in none.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cash {
+    pub balance: Money,
+    pub account: Option<String>,
 }
 
+/// A named account; holdings and lots belong to the account named by their `account`
+/// field, and each account may have its own transaction log. Holdings without an account,
+/// and the portfolio's own transactions, are not in any named account.
 #[derive(Clone, Debug)]
+pub struct Account {
+    pub name: String,
+    pub account_type: AccountType,
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccountType {
+    Taxable,
+    TaxDeferred,
+}
+
+/// Configuration that may be shared by all portfolios, read from the user's
+/// configuration file.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Config {
+    pub provider: Option<ProviderConfig>,
+    pub cache: Option<CacheConfig>,
+}
+
+/// Configures the on-disk quote cache, `ttl` is the number of seconds a cached quote
+/// may be used for by each command (e.g. `show`, or `watch`); commands not listed use
+/// `default_ttl`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CacheConfig {
+    pub directory: Option<String>,
+    pub default_ttl: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub ttl: HashMap<String, u64>,
+}
+
+/// Selects, and configures, the quote provider; `symbols` allows a different provider
+/// to be used for specific symbols.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub token: Option<String>,
+    pub endpoint: Option<String>,
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub symbols: HashMap<Symbol, ProviderConfig>,
+}
+
+/// The target allocation of the portfolio; `tolerance` is the drift, in percentage
+/// points, allowed from each target before it is rebalanced.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Allocation {
+    pub tolerance: Option<f64>,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+}
+
+/// A target percentage of the portfolio's value for either a single symbol, or an asset
+/// class made up of `symbols`; trades for an asset class use the first of its symbols.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct Target {
+    pub symbol: Option<Symbol>,
+    pub asset_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symbols: Vec<Symbol>,
+    pub percent: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Item {
     Watch(Symbol),
     Price(Symbol, Holding),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Holding {
     pub quantity: u32,
     pub purchase_price: Money,
     pub purchase_date: Option<Date>,
+    pub classification: Classification,
+}
+
+/// Free-form tags, and structured fields, used to group holdings in allocation reports;
+/// all are optional.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Classification {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Transaction {
+    Buy(Date, Symbol, Trade),
+    Sell(Date, Symbol, Trade),
+    Dividend(Date, Symbol, Money),
+    /// A dividend reinvested (DRIP) in `quantity` whole shares at `price`, it has no cash
+    /// effect.
+    Reinvest(Date, Symbol, Trade),
+    Split(Date, Symbol, Ratio),
+    Fee(Date, Option<Symbol>, Money),
+    Deposit(Date, Money),
+    Withdrawal(Date, Money),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trade {
+    pub quantity: u32,
+    pub price: Money,
+}
+
+/// A split ratio, `to` new shares for every `from` old shares; so a 4:1 split is
+/// `Ratio { to: 4, from: 1 }`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ratio {
+    pub to: u32,
+    pub from: u32,
+}
+
+/// A single purchase of a security, identified by `id`, which is consumed by sales.
+/// `adjusted` is the date of the last corporate action already applied to the lot's
+/// quantity and price, when it was sold after the action; `original` then keeps the
+/// quantity and price as they were before any action was applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lot {
+    pub id: u32,
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub purchase_price: Money,
+    pub purchase_date: Option<Date>,
+    pub classification: Classification,
+    pub adjusted: Option<Date>,
+    pub original: Option<Trade>,
+}
+
+/// A corporate action, applied to the holdings and lots of `symbol` purchased before its
+/// date; the purchase records themselves are never changed.
+#[derive(Clone, Debug)]
+pub enum CorporateAction {
+    /// A split, or reverse split, of `ratio` new shares for old shares.
+    Split(Date, Symbol, Ratio),
+    /// The symbol is changed to the second symbol.
+    SymbolChange(Date, Symbol, Symbol),
+    /// Holders receive `ratio` shares of the second symbol for their shares, which keep
+    /// the rest of the cost basis; the percentage moves to the new shares.
+    SpinOff(Date, Symbol, Symbol, Ratio, f64),
+    /// The symbol is acquired by the second symbol, holders receive `ratio` shares of the
+    /// acquirer and any cash per share, which reduces their cost basis.
+    Merger(Date, Symbol, Symbol, Ratio, Option<Money>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Term {
+    Short,
+    Long,
+    Unknown,
+}
+
+/// The gain, or loss, from selling `quantity` shares out of the lot `lot`.
+#[derive(Clone, Debug)]
+pub struct RealizedGain {
+    pub lot: u32,
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub purchase_price: Money,
+    pub purchase_date: Option<Date>,
+    pub sale_price: Money,
+    pub sale_date: Date,
+    pub account: Option<String>,
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -60,12 +293,116 @@ struct SerializedHolding {
     #[serde(flatten)]
     pub purchase_price: Option<SerializedMoney>,
     pub purchase_date: Option<String>,
+    #[serde(flatten)]
+    pub classification: Classification,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedTransaction {
+    pub date: String,
+    pub action: String,
+    pub symbol: Option<Symbol>,
+    pub quantity: Option<u32>,
+    #[serde(flatten)]
+    pub amount: Option<SerializedMoney>,
+    pub ratio: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedLot {
+    pub id: u32,
+    pub symbol: Symbol,
+    pub quantity: u32,
+    #[serde(flatten)]
+    pub purchase_price: SerializedMoney,
+    pub purchase_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adjusted: Option<String>,
+    #[serde(flatten)]
+    pub classification: Classification,
+    // a table, so it must follow the plain values.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<SerializedTrade>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedTrade {
+    pub quantity: u32,
+    #[serde(flatten)]
+    pub price: SerializedMoney,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedAction {
+    pub date: String,
+    pub action: String,
+    pub symbol: Symbol,
+    pub new_symbol: Option<Symbol>,
+    pub ratio: Option<String>,
+    pub cost_percent: Option<f64>,
+    #[serde(flatten)]
+    pub cash: Option<SerializedMoney>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedRealizedGain {
+    pub lot: u32,
+    pub symbol: Symbol,
+    pub quantity: u32,
+    pub purchase_date: Option<String>,
+    pub sale_date: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub purchase_price: SerializedMoney,
+    pub sale_price: SerializedMoney,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedAccount {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub account_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<SerializedTransaction>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SerializedCash {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(flatten)]
+    pub balance: SerializedMoney,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 struct SerializedPortfolio {
+    #[serde(default)]
+    pub version: Option<u32>,
     pub default_currency: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub benchmark: Option<Symbol>,
+    #[serde(default)]
     holdings: Vec<SerializedHolding>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transactions: Vec<SerializedTransaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lots: Vec<SerializedLot>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    realized: Vec<SerializedRealizedGain>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    cash: Vec<SerializedCash>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<SerializedAction>,
+    provider: Option<ProviderConfig>,
+    cache: Option<CacheConfig>,
+    allocation: Option<Allocation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    accounts: Vec<SerializedAccount>,
+}
+
+/// The lines of a portfolio file, used to find where an entry, or one of its keys, is.
+struct SourceMap<'a> {
+    lines: Vec<&'a str>,
 }
 
 // ------------------------------------------------------------------------------------------------
@@ -76,13 +413,18 @@ pub fn default_file_name() -> String {
     shellexpand::tilde("~/portfolio.toml").to_string()
 }
 
-pub fn read_file(file_name: Option<String>) -> Result<Portfolio, ModelError> {
-    let file_name = file_name.unwrap_or(default_file_name());
-    info!("model::read_file {}", file_name);
+pub fn default_config_file_name() -> String {
+    shellexpand::tilde("~/.config/folio/config.toml").to_string()
+}
+
+/// Read the user's configuration file, if it exists.
+pub fn read_config_file(file_name: Option<String>) -> Result<Config, ModelError> {
+    let file_name = file_name.unwrap_or(default_config_file_name());
+    info!("model::read_config_file {}", file_name);
 
-    let mut f = match File::open(file_name.to_string()) {
+    let mut f = match File::open(&file_name) {
         Ok(handle) => handle,
-        Err(_) => return Err(ModelError::PathError(file_name)),
+        Err(_) => return Ok(Config::default()),
     };
 
     let mut buffer = String::new();
@@ -91,56 +433,251 @@ pub fn read_file(file_name: Option<String>) -> Result<Portfolio, ModelError> {
         Err(err) => return Err(ModelError::FileError(err)),
     };
 
-    let serialized: SerializedPortfolio = match toml::from_str(&buffer.as_str()) {
+    match toml::from_str::<Config>(buffer.as_str()) {
+        Ok(config) => Ok(config),
+        Err(parse_err) => Err(ModelError::ParseError(parse_err)),
+    }
+}
+
+/// Read a portfolio file in any layout, see `CURRENT_VERSION`, validating every entry.
+/// All of the problems found are returned together, in the order they appear in the
+/// file, as `ModelError::Invalid`.
+pub fn read_file(file_name: Option<String>) -> Result<Portfolio, ModelError> {
+    let file_name = file_name.unwrap_or(default_file_name());
+    info!("model::read_file {}", file_name);
+
+    match read_source(file_name) {
+        Ok(source) => read_str(&source),
+        Err(err) => Err(err),
+    }
+}
+
+/// Read a portfolio from the contents of a file, see `read_file`.
+pub fn read_str(source: &str) -> Result<Portfolio, ModelError> {
+    let mut value = parse_source(source)?;
+    let version = layout_version(&value)?;
+    if version < CURRENT_VERSION {
+        info!("model::read_str upgrading layout from version {}", version);
+        upgrade(&mut value, version);
+    }
+
+    let map = SourceMap::new(source);
+    let mut problems: Vec<ModelError> = Vec::new();
+
+    if let Some(toml::Value::String(code)) = value.get("default_currency") {
+        if with_code(code).is_none() {
+            problems.push(ModelError::UnknownCurrency(code.to_string(), map.locate_key("default_currency")));
+        }
+    }
+
+    let items = read_entries(
+        entries(&value, "holdings"),
+        "holdings",
+        0,
+        &map,
+        &mut problems,
+        |index, entry| match entry_str(entry, "symbol") {
+            Some(symbol) => format!("holding {}", symbol),
+            None => format!("holding {}", index + 1),
+        },
+        read_holding,
+    );
+    check_duplicates(entries(&value, "holdings"), &map, &mut problems);
+
+    let transactions = read_entries(
+        entries(&value, "transactions"),
+        "transactions",
+        0,
+        &map,
+        &mut problems,
+        |index, _| format!("transaction {}", index + 1),
+        |transaction, _| read_transaction(transaction),
+    );
+    check_trade_currencies(entries(&value, "transactions"), "transactions", 0, &map, &mut problems, |index| {
+        format!("transaction {}", index + 1)
+    });
+
+    let lots = read_entries(
+        entries(&value, "lots"),
+        "lots",
+        0,
+        &map,
+        &mut problems,
+        |index, entry| match entry.get("id").and_then(|id| id.as_integer()) {
+            Some(id) => format!("lot {}", id),
+            None => format!("lot entry {}", index + 1),
+        },
+        |lot, _| read_lot(lot),
+    );
+    let mut ids: Vec<u32> = Vec::new();
+    for (index, lot) in lots.iter().enumerate() {
+        if ids.contains(&lot.id) {
+            problems.push(ModelError::InvalidEntry(
+                format!("lot id {} is used by an earlier lot", lot.id),
+                map.locate("lots", index, Some("id"), &format!("lot {}", lot.id)),
+            ));
+        }
+        ids.push(lot.id);
+    }
+
+    let realized = read_entries(
+        entries(&value, "realized"),
+        "realized",
+        0,
+        &map,
+        &mut problems,
+        |index, entry| match entry.get("lot").and_then(|lot| lot.as_integer()) {
+            Some(lot) => format!("realized gain on lot {}", lot),
+            None => format!("realized gain {}", index + 1),
+        },
+        |gain, _| read_realized_gain(gain),
+    );
+
+    let cash = read_entries(
+        entries(&value, "cash"),
+        "cash",
+        0,
+        &map,
+        &mut problems,
+        |index, _| format!("cash balance {}", index + 1),
+        |cash, _| read_cash(cash),
+    );
+
+    let actions = read_entries(
+        entries(&value, "actions"),
+        "actions",
+        0,
+        &map,
+        &mut problems,
+        |index, entry| match entry_str(entry, "symbol") {
+            Some(symbol) => format!("action {} on {}", index + 1, symbol),
+            None => format!("action {}", index + 1),
+        },
+        |action, _| read_action(action),
+    );
+    check_duplicate_splits(entries(&value, "actions"), &map, &mut problems, |index, entry| match entry_str(entry, "symbol") {
+        Some(symbol) => format!("action {} on {}", index + 1, symbol),
+        None => format!("action {}", index + 1),
+    });
+    check_split_actions(entries(&value, "transactions"), "transactions", 0, &actions, &map, &mut problems, |index| {
+        format!("transaction {}", index + 1)
+    });
+    check_oversold(entries(&value, "transactions"), "transactions", 0, &actions, &map, &mut problems, |index| {
+        format!("transaction {}", index + 1)
+    });
+
+    // each account's transactions are read separately, so that each can be located.
+    let declared = entries(&value, "accounts");
+    let without_transactions: Vec<toml::Value> = declared
+        .iter()
+        .map(|account| {
+            let mut account = account.clone();
+            if let Some(table) = account.as_table_mut() {
+                table.remove("transactions");
+            }
+            account
+        })
+        .collect();
+    let mut accounts = read_entries(
+        &without_transactions,
+        "accounts",
+        0,
+        &map,
+        &mut problems,
+        |index, entry| match entry_str(entry, "name") {
+            Some(name) => format!("account {}", name),
+            None => format!("account {}", index + 1),
+        },
+        |account, _| read_account(account),
+    );
+    let mut first = 0;
+    let mut names: Vec<String> = Vec::new();
+    for (index, declared) in declared.iter().enumerate() {
+        let name = entry_str(declared, "name").unwrap_or("").to_string();
+        let account_transactions = entries(declared, "transactions");
+        let read = read_entries(
+            account_transactions,
+            "accounts.transactions",
+            first,
+            &map,
+            &mut problems,
+            |index, _| format!("account {} transaction {}", name, index + 1),
+            |transaction, _| read_transaction(transaction),
+        );
+        check_trade_currencies(account_transactions, "accounts.transactions", first, &map, &mut problems, |index| {
+            format!("account {} transaction {}", name, index + 1)
+        });
+        check_split_actions(account_transactions, "accounts.transactions", first, &actions, &map, &mut problems, |index| {
+            format!("account {} transaction {}", name, index + 1)
+        });
+        check_oversold(account_transactions, "accounts.transactions", first, &actions, &map, &mut problems, |index| {
+            format!("account {} transaction {}", name, index + 1)
+        });
+        first += account_transactions.len();
+        if let Some(account) = accounts.get_mut(index) {
+            account.transactions = read;
+        }
+        if names.contains(&name.to_lowercase()) {
+            problems.push(ModelError::InvalidEntry(
+                format!("account {} is declared more than once", name),
+                map.locate("accounts", index, Some("name"), &format!("account {}", name)),
+            ));
+        }
+        names.push(name.to_lowercase());
+    }
+
+    if !problems.is_empty() {
+        problems.sort_by_key(|problem| match problem.location() {
+            Some(location) => (location.line, location.column),
+            None => (0, 0),
+        });
+        return Err(ModelError::Invalid(problems));
+    }
+
+    let serialized: SerializedPortfolio = match value.try_into() {
         Ok(portfolio) => portfolio,
         Err(parse_err) => return Err(ModelError::ParseError(parse_err)),
     };
 
     Ok(Portfolio {
         default_currency: match serialized.default_currency {
-            Some(c) => Some(with_code(&c).unwrap()),
+            Some(c) => with_code(&c),
             None => None
         },
-        items: serialized
-            .holdings
-            .iter()
-            .map(|holding| {
-                if holding.watch_only {
-                    Item::Watch(holding.symbol.to_string())
-                } else {
-                    let purchase_price = holding.clone().purchase_price.unwrap();
-                    let currency: Currency = with_code(&purchase_price.currency_code).unwrap();
-                    let price =
-                        Money::of_major_minor(currency, purchase_price.currency_major, purchase_price.currency_minor);
-                    Item::Price(
-                        holding.symbol.to_string(),
-                        Holding {
-                            quantity: holding.quantity.unwrap(),
-                            purchase_price: price,
-                            purchase_date: match &holding.purchase_date {
-                                Some(date) => match Date::parse_from_str(&date, DATE_FMT) {
-                                    Ok(d) => Some(d),
-                                    _ => None,
-                                },
-                                _ => None,
-                            },
-                        },
-                    )
-                }
-            })
-            .collect(),
+        benchmark: serialized.benchmark,
+        items,
+        transactions,
+        lots,
+        realized,
+        provider: serialized.provider,
+        cache: serialized.cache,
+        allocation: serialized.allocation,
+        accounts,
+        cash,
+        actions,
     })
 }
 
+/// Write the portfolio file; the file is replaced atomically, after the previous version
+/// is kept as a backup, see `storage::write_atomic`.
 pub fn write_file(file_name: Option<String>, portfolio: &Portfolio) -> Result<(), ModelError> {
     let file_name = file_name.unwrap_or(default_file_name());
     info!("model::write_file {}", file_name);
 
+    let toml = write_string(portfolio)?;
+
+    match storage::write_atomic(&file_name, toml.as_bytes()) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(ModelError::FileError(err)),
+    }
+}
+
+/// The contents of the portfolio file for `portfolio`, in the current layout.
+pub fn write_string(portfolio: &Portfolio) -> Result<String, ModelError> {
     let serializable = SerializedPortfolio {
-        default_currency: match portfolio.default_currency {
-            Some(c) => Some(c.code()),
-            None => None
-        },
+        version: Some(CURRENT_VERSION),
+        default_currency: portfolio.default_currency.map(|c| c.code()),
+        benchmark: portfolio.benchmark.clone(),
         holdings: portfolio
             .items
             .iter()
@@ -151,6 +688,7 @@ pub fn write_file(file_name: Option<String>, portfolio: &Portfolio) -> Result<()
                     quantity: None,
                     purchase_price: None,
                     purchase_date: None,
+                    classification: Classification::default(),
                 },
                 Item::Price(symbol, holding) => SerializedHolding {
                     symbol: symbol.to_string(),
@@ -161,26 +699,1020 @@ pub fn write_file(file_name: Option<String>, portfolio: &Portfolio) -> Result<()
                         currency_minor: holding.purchase_price.minor_part(),
                         currency_code: holding.purchase_price.currency.code(),
                     }),
-                    purchase_date: match &holding.purchase_date {
-                        Some(date) => Some(date.format(DATE_FMT).to_string()),
-                        _ => None,
-                    }
+                    purchase_date: holding.purchase_date.as_ref().map(|date| date.format(DATE_FMT).to_string()),
+                    classification: holding.classification.clone(),
                 },
             })
             .collect(),
+        transactions: portfolio.transactions.iter().map(write_transaction).collect(),
+        lots: portfolio.lots.iter().map(write_lot).collect(),
+        realized: portfolio.realized.iter().map(write_realized_gain).collect(),
+        provider: portfolio.provider.clone(),
+        cache: portfolio.cache.clone(),
+        allocation: portfolio.allocation.clone(),
+        accounts: portfolio.accounts.iter().map(write_account).collect(),
+        cash: portfolio.cash.iter().map(write_cash).collect(),
+        actions: portfolio.actions.iter().map(write_action).collect(),
     };
-    let toml = match toml::to_string(&serializable) {
-        Ok(data) => data,
-        Err(err) => return Err(ModelError::WriteError(err)),
+    match toml::to_string(&serializable) {
+        Ok(data) => Ok(data),
+        Err(err) => Err(ModelError::WriteError(err)),
+    }
+}
+
+/// The layout version of a portfolio file, see `CURRENT_VERSION`.
+pub fn file_version(file_name: Option<String>) -> Result<u32, ModelError> {
+    let file_name = file_name.unwrap_or(default_file_name());
+    info!("model::file_version {}", file_name);
+    match read_source(file_name).and_then(|source| parse_source(&source)) {
+        Ok(value) => layout_version(&value),
+        Err(err) => Err(err),
+    }
+}
+
+/// Upgrade a portfolio file written in an earlier layout to `CURRENT_VERSION`, in place,
+/// after copying it to a backup file. Returns the version the file was in and the name
+/// of the backup, or `None` if it was already current and so left unchanged.
+pub fn migrate_file(file_name: Option<String>) -> Result<(u32, Option<String>), ModelError> {
+    let file_name = file_name.unwrap_or(default_file_name());
+    info!("model::migrate_file {}", file_name);
+
+    let version = file_version(Some(file_name.to_string()))?;
+    if version == CURRENT_VERSION {
+        return Ok((version, None));
+    }
+    let portfolio = read_file(Some(file_name.to_string()))?;
+
+    let backup = format!("{}.v{}.bak", file_name, version);
+    match fs::copy(&file_name, &backup) {
+        Ok(_) => (),
+        Err(err) => return Err(ModelError::FileError(err)),
     };
+    match write_file(Some(file_name), &portfolio) {
+        Ok(()) => Ok((version, Some(backup))),
+        Err(err) => Err(err),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl ModelError {
+    /// Where in the file the problem was found, if known.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ModelError::UnknownCurrency(_, location)
+            | ModelError::MissingPrice(_, location)
+            | ModelError::MissingQuantity(_, location)
+            | ModelError::InvalidDate(_, location)
+            | ModelError::DuplicateSymbol(_, location)
+            | ModelError::InvalidEntry(_, location) => Some(location),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::PathError(name) => write!(f, "no such file {}", name),
+            ModelError::FileError(err) => write!(f, "file error: {}", err),
+            ModelError::ParseError(err) => write!(f, "{}", err),
+            ModelError::WriteError(err) => write!(f, "could not write the portfolio: {}", err),
+            ModelError::CurrencyError(code) => write!(f, "unknown currency code \"{}\"", code),
+            ModelError::TransactionError(message)
+            | ModelError::LotError(message)
+            | ModelError::AccountError(message)
+            | ModelError::ActionError(message)
+            | ModelError::VersionError(message) => write!(f, "{}", message),
+            ModelError::UnknownCurrency(code, location) =>
+                write!(f, "{}: unknown currency code \"{}\"", location, code),
+            ModelError::MissingPrice(symbol, location) => write!(
+                f,
+                "{}: {} is not watch-only so requires a purchase price (currency_major, currency_minor and currency_code)",
+                location, symbol),
+            ModelError::MissingQuantity(symbol, location) =>
+                write!(f, "{}: {} is not watch-only so requires a quantity", location, symbol),
+            ModelError::InvalidDate(date, location) =>
+                write!(f, "{}: could not parse date \"{}\", expected YYYY-MM-DD", location, date),
+            ModelError::DuplicateSymbol(symbol, location) => write!(
+                f,
+                "{}: {} is listed more than once for the same account and purchase date",
+                location, symbol),
+            ModelError::InvalidEntry(message, location) => write!(f, "{}: {}", location, message),
+            ModelError::Invalid(problems) => write!(
+                f,
+                "{}",
+                problems
+                    .iter()
+                    .map(|problem| problem.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.entry)
+    }
+}
 
-    let mut f = match File::create(file_name.to_string()) {
+impl<'a> SourceMap<'a> {
+    fn new(source: &'a str) -> Self {
+        SourceMap { lines: source.lines().collect() }
+    }
+
+    /// The location of a top-level `key`, or the start of the file.
+    fn locate_key(&self, key: &str) -> Location {
+        let end = self.lines.iter().position(|line| is_header(line)).unwrap_or(self.lines.len());
+        match self.find_key(0, end, key) {
+            Some((line, column)) => Location { line, column, entry: key.to_string() },
+            None => Location { line: 1, column: 1, entry: key.to_string() },
+        }
+    }
+
+    /// The location of `key` in the `index`th entry of the array of tables `section`, or
+    /// of the entry's header if `key` is `None` or can't be found. A key in a sub-table
+    /// of the entry is named with its path, for example "sale_price.currency_code".
+    fn locate(&self, section: &str, index: usize, key: Option<&str>, entry: &str) -> Location {
+        let header = format!("[[{}]]", section);
+        let start = match self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| without_comment(line) == header)
+            .map(|(number, _)| number)
+            .nth(index)
+        {
+            Some(start) => start,
+            // entries written as inline tables have no header.
+            None => return Location { line: 1, column: 1, entry: entry.to_string() },
+        };
+        let end = self
+            .lines
+            .iter()
+            .enumerate()
+            .skip(start + 1)
+            .find(|(_, line)| without_comment(line).starts_with("[["))
+            .map(|(number, _)| number)
+            .unwrap_or(self.lines.len());
+        let found = match key {
+            Some(key) => match key.rfind('.') {
+                Some(dot) => {
+                    let table = format!("[{}.{}]", section, &key[..dot]);
+                    match (start + 1..end).find(|number| without_comment(self.lines[*number]) == table) {
+                        Some(table_start) => self.find_key(table_start + 1, end, &key[dot + 1..]),
+                        None => None,
+                    }
+                }
+                None => self.find_key(start + 1, end, key),
+            },
+            None => None,
+        };
+        let (line, column) = found.unwrap_or((start + 1, self.lines[start].find('[').unwrap_or(0) + 1));
+        Location { line, column, entry: entry.to_string() }
+    }
+
+    /// The line and column of the value of `key` between the lines `from` and `to`,
+    /// stopping at the first table header.
+    fn find_key(&self, from: usize, to: usize, key: &str) -> Option<(usize, usize)> {
+        for number in from..to {
+            let line = self.lines[number];
+            if is_header(line) {
+                return None;
+            }
+            if let Some(equals) = line.find('=') {
+                if line[..equals].trim().trim_matches('"') == key {
+                    let value = line[equals + 1..].len() - line[equals + 1..].trim_start().len();
+                    return Some((number + 1, line[..equals + 1 + value].chars().count() + 1));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Transaction {
+    pub fn date(&self) -> Date {
+        match self {
+            Transaction::Buy(d, _, _)
+            | Transaction::Sell(d, _, _)
+            | Transaction::Dividend(d, _, _)
+            | Transaction::Reinvest(d, _, _)
+            | Transaction::Split(d, _, _)
+            | Transaction::Fee(d, _, _)
+            | Transaction::Deposit(d, _)
+            | Transaction::Withdrawal(d, _) => *d,
+        }
+    }
+
+    pub fn symbol(&self) -> Option<&Symbol> {
+        match self {
+            Transaction::Buy(_, s, _)
+            | Transaction::Sell(_, s, _)
+            | Transaction::Dividend(_, s, _)
+            | Transaction::Reinvest(_, s, _)
+            | Transaction::Split(_, s, _)
+            | Transaction::Fee(_, Some(s), _) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl Portfolio {
+    /// The portfolio's default currency, or `DEFAULT_CURRENCY` if it has none.
+    pub fn currency(&self) -> Currency {
+        self.default_currency
+            .unwrap_or_else(|| with_code(DEFAULT_CURRENCY).unwrap())
+    }
+
+    /// The next unused lot identifier.
+    pub fn next_lot_id(&self) -> u32 {
+        self.lots.iter().map(|lot| lot.id).max().unwrap_or(0) + 1
+    }
+}
+
+impl CorporateAction {
+    pub fn date(&self) -> Date {
+        match self {
+            CorporateAction::Split(d, _, _)
+            | CorporateAction::SymbolChange(d, _, _)
+            | CorporateAction::SpinOff(d, _, _, _, _)
+            | CorporateAction::Merger(d, _, _, _, _) => *d,
+        }
+    }
+
+    /// The symbol whose holders are affected.
+    pub fn symbol(&self) -> &Symbol {
+        match self {
+            CorporateAction::Split(_, s, _)
+            | CorporateAction::SymbolChange(_, s, _)
+            | CorporateAction::SpinOff(_, s, _, _, _)
+            | CorporateAction::Merger(_, s, _, _, _) => s,
+        }
+    }
+}
+
+impl Target {
+    /// The symbol, or asset class, this target is for.
+    pub fn name(&self) -> String {
+        match (&self.symbol, &self.asset_class) {
+            (Some(symbol), _) => symbol.to_string(),
+            (None, Some(asset_class)) => asset_class.to_string(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The symbols whose value counts towards this target.
+    pub fn members(&self) -> Vec<Symbol> {
+        match &self.symbol {
+            Some(symbol) => vec![symbol.to_uppercase()],
+            None => self.symbols.iter().map(|s| s.to_uppercase()).collect(),
+        }
+    }
+}
+
+impl RealizedGain {
+    /// The gain, or loss, or `None` if the sale and purchase prices are in different
+    /// currencies.
+    pub fn gain(&self) -> Option<Money> {
+        if self.sale_price.currency == self.purchase_price.currency {
+            Some(money_change_times(self.purchase_price, self.sale_price, self.quantity))
+        } else {
+            None
+        }
+    }
+
+    pub fn term(&self) -> Term {
+        match self.purchase_date {
+            Some(purchase_date) => term(purchase_date, self.sale_date),
+            None => Term::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for AccountType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AccountType::Taxable => ACCOUNT_TAXABLE,
+            AccountType::TaxDeferred => ACCOUNT_TAX_DEFERRED,
+        })
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Term::Short => "short",
+            Term::Long => "long",
+            Term::Unknown => "unknown",
+        })
+    }
+}
+
+impl Ratio {
+    pub fn parse(s: &str) -> Option<Ratio> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() == 2 {
+            match (parts[0].trim().parse::<u32>(), parts[1].trim().parse::<u32>()) {
+                (Ok(to), Ok(from)) if to > 0 && from > 0 => Some(Ratio { to, from }),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub fn apply(&self, quantity: u32) -> u32 {
+        (quantity as u64 * self.to as u64 / self.from as u64) as u32
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.to, self.from)
+    }
+}
+
+/// Classify a holding period; long-term if the sale is more than one year after the
+/// purchase.
+pub fn term(purchase_date: Date, sale_date: Date) -> Term {
+    let anniversary = match purchase_date.with_year(purchase_date.year() + 1) {
+        Some(date) => date,
+        // purchased on February 29th
+        None => purchase_date + Duration::days(365),
+    };
+    if sale_date > anniversary {
+        Term::Long
+    } else {
+        Term::Short
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn read_source(file_name: String) -> Result<String, ModelError> {
+    let mut f = match File::open(&file_name) {
         Ok(handle) => handle,
         Err(_) => return Err(ModelError::PathError(file_name)),
     };
 
-    match f.write_all(toml.as_bytes()) {
-        Ok(()) => Ok(()),
+    let mut buffer = String::new();
+    match f.read_to_string(&mut buffer) {
+        Ok(_) => Ok(buffer),
         Err(err) => Err(ModelError::FileError(err)),
     }
 }
+
+fn parse_source(source: &str) -> Result<toml::Value, ModelError> {
+    match toml::from_str(source) {
+        Ok(value) => Ok(value),
+        Err(parse_err) => Err(ModelError::ParseError(parse_err)),
+    }
+}
+
+/// The `version` key, or for unversioned files 0 if any holding has a nested
+/// `purchase_price` table and 1 otherwise.
+fn layout_version(value: &toml::Value) -> Result<u32, ModelError> {
+    match value.get("version") {
+        Some(toml::Value::Integer(version)) if *version >= 0 && *version <= CURRENT_VERSION as i64 =>
+            Ok(*version as u32),
+        Some(toml::Value::Integer(version)) if *version > CURRENT_VERSION as i64 => Err(ModelError::VersionError(
+            format!("file version {} is newer than this release supports ({})", version, CURRENT_VERSION))),
+        Some(other) => Err(ModelError::VersionError(format!("invalid file version {}", other))),
+        None => {
+            let nested = match value.get("holdings").and_then(|h| h.as_array()) {
+                Some(holdings) => holdings
+                    .iter()
+                    .any(|holding| matches!(holding.get("purchase_price"), Some(toml::Value::Table(_)))),
+                None => false,
+            };
+            Ok(if nested { 0 } else { 1 })
+        }
+    }
+}
+
+/// Rewrite an earlier layout as the current one; only version 0 differs in more than
+/// the `version` key.
+fn upgrade(value: &mut toml::Value, version: u32) {
+    if version == 0 {
+        let holdings = match value.get_mut("holdings").and_then(|h| h.as_array_mut()) {
+            Some(holdings) => holdings,
+            None => return,
+        };
+        for holding in holdings.iter_mut() {
+            if let Some(holding) = holding.as_table_mut() {
+                if let Some(toml::Value::Table(price)) = holding.remove("purchase_price") {
+                    for (key, part) in price {
+                        let key = match key.as_str() {
+                            "major" => "currency_major".to_string(),
+                            "minor" => "currency_minor".to_string(),
+                            _ => key,
+                        };
+                        holding.insert(key, part);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_header(line: &str) -> bool {
+    line.trim_start().starts_with('[')
+}
+
+fn without_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(comment) => line[..comment].trim(),
+        None => line.trim(),
+    }
+}
+
+/// The entries of the array `key` in `value`, if any.
+fn entries<'a>(value: &'a toml::Value, key: &str) -> &'a [toml::Value] {
+    match value.get(key).and_then(|entries| entries.as_array()) {
+        Some(entries) => entries.as_slice(),
+        None => &[],
+    }
+}
+
+fn entry_str<'a>(entry: &'a toml::Value, key: &str) -> Option<&'a str> {
+    entry.get(key).and_then(|value| value.as_str())
+}
+
+/// Read each of `entries`, the array of tables `section` starting with its `first`th
+/// entry, adding every problem found to `problems`. Entries with problems are left out.
+fn read_entries<S, T, L, F>(
+    entries: &[toml::Value],
+    section: &str,
+    first: usize,
+    map: &SourceMap,
+    problems: &mut Vec<ModelError>,
+    label: L,
+    read: F,
+) -> Vec<T>
+where
+    S: DeserializeOwned,
+    L: Fn(usize, &toml::Value) -> String,
+    F: Fn(&S, &dyn Fn(Option<&str>) -> Location) -> Result<T, ModelError>,
+{
+    let mut results: Vec<T> = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let name = label(index, entry);
+        let at = |key: Option<&str>| map.locate(section, first + index, key, &name);
+        let found = problems.len();
+        check_fields(entry, "", &at, problems);
+        if problems.len() > found {
+            continue;
+        }
+        let serialized: S = match entry.clone().try_into() {
+            Ok(serialized) => serialized,
+            Err(err) => {
+                problems.push(ModelError::InvalidEntry(err.to_string(), at(None)));
+                continue;
+            }
+        };
+        match read(&serialized, &at) {
+            Ok(result) => results.push(result),
+            Err(ModelError::Invalid(all)) => problems.extend(all),
+            Err(err) => problems.push(located(err, at(None))),
+        }
+    }
+    results
+}
+
+const DATE_KEYS: [&str; 4] = ["date", "purchase_date", "sale_date", "adjusted"];
+
+/// Check the dates and currency codes of an entry, and of its sub-tables.
+fn check_fields(
+    entry: &toml::Value,
+    path: &str,
+    at: &dyn Fn(Option<&str>) -> Location,
+    problems: &mut Vec<ModelError>,
+) {
+    if let Some(table) = entry.as_table() {
+        for (key, value) in table {
+            let key_path = if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+            match value {
+                toml::Value::String(date) if DATE_KEYS.contains(&key.as_str())
+                    && Date::parse_from_str(date, DATE_FMT).is_err() => {
+                        problems.push(ModelError::InvalidDate(date.to_string(), at(Some(&key_path))));
+                    }
+                toml::Value::String(code) if key == "currency_code"
+                    && with_code(code).is_none() => {
+                        problems.push(ModelError::UnknownCurrency(code.to_string(), at(Some(&key_path))));
+                    }
+                toml::Value::Table(_) => check_fields(value, &key_path, at, problems),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Give an error from one of the `read_` functions a location.
+fn located(err: ModelError, location: Location) -> ModelError {
+    match err {
+        ModelError::CurrencyError(code) => ModelError::UnknownCurrency(code, location),
+        ModelError::TransactionError(message)
+        | ModelError::LotError(message)
+        | ModelError::AccountError(message)
+        | ModelError::ActionError(message) => ModelError::InvalidEntry(message, location),
+        err => err,
+    }
+}
+
+/// Holdings are duplicates if they are for the same symbol, account and purchase date,
+/// or are both watch-only.
+fn check_duplicates(holdings: &[toml::Value], map: &SourceMap, problems: &mut Vec<ModelError>) {
+    let mut seen: Vec<(String, bool, Option<&str>, Option<&str>)> = Vec::new();
+    for (index, holding) in holdings.iter().enumerate() {
+        let symbol = match entry_str(holding, "symbol") {
+            Some(symbol) => symbol,
+            None => continue,
+        };
+        let watch_only = holding.get("watch_only").and_then(|w| w.as_bool()).unwrap_or(false);
+        let key = if watch_only {
+            (symbol.to_uppercase(), true, None, None)
+        } else {
+            (symbol.to_uppercase(), false, entry_str(holding, "account"), entry_str(holding, "purchase_date"))
+        };
+        if seen.contains(&key) {
+            problems.push(ModelError::DuplicateSymbol(
+                symbol.to_string(),
+                map.locate("holdings", index, Some("symbol"), &format!("holding {}", symbol)),
+            ));
+        } else {
+            seen.push(key);
+        }
+    }
+}
+
+/// A symbol's position in a transaction log has one cost basis, so every buy, reinvested
+/// dividend and sale of the symbol must be in the currency it was first bought in.
+fn check_trade_currencies<L>(
+    transactions: &[toml::Value],
+    section: &str,
+    first: usize,
+    map: &SourceMap,
+    problems: &mut Vec<ModelError>,
+    label: L,
+) where
+    L: Fn(usize) -> String,
+{
+    let mut seen: HashMap<String, String> = HashMap::new();
+    for (index, transaction) in transactions.iter().enumerate() {
+        let action = entry_str(transaction, "action").unwrap_or("").to_lowercase();
+        if ![ACTION_BUY, ACTION_REINVEST, ACTION_SELL].contains(&action.as_str()) {
+            continue;
+        }
+        let (symbol, code) = match (entry_str(transaction, "symbol"), entry_str(transaction, "currency_code")) {
+            (Some(symbol), Some(code)) => (symbol.to_uppercase(), code.to_uppercase()),
+            _ => continue,
+        };
+        match seen.get(&symbol) {
+            Some(held) if *held != code => problems.push(ModelError::InvalidEntry(
+                format!("{} {} is in {}, but it was first bought in {}", action, symbol, code, held),
+                map.locate(section, first + index, Some("currency_code"), &label(index)),
+            )),
+            Some(_) => (),
+            None => {
+                seen.insert(symbol, code);
+            }
+        }
+    }
+}
+
+/// Replaying a transaction log, with the portfolio's actions, must never sell more shares
+/// than are held at the time; entries that can't be read are left out of the replay.
+fn check_oversold<L>(
+    transactions: &[toml::Value],
+    section: &str,
+    first: usize,
+    actions: &[CorporateAction],
+    map: &SourceMap,
+    problems: &mut Vec<ModelError>,
+    label: L,
+) where
+    L: Fn(usize) -> String,
+{
+    let read: Vec<(usize, Transaction)> = transactions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let serialized: SerializedTransaction = entry.clone().try_into().ok()?;
+            read_transaction(&serialized).ok().map(|transaction| (index, transaction))
+        })
+        .collect();
+    let replayed: Vec<Transaction> = read.iter().map(|(_, transaction)| transaction.clone()).collect();
+    for (sold, held) in ledger::replay_with_actions(&replayed, actions, None).oversold {
+        let (index, transaction) = &read[sold];
+        if let Transaction::Sell(date, symbol, trade) = transaction {
+            problems.push(ModelError::InvalidEntry(
+                format!(
+                    "sell of {} {} on {} is more than the {} held",
+                    trade.quantity,
+                    symbol,
+                    date.format(DATE_FMT),
+                    held
+                ),
+                map.locate(section, first + index, Some("quantity"), &label(*index)),
+            ));
+        }
+    }
+}
+
+/// A split is applied both from a transaction log and from the portfolio's actions, so
+/// the same split must not be recorded in both.
+fn check_split_actions<L>(
+    transactions: &[toml::Value],
+    section: &str,
+    first: usize,
+    actions: &[CorporateAction],
+    map: &SourceMap,
+    problems: &mut Vec<ModelError>,
+    label: L,
+) where
+    L: Fn(usize) -> String,
+{
+    let mut seen: Vec<(String, Date)> = Vec::new();
+    for (index, transaction) in transactions.iter().enumerate() {
+        let (symbol, date) = match split_entry(transaction, &[ACTION_SPLIT]) {
+            Some(split) => split,
+            None => continue,
+        };
+        let recorded = actions.iter().any(|action| match action {
+            CorporateAction::Split(d, s, _) => *d == date && s.to_uppercase() == symbol,
+            _ => false,
+        });
+        let problem = if recorded {
+            Some(format!("split {} on {} is also recorded in actions, it would be applied twice", symbol, date.format(DATE_FMT)))
+        } else if seen.contains(&(symbol.to_string(), date)) {
+            Some(format!("split {} on {} is recorded more than once, it would be applied twice", symbol, date.format(DATE_FMT)))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            problems.push(ModelError::InvalidEntry(problem, map.locate(section, first + index, Some("action"), &label(index))));
+        }
+        seen.push((symbol, date));
+    }
+}
+
+/// Report a split, or reverse split, action recorded more than once for the same symbol
+/// and date.
+fn check_duplicate_splits<L>(actions: &[toml::Value], map: &SourceMap, problems: &mut Vec<ModelError>, label: L)
+where
+    L: Fn(usize, &toml::Value) -> String,
+{
+    let mut seen: Vec<(String, Date)> = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let (symbol, date) = match split_entry(action, &[ACTION_SPLIT, ACTION_REVERSE_SPLIT]) {
+            Some(split) => split,
+            None => continue,
+        };
+        if seen.contains(&(symbol.to_string(), date)) {
+            problems.push(ModelError::InvalidEntry(
+                format!("split {} on {} is recorded more than once, it would be applied twice", symbol, date.format(DATE_FMT)),
+                map.locate("actions", index, Some("action"), &label(index, action)),
+            ));
+        }
+        seen.push((symbol, date));
+    }
+}
+
+/// The upper-case symbol and date of an entry whose `action` is one of `kinds`.
+fn split_entry(entry: &toml::Value, kinds: &[&str]) -> Option<(String, Date)> {
+    let action = entry_str(entry, "action").unwrap_or("").to_lowercase();
+    if !kinds.contains(&action.as_str()) {
+        return None;
+    }
+    match (entry_str(entry, "symbol"), entry_str(entry, "date")) {
+        (Some(symbol), Some(date)) => Date::parse_from_str(date, DATE_FMT).ok().map(|date| (symbol.to_uppercase(), date)),
+        _ => None,
+    }
+}
+
+fn read_holding(holding: &SerializedHolding, at: &dyn Fn(Option<&str>) -> Location) -> Result<Item, ModelError> {
+    if holding.watch_only {
+        return Ok(Item::Watch(holding.symbol.to_string()));
+    }
+    let mut problems: Vec<ModelError> = Vec::new();
+    let purchase_price = match &holding.purchase_price {
+        Some(price) => match read_money(price) {
+            Ok(price) => Some(price),
+            Err(err) => {
+                problems.push(located(err, at(Some("currency_code"))));
+                None
+            }
+        },
+        None => {
+            problems.push(ModelError::MissingPrice(holding.symbol.to_string(), at(None)));
+            None
+        }
+    };
+    if holding.quantity.is_none() {
+        problems.push(ModelError::MissingQuantity(holding.symbol.to_string(), at(None)));
+    }
+    match (purchase_price, holding.quantity) {
+        (Some(purchase_price), Some(quantity)) => Ok(Item::Price(
+            holding.symbol.to_string(),
+            Holding {
+                quantity,
+                purchase_price,
+                purchase_date: read_date(&holding.purchase_date),
+                classification: holding.classification.clone(),
+            },
+        )),
+        _ => Err(ModelError::Invalid(problems)),
+    }
+}
+
+const ACTION_BUY: &str = "buy";
+const ACTION_SELL: &str = "sell";
+const ACTION_DIVIDEND: &str = "dividend";
+const ACTION_REINVEST: &str = "reinvest";
+const ACTION_SPLIT: &str = "split";
+const ACTION_FEE: &str = "fee";
+const ACTION_DEPOSIT: &str = "deposit";
+const ACTION_WITHDRAWAL: &str = "withdrawal";
+
+fn read_money(money: &SerializedMoney) -> Result<Money, ModelError> {
+    match with_code(&money.currency_code) {
+        Some(currency) => Ok(Money::of_major_minor(currency, money.currency_major, money.currency_minor)),
+        None => Err(ModelError::CurrencyError(money.currency_code.to_string())),
+    }
+}
+
+fn write_money(money: &Money) -> SerializedMoney {
+    SerializedMoney {
+        currency_major: money.major_part(),
+        currency_minor: money.minor_part(),
+        currency_code: money.currency.code(),
+    }
+}
+
+fn read_transaction(transaction: &SerializedTransaction) -> Result<Transaction, ModelError> {
+    let date = match Date::parse_from_str(&transaction.date, DATE_FMT) {
+        Ok(d) => d,
+        Err(_) => return Err(ModelError::TransactionError(
+            format!("could not parse date {}", transaction.date))),
+    };
+    let symbol = || match &transaction.symbol {
+        Some(s) => Ok(s.to_string()),
+        None => Err(ModelError::TransactionError(
+            format!("{} on {} requires a symbol", transaction.action, transaction.date))),
+    };
+    let amount = || match &transaction.amount {
+        Some(m) => read_money(m),
+        None => Err(ModelError::TransactionError(
+            format!("{} on {} requires an amount", transaction.action, transaction.date))),
+    };
+    let trade = || match transaction.quantity {
+        Some(quantity) => Ok(Trade { quantity, price: amount()? }),
+        None => Err(ModelError::TransactionError(
+            format!("{} on {} requires a quantity", transaction.action, transaction.date))),
+    };
+    match transaction.action.to_lowercase().as_str() {
+        ACTION_BUY => Ok(Transaction::Buy(date, symbol()?, trade()?)),
+        ACTION_SELL => Ok(Transaction::Sell(date, symbol()?, trade()?)),
+        ACTION_DIVIDEND => Ok(Transaction::Dividend(date, symbol()?, amount()?)),
+        ACTION_REINVEST => Ok(Transaction::Reinvest(date, symbol()?, trade()?)),
+        ACTION_SPLIT => match transaction.ratio.as_ref().and_then(|r| Ratio::parse(r)) {
+            Some(ratio) => Ok(Transaction::Split(date, symbol()?, ratio)),
+            None => Err(ModelError::TransactionError(
+                format!("split on {} requires a ratio such as \"4:1\"", transaction.date))),
+        },
+        ACTION_FEE => Ok(Transaction::Fee(date, transaction.symbol.clone(), amount()?)),
+        ACTION_DEPOSIT => Ok(Transaction::Deposit(date, amount()?)),
+        ACTION_WITHDRAWAL => Ok(Transaction::Withdrawal(date, amount()?)),
+        action => Err(ModelError::TransactionError(format!("unknown action {}", action))),
+    }
+}
+
+fn write_transaction(transaction: &Transaction) -> SerializedTransaction {
+    let (action, symbol, quantity, amount, ratio) = match transaction {
+        Transaction::Buy(_, s, t) =>
+            (ACTION_BUY, Some(s.to_string()), Some(t.quantity), Some(write_money(&t.price)), None),
+        Transaction::Sell(_, s, t) =>
+            (ACTION_SELL, Some(s.to_string()), Some(t.quantity), Some(write_money(&t.price)), None),
+        Transaction::Dividend(_, s, m) =>
+            (ACTION_DIVIDEND, Some(s.to_string()), None, Some(write_money(m)), None),
+        Transaction::Reinvest(_, s, t) =>
+            (ACTION_REINVEST, Some(s.to_string()), Some(t.quantity), Some(write_money(&t.price)), None),
+        Transaction::Split(_, s, r) =>
+            (ACTION_SPLIT, Some(s.to_string()), None, None, Some(r.to_string())),
+        Transaction::Fee(_, s, m) =>
+            (ACTION_FEE, s.clone(), None, Some(write_money(m)), None),
+        Transaction::Deposit(_, m) =>
+            (ACTION_DEPOSIT, None, None, Some(write_money(m)), None),
+        Transaction::Withdrawal(_, m) =>
+            (ACTION_WITHDRAWAL, None, None, Some(write_money(m)), None),
+    };
+    SerializedTransaction {
+        date: transaction.date().format(DATE_FMT).to_string(),
+        action: action.to_string(),
+        symbol,
+        quantity,
+        amount,
+        ratio,
+    }
+}
+
+fn read_date(date: &Option<String>) -> Option<Date> {
+    match date {
+        Some(date) => Date::parse_from_str(date, DATE_FMT).ok(),
+        _ => None,
+    }
+}
+
+fn write_date(date: &Option<Date>) -> Option<String> {
+    date.as_ref().map(|date| date.format(DATE_FMT).to_string())
+}
+
+fn read_lot(lot: &SerializedLot) -> Result<Lot, ModelError> {
+    Ok(Lot {
+        id: lot.id,
+        symbol: lot.symbol.to_string(),
+        quantity: lot.quantity,
+        purchase_price: read_money(&lot.purchase_price)?,
+        purchase_date: read_date(&lot.purchase_date),
+        classification: lot.classification.clone(),
+        adjusted: read_date(&lot.adjusted),
+        original: match &lot.original {
+            Some(original) => Some(Trade {
+                quantity: original.quantity,
+                price: read_money(&original.price)?,
+            }),
+            None => None,
+        },
+    })
+}
+
+fn write_lot(lot: &Lot) -> SerializedLot {
+    SerializedLot {
+        id: lot.id,
+        symbol: lot.symbol.to_string(),
+        quantity: lot.quantity,
+        purchase_price: write_money(&lot.purchase_price),
+        purchase_date: write_date(&lot.purchase_date),
+        adjusted: write_date(&lot.adjusted),
+        classification: lot.classification.clone(),
+        original: lot.original.as_ref().map(|original| SerializedTrade {
+            quantity: original.quantity,
+            price: write_money(&original.price),
+        }),
+    }
+}
+
+const ACTION_SYMBOL_CHANGE: &str = "symbol-change";
+const ACTION_SPIN_OFF: &str = "spin-off";
+const ACTION_MERGER: &str = "merger";
+const ACTION_REVERSE_SPLIT: &str = "reverse-split";
+
+fn read_action(action: &SerializedAction) -> Result<CorporateAction, ModelError> {
+    let date = match Date::parse_from_str(&action.date, DATE_FMT) {
+        Ok(d) => d,
+        Err(_) => return Err(ModelError::ActionError(
+            format!("could not parse date {}", action.date))),
+    };
+    let symbol = action.symbol.to_uppercase();
+    let new_symbol = || match &action.new_symbol {
+        Some(s) => Ok(s.to_uppercase()),
+        None => Err(ModelError::ActionError(
+            format!("{} of {} on {} requires a new_symbol", action.action, action.symbol, action.date))),
+    };
+    let ratio = || match action.ratio.as_ref().and_then(|r| Ratio::parse(r)) {
+        Some(ratio) => Ok(ratio),
+        None => Err(ModelError::ActionError(
+            format!("{} of {} on {} requires a ratio such as \"4:1\"", action.action, action.symbol, action.date))),
+    };
+    match action.action.to_lowercase().as_str() {
+        ACTION_SPLIT => Ok(CorporateAction::Split(date, symbol, ratio()?)),
+        ACTION_REVERSE_SPLIT => {
+            // either "1:4" or "4:1" is a reverse split, fewer new shares than old.
+            let ratio = ratio()?;
+            Ok(CorporateAction::Split(date, symbol, Ratio {
+                to: ratio.to.min(ratio.from),
+                from: ratio.to.max(ratio.from),
+            }))
+        }
+        ACTION_SYMBOL_CHANGE => Ok(CorporateAction::SymbolChange(date, symbol, new_symbol()?)),
+        ACTION_SPIN_OFF => match action.cost_percent {
+            Some(percent) if (0.0..=100.0).contains(&percent) =>
+                Ok(CorporateAction::SpinOff(date, symbol, new_symbol()?, ratio()?, percent)),
+            _ => Err(ModelError::ActionError(
+                format!("spin-off of {} on {} requires a cost_percent from 0 to 100", action.symbol, action.date))),
+        },
+        ACTION_MERGER => Ok(CorporateAction::Merger(
+            date,
+            symbol,
+            new_symbol()?,
+            ratio()?,
+            match &action.cash {
+                Some(cash) => Some(read_money(cash)?),
+                None => None,
+            })),
+        other => Err(ModelError::ActionError(format!("unknown corporate action {}", other))),
+    }
+}
+
+fn write_action(action: &CorporateAction) -> SerializedAction {
+    let (name, new_symbol, ratio, cost_percent, cash) = match action {
+        CorporateAction::Split(_, _, r) => (ACTION_SPLIT, None, Some(r.to_string()), None, None),
+        CorporateAction::SymbolChange(_, _, n) => (ACTION_SYMBOL_CHANGE, Some(n.to_string()), None, None, None),
+        CorporateAction::SpinOff(_, _, n, r, p) =>
+            (ACTION_SPIN_OFF, Some(n.to_string()), Some(r.to_string()), Some(*p), None),
+        CorporateAction::Merger(_, _, n, r, c) =>
+            (ACTION_MERGER, Some(n.to_string()), Some(r.to_string()), None, c.as_ref().map(write_money)),
+    };
+    SerializedAction {
+        date: action.date().format(DATE_FMT).to_string(),
+        action: name.to_string(),
+        symbol: action.symbol().to_string(),
+        new_symbol,
+        ratio,
+        cost_percent,
+        cash,
+    }
+}
+
+const ACCOUNT_TAXABLE: &str = "taxable";
+const ACCOUNT_TAX_DEFERRED: &str = "tax-deferred";
+
+fn read_account(account: &SerializedAccount) -> Result<Account, ModelError> {
+    let transactions: Result<Vec<Transaction>, ModelError> = account
+        .transactions
+        .iter()
+        .map(read_transaction)
+        .collect();
+    Ok(Account {
+        name: account.name.to_string(),
+        account_type: match &account.account_type {
+            Some(account_type) => match account_type.to_lowercase().as_str() {
+                ACCOUNT_TAXABLE => AccountType::Taxable,
+                ACCOUNT_TAX_DEFERRED | "tax_deferred" => AccountType::TaxDeferred,
+                other => return Err(ModelError::AccountError(
+                    format!("unknown type {} for account {}", other, account.name))),
+            },
+            None => AccountType::Taxable,
+        },
+        transactions: transactions?,
+    })
+}
+
+fn write_account(account: &Account) -> SerializedAccount {
+    SerializedAccount {
+        name: account.name.to_string(),
+        account_type: Some(account.account_type.to_string()),
+        transactions: account.transactions.iter().map(write_transaction).collect(),
+    }
+}
+
+fn read_cash(cash: &SerializedCash) -> Result<Cash, ModelError> {
+    Ok(Cash {
+        balance: read_money(&cash.balance)?,
+        account: cash.account.clone(),
+    })
+}
+
+fn write_cash(cash: &Cash) -> SerializedCash {
+    SerializedCash {
+        account: cash.account.clone(),
+        balance: write_money(&cash.balance),
+    }
+}
+
+fn read_realized_gain(gain: &SerializedRealizedGain) -> Result<RealizedGain, ModelError> {
+    let sale_date = match Date::parse_from_str(&gain.sale_date, DATE_FMT) {
+        Ok(d) => d,
+        Err(_) => return Err(ModelError::LotError(
+            format!("could not parse sale date {} for lot {}", gain.sale_date, gain.lot))),
+    };
+    Ok(RealizedGain {
+        lot: gain.lot,
+        symbol: gain.symbol.to_string(),
+        quantity: gain.quantity,
+        purchase_price: read_money(&gain.purchase_price)?,
+        purchase_date: read_date(&gain.purchase_date),
+        sale_price: read_money(&gain.sale_price)?,
+        sale_date,
+        account: gain.account.clone(),
+    })
+}
+
+fn write_realized_gain(gain: &RealizedGain) -> SerializedRealizedGain {
+    SerializedRealizedGain {
+        lot: gain.lot,
+        symbol: gain.symbol.to_string(),
+        quantity: gain.quantity,
+        purchase_date: write_date(&gain.purchase_date),
+        sale_date: gain.sale_date.format(DATE_FMT).to_string(),
+        account: gain.account.clone(),
+        purchase_price: write_money(&gain.purchase_price),
+        sale_price: write_money(&gain.sale_price),
+    }
+}
@@ -0,0 +1,487 @@
+use fin_model::prelude::*;
+use fin_model::quote::FetchPriceQuote;
+
+use crate::display::{money_f64, money_times};
+use crate::history::{held_on, price_on, valuations, FetchPriceHistory};
+use crate::ledger;
+use crate::model::{Item, Portfolio, Transaction};
+use crate::report::{OutputFormat, Report, Value};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// Money moved into (positive), or out of (negative), a holding by the investor; buying
+/// shares is money in, selling shares or receiving a dividend is money out.
+#[derive(Clone, Debug)]
+pub struct CashFlow {
+    pub date: Date,
+    pub symbol: Option<Symbol>,
+    pub amount: Money,
+}
+
+/// The performance of a single symbol, or of the whole portfolio when `symbol` is `None`,
+/// over a date range. Returns are fractions, so 0.05 is a 5% return.
+#[derive(Clone, Debug)]
+pub struct Performance {
+    pub symbol: Option<Symbol>,
+    pub from: Date,
+    pub to: Date,
+    pub start_value: Option<Money>,
+    pub end_value: Option<Money>,
+    pub net_flows: Option<Money>,
+    pub time_weighted: Option<f64>,
+    pub money_weighted: Option<f64>,
+    pub annualized: Option<f64>,
+}
+
+/// The price return of a benchmark symbol over a date range.
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    pub symbol: Symbol,
+    pub start_price: Option<Money>,
+    pub end_price: Option<Money>,
+    pub total_return: Option<f64>,
+    pub annualized: Option<f64>,
+}
+
+pub const DAYS_PER_YEAR: f64 = 365.0;
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const XIRR_MAX_ITERATIONS: usize = 100;
+
+const XIRR_TOLERANCE: f64 = 1e-9;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Show the performance of each holding and the portfolio; if a benchmark is provided
+/// each is compared with it, and the benchmark itself is shown last.
+pub fn show_performance<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date, benchmark: Option<&Benchmark>, format: OutputFormat) {
+    let mut report = Report::new(&["Symbol", "Start Value", "End Value", "Net Flows", "Time-Weighted", "Money-Weighted", "Annualized"]);
+    if benchmark.is_some() {
+        report.titles.push("Excess Return".to_string());
+        report.titles.push("Tracking Difference".to_string());
+    }
+    for performance in portfolio_performance(portfolio, history, from, to) {
+        let mut row = performance_row(&performance);
+        if let Some(benchmark) = benchmark {
+            row.push(Value::fraction_or(excess_return(&performance, benchmark)));
+            row.push(Value::fraction_or(tracking_difference(&performance, benchmark)));
+        }
+        if performance.symbol.is_none() {
+            report.add_row(row.into_iter().map(Value::bold).collect());
+        } else {
+            report.add_row(row);
+        }
+    }
+    if let Some(benchmark) = benchmark {
+        let mut row = vec![
+            Value::Text(format!("{} (benchmark)", benchmark.symbol)),
+            Value::money_or(benchmark.start_price),
+            match benchmark.end_price {
+                Some(price) => Value::Money(price),
+                None => Value::Error("no price".to_string()),
+            },
+            Value::Empty,
+            Value::fraction_or(benchmark.total_return),
+            Value::Empty,
+            Value::fraction_or(benchmark.annualized),
+        ];
+        row.resize(report.titles.len(), Value::Empty);
+        report.add_row(row);
+    }
+    report.print(format);
+}
+
+/// The price return of `symbol` between the closes of `from` and `to`. Prices come from
+/// the price history, except that if a quote provider is given its current quote is
+/// used as the end price; this is intended for ranges that end today.
+pub fn benchmark_return<T: FetchPriceHistory, Q: FetchPriceQuote>(symbol: &Symbol, history: &T, provider: Option<&Q>, from: Date, to: Date) -> Benchmark {
+    let start_price = price_on(history, symbol, from);
+    let end_price = match provider.map(|provider| provider.real_time(symbol.to_string())) {
+        Some(Ok(quote)) => Some(quote.data.latest.price),
+        Some(Err(err)) => {
+            warn!("Error retrieving quote for {}, using price history: {:?}", symbol, err);
+            price_on(history, symbol, to)
+        }
+        None => price_on(history, symbol, to),
+    };
+    let total_return = match (start_price, end_price) {
+        (Some(start), Some(end)) if start.currency == end.currency && start.minor_amount() != 0 =>
+            Some(money_f64(&end) / money_f64(&start) - 1.0),
+        _ => None,
+    };
+    Benchmark {
+        symbol: symbol.to_string(),
+        start_price,
+        end_price,
+        total_return,
+        annualized: total_return.and_then(|r| annualized(r, (to - from).num_days())),
+    }
+}
+
+/// The time-weighted return in excess of the benchmark's return over the same period.
+pub fn excess_return(performance: &Performance, benchmark: &Benchmark) -> Option<f64> {
+    match (performance.time_weighted, benchmark.total_return) {
+        (Some(r), Some(b)) => Some(r - b),
+        _ => None,
+    }
+}
+
+/// The difference between the annualized return and the benchmark's annualized return.
+pub fn tracking_difference(performance: &Performance, benchmark: &Benchmark) -> Option<f64> {
+    match (performance.annualized, benchmark.annualized) {
+        (Some(r), Some(b)) => Some(r - b),
+        _ => None,
+    }
+}
+
+/// The performance of each symbol held during the date range, in the order they first
+/// appear, followed by the performance of the whole portfolio.
+pub fn portfolio_performance<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date) -> Vec<Performance> {
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let held = held_on(portfolio, from).into_iter().map(|position| position.symbol);
+    let traded = cash_flows(portfolio, from, to).into_iter().filter_map(|flow| flow.symbol);
+    for symbol in held.chain(traded) {
+        if !symbols.contains(&symbol) {
+            symbols.push(symbol);
+        }
+    }
+
+    symbols
+        .iter()
+        .map(|symbol| Performance {
+            symbol: Some(symbol.to_string()),
+            ..performance(&symbol_portfolio(portfolio, symbol), history, from, to)
+        })
+        .chain(vec![performance(portfolio, history, from, to)])
+        .collect()
+}
+
+/// The performance of everything in `portfolio` between the close of `from` and the
+/// close of `to`.
+pub fn performance<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date) -> Performance {
+    let flows = cash_flows(portfolio, from, to);
+    let mut dates: Vec<Date> = vec![from];
+    for flow in &flows {
+        if !dates.contains(&flow.date) {
+            dates.push(flow.date);
+        }
+    }
+    if !dates.contains(&to) {
+        dates.push(to);
+    }
+    dates.sort();
+
+    let valued = valuations(portfolio, history, &dates);
+    let values: Vec<Option<f64>> = valued
+        .iter()
+        .map(|valuation| valuation.value.map(|value| money_f64(&value)))
+        .collect();
+    let start_value = values.first().cloned().unwrap_or(None);
+    let end_value = values.last().cloned().unwrap_or(None);
+    let net_flows = flows.iter().fold(None, |total: Option<Money>, flow| match total {
+        Some(total) if total.currency == flow.amount.currency => Some(total + flow.amount),
+        Some(total) => {
+            warn!("Ignoring {:?} in net flows, expected currency {}", flow.amount, total.currency.code());
+            Some(total)
+        }
+        None => Some(flow.amount),
+    });
+
+    let time_weighted = if values.iter().all(|v| v.is_some()) {
+        let periods: Vec<(f64, f64)> = dates
+            .iter()
+            .zip(values.iter())
+            .map(|(date, value)| {
+                let flow: f64 = flows
+                    .iter()
+                    .filter(|flow| flow.date == *date)
+                    .map(|flow| money_f64(&flow.amount))
+                    .sum();
+                (value.unwrap(), flow)
+            })
+            .collect();
+        time_weighted_return(&periods)
+    } else {
+        None
+    };
+
+    let money_weighted = match (start_value, end_value) {
+        (Some(start_value), Some(end_value)) => {
+            // from the investor's view; the starting value is paid in, the end value paid out.
+            let mut investor: Vec<(Date, f64)> = vec![(from, -start_value)];
+            investor.extend(flows.iter().map(|flow| (flow.date, -money_f64(&flow.amount))));
+            investor.push((to, end_value));
+            xirr(&investor)
+        }
+        _ => None,
+    };
+
+    Performance {
+        symbol: None,
+        from,
+        to,
+        start_value: valued.first().and_then(|v| v.value),
+        end_value: valued.last().and_then(|v| v.value),
+        net_flows,
+        time_weighted,
+        money_weighted,
+        annualized: time_weighted.and_then(|r| annualized(r, (to - from).num_days())),
+    }
+}
+
+/// External cash flows in the range, after the close of `from` up to, and including, the
+/// close of `to`.
+pub fn cash_flows(portfolio: &Portfolio, from: Date, to: Date) -> Vec<CashFlow> {
+    let in_range = |date: Date| date > from && date <= to;
+    let mut flows: Vec<CashFlow> = Vec::new();
+    let mut add = |date: Date, symbol: &Symbol, amount: Money| {
+        if in_range(date) {
+            flows.push(CashFlow { date, symbol: Some(symbol.to_string()), amount });
+        }
+    };
+
+    for item in &portfolio.items {
+        if let Item::Price(symbol, holding) = item {
+            if let Some(date) = holding.purchase_date {
+                add(date, symbol, money_times(holding.purchase_price, holding.quantity));
+            }
+        }
+    }
+    for lot in &portfolio.lots {
+        if let Some(date) = lot.purchase_date {
+            add(date, &lot.symbol, money_times(lot.purchase_price, lot.quantity));
+        }
+    }
+    for gain in &portfolio.realized {
+        if let Some(date) = gain.purchase_date {
+            add(date, &gain.symbol, money_times(gain.purchase_price, gain.quantity));
+        }
+        add(gain.sale_date, &gain.symbol, money_times(gain.sale_price, gain.quantity) * -1);
+    }
+    for transaction in &ledger::all_transactions(portfolio) {
+        match transaction {
+            Transaction::Buy(date, symbol, trade) => add(*date, symbol, money_times(trade.price, trade.quantity)),
+            Transaction::Sell(date, symbol, trade) => add(*date, symbol, money_times(trade.price, trade.quantity) * -1),
+            Transaction::Dividend(date, symbol, amount) => add(*date, symbol, *amount * -1),
+            Transaction::Fee(date, Some(symbol), amount) => add(*date, symbol, *amount),
+            _ => (),
+        }
+    }
+    flows.sort_by_key(|flow| flow.date);
+    flows
+}
+
+/// Chain the returns of each sub-period between cash flows; `periods` is the value at the
+/// close of each date, including any flow on that date, and the net flow on that date.
+/// Sub-periods that start with nothing held are skipped.
+pub fn time_weighted_return(periods: &[(f64, f64)]) -> Option<f64> {
+    if periods.len() < 2 {
+        return None;
+    }
+    let mut growth = 1.0;
+    let mut measured = false;
+    for pair in periods.windows(2) {
+        let (start, _) = pair[0];
+        let (end, flow) = pair[1];
+        if start.abs() > f64::EPSILON {
+            growth *= (end - flow) / start;
+            measured = true;
+        }
+    }
+    if measured {
+        Some(growth - 1.0)
+    } else {
+        None
+    }
+}
+
+/// The annual rate at which the net present value of the dated `flows` is zero, `None` if
+/// there is no solution; flows paid in are negative and flows paid out positive.
+pub fn xirr(flows: &[(Date, f64)]) -> Option<f64> {
+    let first = flows.iter().map(|(date, _)| *date).min()?;
+    let years: Vec<(f64, f64)> = flows
+        .iter()
+        .map(|(date, amount)| ((*date - first).num_days() as f64 / DAYS_PER_YEAR, *amount))
+        .collect();
+    if !years.iter().any(|(_, a)| *a > 0.0) || !years.iter().any(|(_, a)| *a < 0.0) {
+        return None;
+    }
+    let npv = |rate: f64| -> f64 { years.iter().map(|(t, a)| a / (1.0 + rate).powf(*t)).sum() };
+    let derivative = |rate: f64| -> f64 {
+        years.iter().map(|(t, a)| -t * a / (1.0 + rate).powf(t + 1.0)).sum()
+    };
+
+    // Newton's method, falling back to bisection if it fails to converge.
+    let mut rate = 0.1;
+    for _ in 0..XIRR_MAX_ITERATIONS {
+        let value = npv(rate);
+        if value.abs() < XIRR_TOLERANCE {
+            return Some(rate);
+        }
+        let slope = derivative(rate);
+        if slope.abs() < f64::EPSILON {
+            break;
+        }
+        let next = rate - value / slope;
+        if !next.is_finite() || next <= -1.0 {
+            break;
+        }
+        if (next - rate).abs() < XIRR_TOLERANCE {
+            return Some(next);
+        }
+        rate = next;
+    }
+
+    let (mut low, mut high) = (-0.9999, 10.0);
+    if npv(low).signum() == npv(high).signum() {
+        return None;
+    }
+    for _ in 0..XIRR_MAX_ITERATIONS * 10 {
+        let mid = (low + high) / 2.0;
+        let value = npv(mid);
+        if value.abs() < XIRR_TOLERANCE || (high - low) / 2.0 < XIRR_TOLERANCE {
+            return Some(mid);
+        }
+        if value.signum() == npv(low).signum() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+/// Convert a return over `days` into an annual rate.
+pub fn annualized(total_return: f64, days: i64) -> Option<f64> {
+    if days <= 0 || total_return <= -1.0 {
+        None
+    } else {
+        Some((1.0 + total_return).powf(DAYS_PER_YEAR / days as f64) - 1.0)
+    }
+}
+
+/// A portfolio containing only the holdings, lots and transactions for `symbol`.
+pub fn symbol_portfolio(portfolio: &Portfolio, symbol: &Symbol) -> Portfolio {
+    Portfolio {
+        default_currency: portfolio.default_currency,
+        items: portfolio
+            .items
+            .iter()
+            .filter(|item| match item {
+                Item::Price(s, _) => s == symbol,
+                _ => false,
+            })
+            .cloned()
+            .collect(),
+        transactions: ledger::all_transactions(portfolio)
+            .into_iter()
+            .filter(|t| t.symbol() == Some(symbol))
+            .collect(),
+        lots: portfolio.lots.iter().filter(|lot| lot.symbol == *symbol).cloned().collect(),
+        realized: portfolio.realized.iter().filter(|gain| gain.symbol == *symbol).cloned().collect(),
+        actions: portfolio.actions.clone(),
+        ..Default::default()
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn performance_row(performance: &Performance) -> Vec<Value> {
+    vec![
+        match &performance.symbol {
+            Some(symbol) => Value::text(symbol),
+            None => Value::text("Total"),
+        },
+        Value::money_or(performance.start_value),
+        match performance.end_value {
+            Some(value) => Value::Money(value),
+            None => Value::Error("no price".to_string()),
+        },
+        Value::money_or(performance.net_flows),
+        Value::fraction_or(performance.time_weighted),
+        Value::fraction_or(performance.money_weighted),
+        Value::fraction_or(performance.annualized),
+    ]
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Date {
+        Date::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        match actual {
+            Some(actual) => assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected),
+            None => panic!("expected {}, not None", expected),
+        }
+    }
+
+    #[test]
+    fn chains_sub_period_returns() {
+        // 10%, then 120 / 110 after a deposit of 100, then 5%.
+        let periods = [(100.0, 100.0), (110.0, 0.0), (220.0, 100.0), (231.0, 0.0)];
+        assert_near(time_weighted_return(&periods), 0.26);
+    }
+
+    #[test]
+    fn skips_periods_starting_empty() {
+        assert_near(time_weighted_return(&[(0.0, 0.0), (100.0, 100.0), (110.0, 0.0)]), 0.1);
+        assert_eq!(time_weighted_return(&[(0.0, 0.0), (100.0, 100.0)]), None);
+        assert_eq!(time_weighted_return(&[(100.0, 100.0)]), None);
+    }
+
+    #[test]
+    fn solves_a_single_year() {
+        let flows = [(date(2019, 1, 1), -1000.0), (date(2020, 1, 1), 1100.0)];
+        assert_near(xirr(&flows), 0.1);
+    }
+
+    #[test]
+    fn solves_several_flows() {
+        // checked by bisection of the net present value, days 0, 182 and 365.
+        let flows = [
+            (date(2019, 1, 1), -1000.0),
+            (date(2019, 7, 2), -1000.0),
+            (date(2020, 1, 1), 2200.0),
+        ];
+        assert_near(xirr(&flows), 0.134626980);
+    }
+
+    #[test]
+    fn needs_flows_in_and_out() {
+        assert_eq!(xirr(&[]), None);
+        assert_eq!(xirr(&[(date(2019, 1, 1), -1000.0), (date(2020, 1, 1), -100.0)]), None);
+        assert_eq!(xirr(&[(date(2019, 1, 1), 1000.0), (date(2020, 1, 1), 100.0)]), None);
+    }
+
+    #[test]
+    fn gives_up_without_convergence() {
+        // a thousandfold return in a day has a rate far beyond any that is searched.
+        let flows = [(date(2019, 1, 1), -1.0), (date(2019, 1, 2), 1000.0)];
+        assert_eq!(xirr(&flows), None);
+    }
+
+    #[test]
+    fn annualizes_returns() {
+        assert_near(annualized(0.21, 730), 0.1);
+        assert_near(annualized(0.1, 365), 0.1);
+        assert_eq!(annualized(0.1, 0), None);
+        assert_eq!(annualized(-1.0, 365), None);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use fin_iex::IEXProvider;
+use fin_model::prelude::*;
+use fin_model::provider::Provider;
+use fin_model::quote::{FetchPriceQuote, Quote};
+
+use crate::fixture::{FileProvider, FILE_PROVIDER_NAME};
+use crate::iex::IexClient;
+use crate::model::ProviderConfig;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// Any quote provider, created by a `ProviderFactory`; providers may be shared between
+/// threads when fetching quotes concurrently.
+pub type BoxedProvider = Box<dyn FetchPriceQuote + Send + Sync>;
+
+/// Creates a provider from its configuration.
+pub type ProviderFactory = fn(&ProviderConfig) -> Result<BoxedProvider, RequestError>;
+
+/// Maps provider names, as used in the configuration, to the factory that creates them.
+pub struct Registry {
+    factories: HashMap<String, ProviderFactory>,
+}
+
+/// A provider that routes each request to the provider configured for the symbol, or
+/// for its exchange suffix (such as `.L`), or to the default provider.
+pub struct SymbolRouter {
+    default: BoxedProvider,
+    overrides: HashMap<Symbol, BoxedProvider>,
+}
+
+pub const IEX_PROVIDER_NAME: &str = "iex";
+
+pub const DEFAULT_PROVIDER_NAME: &str = IEX_PROVIDER_NAME;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Parse the `--provider` command-line value, either a provider name or `file:PATH`.
+pub fn parse_provider_arg(value: &str) -> ProviderConfig {
+    let prefix = format!("{}:", FILE_PROVIDER_NAME);
+    if value.starts_with(&prefix) {
+        ProviderConfig {
+            name: FILE_PROVIDER_NAME.to_string(),
+            path: Some(value[prefix.len()..].to_string()),
+            ..Default::default()
+        }
+    } else {
+        ProviderConfig {
+            name: value.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::new();
+        registry.register(IEX_PROVIDER_NAME, create_iex);
+        registry.register(FILE_PROVIDER_NAME, create_file);
+        registry
+    }
+}
+
+impl Registry {
+    /// An empty registry, use `Registry::default()` for one with the built-in providers.
+    pub fn new() -> Self {
+        Registry { factories: HashMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, factory: ProviderFactory) {
+        self.factories.insert(name.to_lowercase(), factory);
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Create a single provider, ignoring any per-symbol overrides.
+    pub fn create(&self, config: &ProviderConfig) -> Result<BoxedProvider, RequestError> {
+        info!("providers::create {}", config.name);
+        match self.factories.get(&config.name.to_lowercase()) {
+            Some(factory) => factory(config),
+            None => Err(RequestError::ConfigurationError(format!(
+                "unknown provider {}, expecting one of {}",
+                config.name,
+                self.names().join(", ")
+            ))),
+        }
+    }
+
+    /// Create the default provider and any per-symbol overrides.
+    pub fn create_router(&self, config: &ProviderConfig) -> Result<SymbolRouter, RequestError> {
+        let mut overrides: HashMap<Symbol, BoxedProvider> = HashMap::new();
+        for (symbol, symbol_config) in &config.symbols {
+            overrides.insert(symbol.to_uppercase(), self.create(symbol_config)?);
+        }
+        Ok(SymbolRouter {
+            default: self.create(config)?,
+            overrides,
+        })
+    }
+}
+
+impl Provider for SymbolRouter {
+    fn name(&self) -> &str {
+        self.default.name()
+    }
+}
+
+impl SymbolRouter {
+    /// The provider for `symbol`; one configured for the symbol itself is preferred to
+    /// one configured for its exchange suffix.
+    fn route(&self, symbol: &str) -> &BoxedProvider {
+        let symbol = symbol.to_uppercase();
+        if let Some(provider) = self.overrides.get(&symbol) {
+            return provider;
+        }
+        match symbol.rfind('.') {
+            Some(index) if index > 0 => self.overrides.get(&symbol[index..]).unwrap_or(&self.default),
+            _ => &self.default,
+        }
+    }
+}
+
+impl FetchPriceQuote for SymbolRouter {
+    fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+        self.route(&for_symbol).real_time(for_symbol)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn create_iex(config: &ProviderConfig) -> Result<BoxedProvider, RequestError> {
+    // the fin_iex provider reads its token from IEX_TOKEN, a configured token or endpoint
+    // is passed to a client of our own instead; an endpoint may be used without a token.
+    match (&config.token, &config.endpoint) {
+        (None, None) => Ok(Box::new(IEXProvider::new()?)),
+        (token, endpoint) => {
+            let token = token.as_ref().map(|token| match shellexpand::env(token) {
+                Ok(expanded) => expanded.to_string(),
+                Err(_) => token.to_string(),
+            });
+            Ok(Box::new(IexClient::new(token.as_deref(), endpoint.as_deref())))
+        }
+    }
+}
+
+fn create_file(config: &ProviderConfig) -> Result<BoxedProvider, RequestError> {
+    match &config.path {
+        Some(path) => match FileProvider::open(&shellexpand::tilde(path)) {
+            Ok(provider) => Ok(Box::new(provider)),
+            Err(err) => Err(RequestError::ConfigurationError(format!("{:?}", err))),
+        },
+        None => Err(RequestError::ConfigurationError(
+            "the file provider requires a path".to_string())),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fin_model::quote::{LatestPrice, QuoteData};
+    use steel_cent::currency::with_code;
+
+    /// Quotes every symbol at a price, in dollars, given by its configured `path`, so
+    /// that each route can be told apart.
+    struct FixedProvider {
+        dollars: i32,
+    }
+
+    impl Provider for FixedProvider {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+    }
+
+    impl FetchPriceQuote for FixedProvider {
+        fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+            Ok(Quote {
+                symbol: for_symbol,
+                data: QuoteData {
+                    latest: LatestPrice {
+                        price: Money::of_major(with_code("USD").unwrap(), self.dollars),
+                        change: None,
+                        percentage: None,
+                    },
+                    range: None,
+                },
+            })
+        }
+    }
+
+    fn create_fixed(config: &ProviderConfig) -> Result<BoxedProvider, RequestError> {
+        match config.path.as_ref().map(|path| path.parse::<i32>()) {
+            Some(Ok(dollars)) => Ok(Box::new(FixedProvider { dollars })),
+            _ => Err(RequestError::ConfigurationError("expecting a price as the path".to_string())),
+        }
+    }
+
+    fn fixed(dollars: i32) -> ProviderConfig {
+        ProviderConfig {
+            name: "fixed".to_string(),
+            path: Some(dollars.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn router(symbols: Vec<(&str, ProviderConfig)>) -> SymbolRouter {
+        let mut registry = Registry::new();
+        registry.register("fixed", create_fixed);
+        let config = ProviderConfig {
+            symbols: symbols.into_iter().map(|(symbol, config)| (symbol.to_string(), config)).collect(),
+            ..fixed(1)
+        };
+        registry.create_router(&config).unwrap()
+    }
+
+    fn price(router: &SymbolRouter, symbol: &str) -> i32 {
+        router.real_time(symbol.to_string()).unwrap().data.latest.price.major_part()
+    }
+
+    #[test]
+    fn routes_by_symbol_and_exchange_suffix() {
+        let router = router(vec![(".L", fixed(2)), ("VOD.L", fixed(3)), ("vtsax", fixed(4))]);
+        assert_eq!(price(&router, "BARC.L"), 2);
+        assert_eq!(price(&router, "barc.l"), 2);
+        assert_eq!(price(&router, "VOD.L"), 3);
+        assert_eq!(price(&router, "VTSAX"), 4);
+    }
+
+    #[test]
+    fn falls_back_to_the_default_provider() {
+        let router = router(vec![(".L", fixed(2))]);
+        assert_eq!(price(&router, "AAPL"), 1);
+        assert_eq!(price(&router, "BRK.B"), 1);
+        assert_eq!(price(&router, ".L"), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_providers() {
+        let registry = Registry::default();
+        let config = ProviderConfig {
+            name: "missing".to_string(),
+            ..Default::default()
+        };
+        match registry.create(&config) {
+            Err(RequestError::ConfigurationError(message)) => assert!(message.contains("file, iex"), "{}", message),
+            Err(err) => panic!("expected a configuration error, not {:?}", err),
+            Ok(_) => panic!("an unknown provider should not be created"),
+        }
+    }
+}
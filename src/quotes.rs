@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use fin_model::prelude::*;
+use fin_model::quote::{FetchPriceQuote, Quote};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The result of fetching a quote for each of a set of symbols; a failure for one symbol
+/// does not affect the others.
+pub type QuoteResults = HashMap<Symbol, RequestResult<Quote>>;
+
+/// The symbols whose quote could not be retrieved, with the reason.
+pub type QuoteFailures = Vec<(Symbol, RequestError)>;
+
+pub const DEFAULT_WORKERS: usize = 4;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Fetch real-time quotes for all `symbols`, duplicates are only fetched once, using at
+/// most `workers` concurrent requests.
+pub fn fetch_quotes<T: FetchPriceQuote + Sync>(provider: &T, symbols: &[Symbol], workers: usize) -> QuoteResults {
+    let mut unique: Vec<Symbol> = Vec::new();
+    for symbol in symbols {
+        if !unique.contains(symbol) {
+            unique.push(symbol.to_string());
+        }
+    }
+    info!("quotes::fetch_quotes {} symbols, {} workers", unique.len(), workers);
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<QuoteResults> = Mutex::new(HashMap::new());
+    let workers = workers.max(1).min(unique.len().max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                match unique.get(index) {
+                    Some(symbol) => {
+                        let result = provider.real_time(symbol.to_string());
+                        if let Err(err) = &result {
+                            warn!("Error retrieving quote for {}: {:?}", symbol, err);
+                        }
+                        results.lock().unwrap().insert(symbol.to_string(), result);
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+/// Split results into the successful quotes and the symbols that failed.
+pub fn partition(results: QuoteResults) -> (HashMap<Symbol, Quote>, QuoteFailures) {
+    let mut quotes: HashMap<Symbol, Quote> = HashMap::new();
+    let mut failures: QuoteFailures = Vec::new();
+    for (symbol, result) in results {
+        match result {
+            Ok(quote) => {
+                quotes.insert(symbol, quote);
+            }
+            Err(err) => failures.push((symbol, err)),
+        }
+    }
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    (quotes, failures)
+}
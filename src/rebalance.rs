@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+
+use fin_model::prelude::*;
+use fin_model::quote::FetchPriceQuote;
+use steel_cent::currency::{Currency, with_code};
+
+use crate::allocation::classifications;
+use crate::cash::{cash_balances, CASH_ASSET_CLASS};
+use crate::display::{money_f64, money_from_f64};
+use crate::fx::Converter;
+use crate::ledger;
+use crate::model::{Allocation, Item, Portfolio, Target};
+use crate::quotes::{fetch_quotes, partition, QuoteFailures, DEFAULT_WORKERS};
+use crate::report::{OutputFormat, Report, Value};
+use crate::show::print_rates;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// How trades are suggested.
+#[derive(Clone, Debug)]
+pub struct RebalanceOptions {
+    /// The allowed drift, in percentage points, overriding the portfolio's tolerance.
+    pub tolerance: Option<f64>,
+    /// Suggest fractional share quantities rather than whole shares.
+    pub fractional: bool,
+    /// Only buy, using `cash`, never sell.
+    pub no_sells: bool,
+    /// New cash to invest, in the reporting currency, in addition to any cash held.
+    pub cash: f64,
+}
+
+/// The current, and target, weight of one allocation target and the trade that moves it
+/// back to its target; `quantity` is negative for a sale.
+#[derive(Clone, Debug)]
+pub struct Rebalance {
+    pub target: String,
+    pub symbol: Symbol,
+    pub price: Option<Money>,
+    pub value: f64,
+    pub weight: f64,
+    pub target_weight: f64,
+    pub quantity: f64,
+    pub trade_value: f64,
+    /// Why the target's value is unknown, such as a member without a quote.
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum RebalanceError {
+    NoAllocation,
+    /// A quote could not be converted into the reporting currency.
+    NoRate(Symbol, String),
+    /// Quotes are in more than one currency and there is no reporting currency to
+    /// convert them into.
+    MixedCurrencies(Vec<String>),
+}
+
+pub const DEFAULT_TOLERANCE: f64 = 5.0;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Show the suggested trades, returning the number of symbols whose quote could not be
+/// retrieved; without every quote the weights are unknown, so no trades are suggested.
+pub fn show_rebalance<T: FetchPriceQuote + Sync>(portfolio: Portfolio, provider: T, options: &RebalanceOptions, format: OutputFormat, converter: Option<&Converter>) -> usize {
+    let (currency, trades, failures) = match rebalance(&portfolio, &provider, options, converter) {
+        Ok(result) => result,
+        Err(RebalanceError::NoAllocation) => {
+            println!("No allocation targets in the portfolio file");
+            return 0;
+        }
+        Err(RebalanceError::NoRate(symbol, err)) => {
+            println!("Could not convert the quote for {}: {}", symbol, err);
+            return 0;
+        }
+        Err(RebalanceError::MixedCurrencies(codes)) => {
+            println!("Quotes are in {}, use --report-currency to rebalance in one currency", codes.join(", "));
+            return 0;
+        }
+    };
+
+    let money = |value: f64| Value::Money(money_from_f64(currency, value));
+    let mut report = Report::new(&["Target", "Symbol", "Price", "Value", "Weight", "Target %", "Drift", "Action", "Quantity", "Trade Value"]);
+    for trade in &trades {
+        if let Some(error) = &trade.error {
+            let mut row = vec![Value::text(&trade.target), Value::text(&trade.symbol), Value::Error(error.to_string())];
+            row.resize(report.titles.len(), Value::Empty);
+            report.add_row(row);
+            continue;
+        }
+        if !failures.is_empty() {
+            report.add_row(vec![
+                Value::text(&trade.target),
+                Value::text(&trade.symbol),
+                Value::money_or(trade.price),
+                money(trade.value),
+                Value::Empty,
+                Value::Percent(trade.target_weight),
+                Value::Empty,
+                Value::Empty,
+                Value::Empty,
+                Value::Empty,
+            ]);
+            continue;
+        }
+        report.add_row(vec![
+            Value::text(&trade.target),
+            Value::text(&trade.symbol),
+            Value::money_or(trade.price),
+            money(trade.value),
+            Value::Percent(trade.weight),
+            Value::Percent(trade.target_weight),
+            Value::Percent(trade.weight - trade.target_weight),
+            Value::text(trade.action()),
+            if trade.quantity == 0.0 {
+                Value::Empty
+            } else if options.fractional {
+                Value::Decimal(trade.quantity.abs())
+            } else {
+                Value::Integer(trade.quantity.abs() as i64)
+            },
+            if trade.quantity == 0.0 { Value::Empty } else { money(trade.trade_value) },
+        ]);
+    }
+    report.print(format);
+    print_rates(converter, format);
+    if !failures.is_empty() {
+        if format == OutputFormat::Table {
+            println!("No trades are suggested without a quote for every holding");
+        }
+        return failures.len();
+    }
+    if format == OutputFormat::Table {
+        let net: f64 = trades.iter().map(|trade| trade.trade_value).sum();
+        let cash = options.cash + held_cash(&portfolio, currency, converter);
+        if trades.iter().all(|trade| trade.quantity == 0.0) {
+            println!("All targets are within tolerance");
+        } else if options.no_sells || cash != 0.0 {
+            println!("Cash remaining {:.2} {}", cash - net, currency.code());
+        } else {
+            println!("Net cash required {:.2} {}", net, currency.code());
+        }
+    }
+    0
+}
+
+/// Compare current weights, from live quotes, with the portfolio's allocation targets and
+/// suggest a trade for each target that has drifted more than the tolerance. Symbols
+/// without a target are counted in the total value but never traded. Cash held is
+/// counted in the total value, and in any cash asset class target, and is available to
+/// invest along with the new cash in the options; new cash is also invested in targets
+/// within tolerance that are under their target, which are only ever bought. Prices are
+/// converted with `converter`, without one they must all be in the same currency. If any
+/// quote could not be retrieved the targets with that member have an error, no trades
+/// are suggested and the symbols are returned with their request error.
+pub fn rebalance<T: FetchPriceQuote + Sync>(
+    portfolio: &Portfolio,
+    provider: &T,
+    options: &RebalanceOptions,
+    converter: Option<&Converter>,
+) -> Result<(Currency, Vec<Rebalance>, QuoteFailures), RebalanceError> {
+    let allocation: &Allocation = match &portfolio.allocation {
+        Some(allocation) if !allocation.targets.is_empty() => allocation,
+        _ => return Err(RebalanceError::NoAllocation),
+    };
+    let tolerance = options
+        .tolerance
+        .or(allocation.tolerance)
+        .unwrap_or(DEFAULT_TOLERANCE);
+    let total_percent: f64 = allocation.targets.iter().map(|target| target.percent).sum();
+    if (total_percent - 100.0).abs() > 0.01 {
+        warn!("Allocation targets total {}%, not 100%", total_percent);
+    }
+
+    let classifications = classifications(portfolio);
+    let mut quantities: HashMap<Symbol, u32> = HashMap::new();
+    let mut asset_classes: HashMap<Symbol, String> = HashMap::new();
+    for item in ledger::current_items(portfolio) {
+        if let Item::Price(symbol, holding) = item {
+            let asset_class = holding
+                .classification
+                .asset_class
+                .or(classifications.get(&symbol).and_then(|c| c.asset_class.clone()));
+            if let Some(asset_class) = asset_class {
+                asset_classes.insert(symbol.to_uppercase(), asset_class);
+            }
+            *quantities.entry(symbol.to_uppercase()).or_insert(0) += holding.quantity;
+        }
+    }
+    let mut symbols: Vec<Symbol> = quantities.keys().cloned().collect();
+    for target in &allocation.targets {
+        for symbol in members(target, &asset_classes) {
+            if !symbols.contains(&symbol) {
+                symbols.push(symbol);
+            }
+        }
+    }
+    let (quotes, failures) = partition(fetch_quotes(provider, &symbols, DEFAULT_WORKERS));
+
+    // values are summed as plain numbers, so every price must be in the one currency.
+    let mut prices: HashMap<Symbol, Money> = HashMap::new();
+    for (symbol, quote) in &quotes {
+        let price = match converter {
+            Some(converter) => match converter.convert(quote.data.latest.price) {
+                Ok(converted) => converted,
+                Err(err) => return Err(RebalanceError::NoRate(symbol.to_string(), err.to_string())),
+            },
+            None => quote.data.latest.price,
+        };
+        prices.insert(symbol.to_string(), price);
+    }
+    let currency = match converter {
+        Some(converter) => converter.target(),
+        None => {
+            let mut codes: Vec<String> = prices.values().map(|price| price.currency.code()).collect();
+            codes.sort();
+            codes.dedup();
+            match codes.len() {
+                0 => portfolio.currency(),
+                1 => with_code(&codes[0]).unwrap(),
+                _ => return Err(RebalanceError::MixedCurrencies(codes)),
+            }
+        }
+    };
+    let value_of = |symbol: &Symbol| match (prices.get(symbol), quantities.get(symbol)) {
+        (Some(price), Some(quantity)) => money_f64(price) * *quantity as f64,
+        _ => 0.0,
+    };
+    let held = held_cash(portfolio, currency, converter);
+    let cash = options.cash + held;
+    let total: f64 = quantities.keys().map(&value_of).sum::<f64>() + held;
+    let new_total = total + options.cash;
+
+    let mut trades: Vec<Rebalance> = allocation
+        .targets
+        .iter()
+        .map(|target| {
+            let members = members(target, &asset_classes);
+            let mut value: f64 = members.iter().map(&value_of).sum();
+            if target.asset_class.as_deref() == Some(CASH_ASSET_CLASS) {
+                value += held;
+            }
+            let symbol = trade_symbol(&members, &quantities);
+            let error = failures
+                .iter()
+                .find(|(failed, _)| members.contains(failed))
+                .map(|(failed, err)| format!("no quote for {}: {:?}", failed, err));
+            Rebalance {
+                target: target.name(),
+                price: prices.get(&symbol).cloned(),
+                symbol,
+                value,
+                weight: if total > 0.0 { value / total * 100.0 } else { 0.0 },
+                target_weight: target.percent,
+                quantity: 0.0,
+                trade_value: 0.0,
+                error,
+            }
+        })
+        .collect();
+    if !failures.is_empty() {
+        return Ok((currency, trades, failures));
+    }
+
+    // only new cash forces trades, cash already held only counts towards the weights.
+    let drifted = options.cash > 0.0
+        || trades.iter().any(|trade| (trade.weight - trade.target_weight).abs() > tolerance);
+    if !drifted {
+        return Ok((currency, trades, failures));
+    }
+
+    if options.no_sells {
+        // spread the new cash over the targets that are under their new target value.
+        let shortfalls: Vec<f64> = trades
+            .iter()
+            .map(|trade| (trade.target_weight / 100.0 * new_total - trade.value).max(0.0))
+            .collect();
+        let needed: f64 = shortfalls.iter().sum();
+        let scale = if needed > cash && needed > 0.0 { cash / needed } else { 1.0 };
+        for (trade, shortfall) in trades.iter_mut().zip(shortfalls) {
+            set_trade(trade, shortfall * scale, options.fractional);
+        }
+    } else {
+        // targets within tolerance are only traded to invest new cash, so are never sold.
+        for trade in trades.iter_mut() {
+            let delta = trade.target_weight / 100.0 * new_total - trade.value;
+            if (trade.weight - trade.target_weight).abs() > tolerance || (options.cash > 0.0 && delta > 0.0) {
+                set_trade(trade, delta, options.fractional);
+            }
+        }
+    }
+    Ok((currency, trades, failures))
+}
+
+/// The total of the positive cash balances, in `currency`, that are available to invest.
+pub fn held_cash(portfolio: &Portfolio, currency: Currency, converter: Option<&Converter>) -> f64 {
+    cash_balances(portfolio)
+        .into_iter()
+        .filter(|cash| cash.balance.minor_amount() > 0)
+        .filter_map(|cash| match converter {
+            Some(converter) => match converter.convert(cash.balance) {
+                Ok(balance) => Some(balance),
+                Err(err) => {
+                    warn!("Could not convert cash balance {:?}: {}", cash.balance, err);
+                    None
+                }
+            },
+            None => Some(cash.balance),
+        })
+        .filter(|balance| balance.currency == currency)
+        .map(|balance| money_f64(&balance))
+        .sum()
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Default for RebalanceOptions {
+    fn default() -> Self {
+        RebalanceOptions {
+            tolerance: None,
+            fractional: false,
+            no_sells: false,
+            cash: 0.0,
+        }
+    }
+}
+
+impl Rebalance {
+    pub fn action(&self) -> &'static str {
+        if self.quantity > 0.0 {
+            "buy"
+        } else if self.quantity < 0.0 {
+            "sell"
+        } else {
+            "hold"
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The symbols of a target; an asset class without a list of symbols is made up of the
+/// holdings classified with that asset class.
+fn members(target: &Target, asset_classes: &HashMap<Symbol, String>) -> Vec<Symbol> {
+    let listed = target.members();
+    match &target.asset_class {
+        Some(asset_class) if listed.is_empty() => {
+            let mut symbols: Vec<Symbol> = asset_classes
+                .iter()
+                .filter(|(_, class)| *class == asset_class)
+                .map(|(symbol, _)| symbol.to_string())
+                .collect();
+            symbols.sort();
+            symbols
+        }
+        _ => listed,
+    }
+}
+
+/// The symbol traded for a target, the member with the most shares held or the first
+/// listed.
+fn trade_symbol(members: &[Symbol], quantities: &HashMap<Symbol, u32>) -> Symbol {
+    members
+        .iter()
+        .filter(|symbol| quantities.contains_key(*symbol))
+        .max_by_key(|symbol| quantities[*symbol])
+        .or(members.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Set the quantity for a trade of `amount`; whole share trades are rounded towards zero
+/// so that they never spend more cash than is available.
+fn set_trade(trade: &mut Rebalance, amount: f64, fractional: bool) {
+    let price = match trade.price {
+        Some(price) if price.minor_amount() > 0 => money_f64(&price),
+        _ => return,
+    };
+    let quantity = amount / price;
+    trade.quantity = if fractional {
+        (quantity * 10_000.0).trunc() / 10_000.0
+    } else {
+        quantity.trunc()
+    };
+    trade.trade_value = trade.quantity * price;
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fin_model::provider::Provider;
+    use fin_model::quote::{LatestPrice, Quote, QuoteData};
+
+    use crate::model::{Classification, Holding};
+
+    /// Quotes a fixed price, in dollars, for each of its symbols.
+    struct FixedProvider {
+        prices: Vec<(&'static str, i32)>,
+    }
+
+    impl Provider for FixedProvider {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+    }
+
+    impl FetchPriceQuote for FixedProvider {
+        fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+            match self.prices.iter().find(|(symbol, _)| *symbol == for_symbol) {
+                Some((_, price)) => Ok(Quote {
+                    symbol: for_symbol,
+                    data: QuoteData {
+                        latest: LatestPrice {
+                            price: dollars(*price),
+                            change: None,
+                            percentage: None,
+                        },
+                        range: None,
+                    },
+                }),
+                None => Err(RequestError::ConfigurationError(format!("no quote for {}", for_symbol))),
+            }
+        }
+    }
+
+    fn dollars(amount: i32) -> Money {
+        Money::of_major(with_code("USD").unwrap(), amount)
+    }
+
+    fn holding(symbol: &str, quantity: u32) -> Item {
+        Item::Price(symbol.to_string(), Holding {
+            quantity,
+            purchase_price: dollars(1),
+            purchase_date: None,
+            classification: Classification::default(),
+        })
+    }
+
+    /// VTI and BND held, with a 60/40 target.
+    fn portfolio(vti: u32, bnd: u32) -> Portfolio {
+        let target = |symbol: &str, percent: f64| Target {
+            symbol: Some(symbol.to_string()),
+            percent,
+            ..Default::default()
+        };
+        Portfolio {
+            items: vec![holding("VTI", vti), holding("BND", bnd)],
+            allocation: Some(Allocation {
+                tolerance: None,
+                targets: vec![target("VTI", 60.0), target("BND", 40.0)],
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn trades(portfolio: &Portfolio, bnd_price: i32, options: &RebalanceOptions) -> Vec<Rebalance> {
+        let provider = FixedProvider { prices: vec![("VTI", 100), ("BND", bnd_price)] };
+        let (_, trades, failures) = rebalance(portfolio, &provider, options, None).unwrap();
+        assert!(failures.is_empty(), "{:?}", failures);
+        trades
+    }
+
+    fn quantities(trades: &[Rebalance]) -> Vec<f64> {
+        trades.iter().map(|trade| trade.quantity).collect()
+    }
+
+    #[test]
+    fn holds_targets_within_tolerance() {
+        // 62% and 38%, 2 points from the targets.
+        let portfolio = portfolio(62, 76);
+        let within = trades(&portfolio, 50, &RebalanceOptions::default());
+        assert_eq!(quantities(&within), vec![0.0, 0.0]);
+        assert!((within[0].weight - 62.0).abs() < 1e-9, "{}", within[0].weight);
+        assert_eq!(within.iter().map(|trade| trade.action()).collect::<Vec<_>>(), vec!["hold", "hold"]);
+
+        let options = RebalanceOptions { tolerance: Some(1.0), ..Default::default() };
+        let drifted = trades(&portfolio, 50, &options);
+        assert_eq!(quantities(&drifted), vec![-2.0, 4.0]);
+        assert_eq!(drifted[0].action(), "sell");
+        assert_eq!(drifted[1].trade_value, 200.0);
+    }
+
+    #[test]
+    fn rounds_whole_shares_towards_zero() {
+        // BND is $1,000 under its target, at $30 a share.
+        let portfolio = portfolio(70, 100);
+        let whole = trades(&portfolio, 30, &RebalanceOptions::default());
+        assert_eq!(quantities(&whole), vec![-10.0, 33.0]);
+        assert_eq!(whole[1].trade_value, 990.0);
+
+        let options = RebalanceOptions { fractional: true, ..Default::default() };
+        let fractional = trades(&portfolio, 30, &options);
+        assert_eq!(fractional[0].quantity, -10.0);
+        assert!((fractional[1].quantity - 33.3333).abs() < 1e-9, "{}", fractional[1].quantity);
+    }
+
+    #[test]
+    fn scales_buys_to_the_cash_without_selling() {
+        // VTI is over, and BND $1,000 under, their targets with $500 more to invest.
+        let portfolio = portfolio(68, 128);
+        let options = RebalanceOptions { no_sells: true, cash: 500.0, ..Default::default() };
+        let scaled = trades(&portfolio, 25, &options);
+        assert_eq!(quantities(&scaled), vec![0.0, 20.0]);
+        assert_eq!(scaled[1].trade_value, 500.0);
+
+        // with enough cash both are bought up to their targets.
+        let options = RebalanceOptions { no_sells: true, cash: 2000.0, ..Default::default() };
+        let bought = trades(&portfolio, 25, &options);
+        assert_eq!(quantities(&bought), vec![4.0, 64.0]);
+    }
+}
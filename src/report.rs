@@ -0,0 +1,461 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use fin_model::prelude::*;
+use num_format::SystemLocale;
+use prettytable::{Attr, Cell, Row, Table, color};
+use prettytable::format::Alignment;
+use serde_json::{Map, Number, Value as Json};
+
+use crate::display::*;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+    Markdown,
+    Html,
+}
+
+/// A single value in a report; kept unformatted so that each output format can decide
+/// how it is presented.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Empty,
+    Text(String),
+    Integer(i64),
+    Money(Money),
+    /// A gain, or loss, shown in green, or red, in table output.
+    Gain(Money),
+    /// A change in price, and the percentage change.
+    Change(Money, f64),
+    Percent(f64),
+    /// A plain number, such as a ratio, shown with two decimal places in tables.
+    Decimal(f64),
+    Date(Date),
+    /// A value that could not be determined, and the reason.
+    Error(String),
+    /// Emphasized in table output, the same as the inner value otherwise.
+    Bold(Box<Value>),
+}
+
+/// A report is a set of titled columns and rows of values, it is the common form for
+/// command output that can be rendered in any `OutputFormat`.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub titles: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+pub const FORMAT_NAMES: [&str; 5] = ["table", "json", "csv", "markdown", "html"];
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            other => Err(format!("unknown output format {}", other)),
+        }
+    }
+}
+
+impl Value {
+    pub fn bold(value: Value) -> Value {
+        Value::Bold(Box::new(value))
+    }
+
+    pub fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    pub fn money_or(value: Option<Money>) -> Value {
+        match value {
+            Some(value) => Value::Money(value),
+            None => Value::Empty,
+        }
+    }
+
+    pub fn percent_or(value: Option<f64>) -> Value {
+        match value {
+            Some(value) => Value::Percent(value),
+            None => Value::Empty,
+        }
+    }
+
+    /// A fraction, such as a return of 0.05, shown as a percentage.
+    pub fn fraction_or(value: Option<f64>) -> Value {
+        Value::percent_or(value.map(|value| value * 100.0))
+    }
+
+    pub fn integer_or(value: Option<u64>) -> Value {
+        match value {
+            Some(value) => Value::Integer(value as i64),
+            None => Value::Empty,
+        }
+    }
+
+    pub fn date_or(value: Option<Date>) -> Value {
+        match value {
+            Some(value) => Value::Date(value),
+            None => Value::Empty,
+        }
+    }
+
+    fn cell(&self, locale: &SystemLocale) -> Cell {
+        match self {
+            Value::Empty => default_cell(),
+            Value::Text(s) => Cell::new(s),
+            Value::Integer(n) => number_cell(*n, locale),
+            Value::Money(m) => price_cell(*m),
+            Value::Gain(m) => gain_cell(*m),
+            Value::Change(m, p) => change_value_cell(m, p),
+            Value::Percent(p) => percent_cell(*p),
+            Value::Decimal(n) => Cell::new_align(&format!("{:.2}", n), Alignment::RIGHT),
+            Value::Date(d) => date_cell(*d),
+            Value::Error(e) => Cell::new(&format!("error: {}", e))
+                .with_style(Attr::ForegroundColor(color::RED)),
+            Value::Bold(v) => v.cell(locale).with_style(Attr::Bold),
+        }
+    }
+
+    /// The flattened columns for this value, a money value becomes an amount and a
+    /// currency code.
+    fn fields(&self, title: &str) -> Vec<(String, String)> {
+        match self {
+            Value::Empty => vec![(title.to_string(), String::new())],
+            Value::Text(s) => vec![(title.to_string(), s.to_string())],
+            Value::Integer(n) => vec![(title.to_string(), n.to_string())],
+            Value::Money(m) | Value::Gain(m) => vec![
+                (title.to_string(), money_decimal(m)),
+                (format!("{} Currency", title), m.currency.code()),
+            ],
+            Value::Change(m, p) => vec![
+                (title.to_string(), money_decimal(m)),
+                (format!("{} Currency", title), m.currency.code()),
+                (format!("{} %", title), p.to_string()),
+            ],
+            Value::Percent(p) => vec![(title.to_string(), p.to_string())],
+            Value::Decimal(n) => vec![(title.to_string(), n.to_string())],
+            Value::Date(d) => vec![(title.to_string(), d.format(DATE_FMT).to_string())],
+            Value::Error(e) => vec![(title.to_string(), format!("error: {}", e))],
+            Value::Bold(v) => v.fields(title),
+        }
+    }
+
+    fn json(&self) -> Json {
+        match self {
+            Value::Empty => Json::Null,
+            Value::Text(s) => Json::String(s.to_string()),
+            Value::Integer(n) => Json::Number(Number::from(*n)),
+            Value::Money(m) | Value::Gain(m) => {
+                let mut object = Map::new();
+                object.insert("amount".to_string(), json_money(m));
+                object.insert("currency".to_string(), Json::String(m.currency.code()));
+                Json::Object(object)
+            }
+            Value::Change(m, p) => {
+                let mut object = Map::new();
+                object.insert("amount".to_string(), json_money(m));
+                object.insert("currency".to_string(), Json::String(m.currency.code()));
+                object.insert("percent".to_string(), json_f64(*p));
+                Json::Object(object)
+            }
+            Value::Percent(p) => json_f64(*p),
+            Value::Decimal(n) => json_f64(*n),
+            Value::Date(d) => Json::String(d.format(DATE_FMT).to_string()),
+            Value::Error(e) => {
+                let mut object = Map::new();
+                object.insert("error".to_string(), Json::String(e.to_string()));
+                Json::Object(object)
+            }
+            Value::Bold(v) => v.json(),
+        }
+    }
+}
+
+impl Report {
+    pub fn new(titles: &[&str]) -> Self {
+        Report {
+            titles: titles.iter().map(|t| t.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn add_row(&mut self, row: Vec<Value>) {
+        self.rows.push(row);
+    }
+
+    pub fn table(&self) -> Table {
+        let locale: SystemLocale = SystemLocale::default().unwrap();
+        let mut table = Table::new();
+        table.set_titles(Row::new(self.titles.iter().map(|t| Cell::new(t)).collect()));
+        for row in &self.rows {
+            table.add_row(Row::new(row.iter().map(|v| v.cell(&locale)).collect()));
+        }
+        table
+    }
+
+    /// Print the report to stdout, tables are printed with terminal colors.
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Table => {
+                self.table().printstd();
+            }
+            _ => {
+                let stdout = io::stdout();
+                let mut out = stdout.lock();
+                match self.write(format, &mut out) {
+                    Ok(_) => (),
+                    Err(err) => error!("Error writing report: {:?}", err),
+                }
+            }
+        }
+    }
+
+    pub fn write<W: Write>(&self, format: OutputFormat, out: &mut W) -> io::Result<()> {
+        match format {
+            OutputFormat::Table => {
+                self.table().print(out)?;
+                Ok(())
+            }
+            OutputFormat::Json => self.write_json(out),
+            OutputFormat::Csv => self.write_csv(out),
+            OutputFormat::Markdown => self.write_markdown(out),
+            OutputFormat::Html => self.write_html(out),
+        }
+    }
+
+    fn flattened(&self) -> (Vec<String>, Vec<Vec<String>>) {
+        // a column expands to the most fields any row has for it, so a money column
+        // gains a currency column even if its first value is empty.
+        let titles: Vec<String> = self.titles
+            .iter()
+            .enumerate()
+            .flat_map(|(i, title)| {
+                let widest: Vec<String> = self.rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|value| value.fields(title))
+                    .max_by_key(|fields| fields.len())
+                    .unwrap_or_else(|| vec![(title.to_string(), String::new())])
+                    .into_iter()
+                    .map(|(t, _)| t)
+                    .collect();
+                widest.into_iter()
+            })
+            .collect();
+        let rows = self.rows
+            .iter()
+            .map(|row| {
+                let fields: Vec<(String, String)> = self.titles
+                    .iter()
+                    .zip(row.iter())
+                    .flat_map(|(title, value)| value.fields(title).into_iter())
+                    .collect();
+                titles
+                    .iter()
+                    .map(|title| match fields.iter().find(|(t, _)| t == title) {
+                        Some((_, v)) => v.to_string(),
+                        None => String::new(),
+                    })
+                    .collect()
+            })
+            .collect();
+        (titles, rows)
+    }
+
+    fn write_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let rows: Vec<Json> = self.rows
+            .iter()
+            .map(|row| {
+                let mut object = Map::new();
+                for (title, value) in self.titles.iter().zip(row.iter()) {
+                    object.insert(json_key(title), value.json());
+                }
+                Json::Object(object)
+            })
+            .collect();
+        match serde_json::to_string_pretty(&Json::Array(rows)) {
+            Ok(s) => writeln!(out, "{}", s),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (titles, rows) = self.flattened();
+        writeln!(out, "{}", titles.iter().map(|s| csv_escape(s)).collect::<Vec<String>>().join(","))?;
+        for row in rows {
+            writeln!(out, "{}", row.iter().map(|s| csv_escape(s)).collect::<Vec<String>>().join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_markdown<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (titles, rows) = self.flattened();
+        let line = |fields: &Vec<String>| format!(
+            "| {} |",
+            fields.iter().map(|s| s.replace("|", "\\|")).collect::<Vec<String>>().join(" | "));
+        writeln!(out, "{}", line(&titles))?;
+        writeln!(out, "|{}", titles.iter().map(|_| "---|").collect::<String>())?;
+        for row in rows {
+            writeln!(out, "{}", line(&row))?;
+        }
+        Ok(())
+    }
+
+    fn write_html<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (titles, rows) = self.flattened();
+        writeln!(out, "<table>")?;
+        writeln!(out, "  <thead>")?;
+        writeln!(out, "    <tr>{}</tr>", titles.iter().map(|s| format!("<th>{}</th>", html_escape(s))).collect::<String>())?;
+        writeln!(out, "  </thead>")?;
+        writeln!(out, "  <tbody>")?;
+        for row in rows {
+            writeln!(out, "    <tr>{}</tr>", row.iter().map(|s| format!("<td>{}</td>", html_escape(s))).collect::<String>())?;
+        }
+        writeln!(out, "  </tbody>")?;
+        writeln!(out, "</table>")
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn json_key(title: &str) -> String {
+    title
+        .to_lowercase()
+        .replace("%", "percent")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join("_")
+}
+
+/// The exact decimal amount as a JSON number; with serde_json's `arbitrary_precision`
+/// it is written as the decimal digits, not converted through a binary float.
+fn json_money(value: &Money) -> Json {
+    Json::Number(Number::from_string_unchecked(money_decimal(value)))
+}
+
+fn json_f64(value: f64) -> Json {
+    match Number::from_f64(value) {
+        Some(n) => Json::Number(n),
+        None => Json::Null,
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use steel_cent::currency::with_code;
+
+    #[test]
+    fn writes_exact_amounts_in_json() {
+        let usd = with_code("USD").unwrap();
+        let jpy = with_code("JPY").unwrap();
+        let mut report = Report::new(&["Symbol", "Price", "Gain"]);
+        report.add_row(vec![
+            Value::text("AAPL"),
+            Value::Money(Money::of_minor(usd, 30)),
+            Value::Gain(Money::of_minor(usd, -2_123_456_789)),
+        ]);
+        report.add_row(vec![Value::text("7203.T"), Value::Money(Money::of_minor(jpy, 1500)), Value::Empty]);
+
+        let mut out: Vec<u8> = Vec::new();
+        report.write(OutputFormat::Json, &mut out).unwrap();
+        let rows: Json = serde_json::from_slice(&out).unwrap();
+        assert!(rows[0]["price"]["amount"].is_number());
+        assert_eq!(rows[0]["price"]["amount"].to_string(), "0.30");
+        assert_eq!(rows[0]["gain"]["amount"].to_string(), "-21234567.89");
+        assert_eq!(rows[0]["gain"]["currency"], Json::String("USD".to_string()));
+        assert_eq!(rows[1]["price"]["amount"].to_string(), "1500");
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        let usd = with_code("USD").unwrap();
+        let mut report = Report::new(&["Name", "Value"]);
+        report.add_row(vec![Value::text("Apple, Inc."), Value::Money(Money::of_minor(usd, 12_345))]);
+        report.add_row(vec![Value::text("The \"Fund\""), Value::Error("no quote, try later".to_string())]);
+
+        assert_eq!(
+            written(&report, OutputFormat::Csv),
+            "Name,Value,Value Currency\n\
+             \"Apple, Inc.\",123.45,USD\n\
+             \"The \"\"Fund\"\"\",\"error: no quote, try later\",\n"
+        );
+    }
+
+    #[test]
+    fn escapes_pipes_in_markdown() {
+        let mut report = Report::new(&["Name|Alias", "Note"]);
+        report.add_row(vec![Value::text("A|B"), Value::Error("bad | value".to_string())]);
+
+        assert_eq!(
+            written(&report, OutputFormat::Markdown),
+            "| Name\\|Alias | Note |\n\
+             |---|---|\n\
+             | A\\|B | error: bad \\| value |\n"
+        );
+    }
+
+    #[test]
+    fn escapes_entities_in_html() {
+        let mut report = Report::new(&["<Name>", "Note"]);
+        report.add_row(vec![Value::text("AT&T"), Value::text("\"quoted\" <b>")]);
+
+        assert_eq!(
+            written(&report, OutputFormat::Html),
+            "<table>\n  \
+               <thead>\n    \
+                 <tr><th>&lt;Name&gt;</th><th>Note</th></tr>\n  \
+               </thead>\n  \
+               <tbody>\n    \
+                 <tr><td>AT&amp;T</td><td>&quot;quoted&quot; &lt;b&gt;</td></tr>\n  \
+               </tbody>\n\
+             </table>\n"
+        );
+    }
+
+    fn written(report: &Report, format: OutputFormat) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        report.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+}
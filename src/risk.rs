@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use fin_model::prelude::*;
+
+use crate::display::{money_f64, money_from_f64, money_times, DATE_FMT};
+use crate::history::{price_on, FetchPriceHistory};
+use crate::ledger;
+use crate::model::{Item, Portfolio};
+use crate::report::{OutputFormat, Report, Value};
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+/// The settings used to calculate risk metrics; rates are fractions, so 0.02 is 2%.
+#[derive(Clone, Debug)]
+pub struct RiskOptions {
+    /// The annual risk-free rate used by the Sharpe and Sortino ratios.
+    pub risk_free_rate: f64,
+    /// The confidence level of the one-day value-at-risk, for example 0.95.
+    pub confidence: f64,
+    /// The symbol that beta is measured against.
+    pub benchmark: Option<Symbol>,
+}
+
+/// Risk metrics for a single symbol, or for the weighted portfolio when `symbol` is
+/// `None`. All values are calculated from daily returns, annualized where noted, and are
+/// `None` if there are too few prices.
+#[derive(Clone, Debug)]
+pub struct RiskMetrics {
+    pub symbol: Option<Symbol>,
+    /// The fraction of the portfolio's value at the end of the range.
+    pub weight: Option<f64>,
+    pub market_value: Option<Money>,
+    /// Annualized standard deviation of daily returns.
+    pub volatility: Option<f64>,
+    pub beta: Option<f64>,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+    /// The largest fall from a peak, as a positive fraction.
+    pub max_drawdown: Option<f64>,
+    /// The one-day historical value-at-risk, as a positive fraction of value.
+    pub value_at_risk: Option<f64>,
+    /// Why the metrics could not be calculated, only for the portfolio.
+    pub error: Option<String>,
+}
+
+pub const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+pub const DEFAULT_CONFIDENCE: f64 = 0.95;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn show_risk<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date, options: &RiskOptions, format: OutputFormat) {
+    let var_title = format!("VaR {}%", options.confidence * 100.0);
+    let mut report = Report::new(&["Symbol", "Weight", "Volatility", "Beta", "Sharpe", "Sortino", "Max Drawdown", &var_title, "VaR Amount"]);
+    for metrics in portfolio_risk(portfolio, history, from, to, options) {
+        let mut row = vec![
+            match &metrics.symbol {
+                Some(symbol) => Value::text(symbol),
+                None => Value::text("Portfolio"),
+            },
+            Value::fraction_or(metrics.weight),
+            Value::fraction_or(metrics.volatility),
+            ratio_or(metrics.beta),
+            ratio_or(metrics.sharpe),
+            ratio_or(metrics.sortino),
+            Value::fraction_or(metrics.max_drawdown),
+            Value::fraction_or(metrics.value_at_risk),
+            match (metrics.market_value, metrics.value_at_risk) {
+                (Some(value), Some(var)) => Value::Money(money_from_f64(value.currency, money_f64(&value) * var)),
+                _ => Value::Empty,
+            },
+        ];
+        // metrics that could not be calculated are empty, with the reason in place of the weight.
+        if let Some(err) = &metrics.error {
+            row[1] = Value::Error(err.to_string());
+        }
+        if metrics.symbol.is_none() {
+            report.add_row(row.into_iter().map(Value::bold).collect());
+        } else {
+            report.add_row(row);
+        }
+    }
+    report.print(format);
+    if format == OutputFormat::Table {
+        println!(
+            "Daily prices from {} to {}, risk-free rate {}%, {}",
+            from.format(DATE_FMT),
+            to.format(DATE_FMT),
+            options.risk_free_rate * 100.0,
+            match &options.benchmark {
+                Some(benchmark) => format!("beta against {}", benchmark),
+                None => "no benchmark for beta".to_string(),
+            });
+    }
+}
+
+/// Calculate risk metrics for each symbol currently held, and for the portfolio with each
+/// symbol weighted by its value at the end of the range. The portfolio's metrics are an
+/// error if the symbols are valued in more than one currency, as they can't be weighted.
+pub fn portfolio_risk<T: FetchPriceHistory>(portfolio: &Portfolio, history: &T, from: Date, to: Date, options: &RiskOptions) -> Vec<RiskMetrics> {
+    let mut order: Vec<Symbol> = Vec::new();
+    let mut quantities: HashMap<Symbol, u32> = HashMap::new();
+    for item in ledger::current_items(portfolio) {
+        if let Item::Price(symbol, holding) = item {
+            let symbol = symbol.to_uppercase();
+            if !quantities.contains_key(&symbol) {
+                order.push(symbol.to_string());
+            }
+            *quantities.entry(symbol).or_insert(0) += holding.quantity;
+        }
+    }
+
+    let benchmark_returns = options.benchmark.as_ref().map(|symbol| daily_returns(&closes(history, symbol, from, to)));
+
+    let values: HashMap<Symbol, Money> = order
+        .iter()
+        .filter_map(|symbol| {
+            price_on(history, symbol, to).map(|price| (symbol.to_string(), money_times(price, quantities[symbol])))
+        })
+        .collect();
+    let mut currencies: Vec<String> = Vec::new();
+    for value in order.iter().filter_map(|symbol| values.get(symbol)) {
+        if !currencies.contains(&value.currency.code()) {
+            currencies.push(value.currency.code());
+        }
+    }
+    let currency = match currencies.len() {
+        1 => values.values().next().map(|value| value.currency),
+        _ => None,
+    };
+    let total: f64 = values.values().map(money_f64).sum();
+
+    let mut all_returns: Vec<(f64, Vec<(Date, f64)>)> = Vec::new();
+    let mut results: Vec<RiskMetrics> = Vec::new();
+    for symbol in &order {
+        let returns = daily_returns(&closes(history, symbol, from, to));
+        let weight = match values.get(symbol) {
+            Some(value) if Some(value.currency) == currency && total > 0.0 => Some(money_f64(value) / total),
+            _ => None,
+        };
+        if let Some(weight) = weight {
+            all_returns.push((weight, returns.clone()));
+        }
+        results.push(RiskMetrics {
+            symbol: Some(symbol.to_string()),
+            weight,
+            market_value: values.get(symbol).cloned(),
+            ..metrics(&returns, benchmark_returns.as_ref(), options)
+        });
+    }
+
+    if currencies.len() > 1 {
+        results.push(RiskMetrics {
+            error: Some(format!("values are in {}, the symbols can't be weighted", currencies.join(", "))),
+            ..metrics(&[], None, options)
+        });
+        return results;
+    }
+    let returns = weighted_returns(&all_returns);
+    results.push(RiskMetrics {
+        symbol: None,
+        weight: if all_returns.is_empty() { None } else { Some(1.0) },
+        market_value: currency.map(|currency| money_from_f64(currency, total)),
+        ..metrics(&returns, benchmark_returns.as_ref(), options)
+    });
+    results
+}
+
+/// The return from each close to the next; the date is that of the later close.
+pub fn daily_returns(closes: &[(Date, Money)]) -> Vec<(Date, f64)> {
+    closes
+        .windows(2)
+        .filter_map(|pair| {
+            let (_, previous) = pair[0];
+            let (date, close) = pair[1];
+            if previous.minor_amount() == 0 || previous.currency != close.currency {
+                None
+            } else {
+                Some((date, money_f64(&close) / money_f64(&previous) - 1.0))
+            }
+        })
+        .collect()
+}
+
+/// The annualized sample standard deviation of daily returns.
+pub fn volatility(returns: &[f64]) -> Option<f64> {
+    standard_deviation(returns).map(|sd| sd * TRADING_DAYS_PER_YEAR.sqrt())
+}
+
+/// The covariance of the returns with the benchmark's, over the benchmark's variance;
+/// only dates with a return for both are used.
+pub fn beta(returns: &[(Date, f64)], benchmark: &[(Date, f64)]) -> Option<f64> {
+    let benchmark: HashMap<Date, f64> = benchmark.iter().cloned().collect();
+    let pairs: Vec<(f64, f64)> = returns
+        .iter()
+        .filter_map(|(date, r)| benchmark.get(date).map(|b| (*r, *b)))
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_r = pairs.iter().map(|(r, _)| r).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let covariance = pairs.iter().map(|(r, b)| (r - mean_r) * (b - mean_b)).sum::<f64>() / (n - 1.0);
+    let variance = pairs.iter().map(|(_, b)| (b - mean_b).powi(2)).sum::<f64>() / (n - 1.0);
+    if variance.abs() < f64::EPSILON {
+        None
+    } else {
+        Some(covariance / variance)
+    }
+}
+
+/// The annualized return in excess of the risk-free rate, over the annualized volatility.
+pub fn sharpe_ratio(returns: &[f64], risk_free_rate: f64) -> Option<f64> {
+    match (mean(returns), volatility(returns)) {
+        (Some(mean), Some(volatility)) if volatility > 0.0 =>
+            Some((mean * TRADING_DAYS_PER_YEAR - risk_free_rate) / volatility),
+        _ => None,
+    }
+}
+
+/// As the Sharpe ratio, but only returns below the daily risk-free rate count as risk.
+pub fn sortino_ratio(returns: &[f64], risk_free_rate: f64) -> Option<f64> {
+    let target = risk_free_rate / TRADING_DAYS_PER_YEAR;
+    let mean = mean(returns)?;
+    if returns.len() < 2 {
+        return None;
+    }
+    let downside = returns
+        .iter()
+        .map(|r| (r - target).min(0.0).powi(2))
+        .sum::<f64>()
+        / returns.len() as f64;
+    let downside = downside.sqrt() * TRADING_DAYS_PER_YEAR.sqrt();
+    if downside > 0.0 {
+        Some((mean * TRADING_DAYS_PER_YEAR - risk_free_rate) / downside)
+    } else {
+        None
+    }
+}
+
+/// The largest fall, as a fraction, from a peak in the value that the returns compound.
+pub fn max_drawdown(returns: &[f64]) -> Option<f64> {
+    if returns.is_empty() {
+        return None;
+    }
+    let mut value = 1.0;
+    let mut peak = 1.0;
+    let mut drawdown: f64 = 0.0;
+    for r in returns {
+        value *= 1.0 + r;
+        if value > peak {
+            peak = value;
+        }
+        drawdown = drawdown.max((peak - value) / peak);
+    }
+    Some(drawdown)
+}
+
+/// The one-day loss that was not exceeded on `confidence` of days, as a positive
+/// fraction, taken directly from the historical returns.
+pub fn value_at_risk(returns: &[f64], confidence: f64) -> Option<f64> {
+    if returns.is_empty() || confidence <= 0.0 || confidence >= 1.0 {
+        return None;
+    }
+    let mut sorted = returns.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let index = ((1.0 - confidence) * sorted.len() as f64).floor() as usize;
+    Some((-sorted[index.min(sorted.len() - 1)]).max(0.0))
+}
+
+/// The default range, a year up to `to`.
+pub fn default_from(to: Date) -> Date {
+    to - Duration::days(365)
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Default for RiskOptions {
+    fn default() -> Self {
+        RiskOptions {
+            risk_free_rate: 0.0,
+            confidence: DEFAULT_CONFIDENCE,
+            benchmark: None,
+        }
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn closes<T: FetchPriceHistory>(history: &T, symbol: &Symbol, from: Date, to: Date) -> Vec<(Date, Money)> {
+    match history.daily_closes(symbol, from, to) {
+        Ok(closes) => closes,
+        Err(err) => {
+            warn!("Error retrieving prices for {}: {:?}", symbol, err);
+            Vec::new()
+        }
+    }
+}
+
+fn metrics(returns: &[(Date, f64)], benchmark: Option<&Vec<(Date, f64)>>, options: &RiskOptions) -> RiskMetrics {
+    let values: Vec<f64> = returns.iter().map(|(_, r)| *r).collect();
+    RiskMetrics {
+        symbol: None,
+        weight: None,
+        market_value: None,
+        volatility: volatility(&values),
+        beta: benchmark.and_then(|benchmark| beta(returns, benchmark)),
+        sharpe: sharpe_ratio(&values, options.risk_free_rate),
+        sortino: sortino_ratio(&values, options.risk_free_rate),
+        max_drawdown: max_drawdown(&values),
+        value_at_risk: value_at_risk(&values, options.confidence),
+        error: None,
+    }
+}
+
+/// The weighted sum of returns, on the dates for which every symbol has a return.
+fn weighted_returns(returns: &[(f64, Vec<(Date, f64)>)]) -> Vec<(Date, f64)> {
+    let lookup: Vec<(f64, HashMap<Date, f64>)> = returns
+        .iter()
+        .map(|(weight, returns)| (*weight, returns.iter().cloned().collect()))
+        .collect();
+    match returns.first() {
+        Some((_, first)) => first
+            .iter()
+            .filter_map(|(date, _)| {
+                let mut total = 0.0;
+                for (weight, returns) in &lookup {
+                    match returns.get(date) {
+                        Some(r) => total += weight * r,
+                        None => return None,
+                    }
+                }
+                Some((*date, total))
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+fn standard_deviation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = mean(values).unwrap();
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+fn ratio_or(value: Option<f64>) -> Value {
+    match value {
+        Some(value) => Value::Decimal(value),
+        None => Value::Empty,
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use steel_cent::currency::with_code;
+
+    use crate::model::read_str;
+
+    /// Three daily closes of each symbol, rising by a tenth of the first each day.
+    struct Closes {
+        first: HashMap<Symbol, Money>,
+    }
+
+    impl FetchPriceHistory for Closes {
+        fn daily_closes(&self, symbol: &Symbol, from: Date, _: Date) -> RequestResult<Vec<(Date, Money)>> {
+            match self.first.get(symbol) {
+                Some(first) => Ok((0..3)
+                    .map(|day| (from + Duration::days(day), money_from_f64(first.currency, money_f64(first) * (1.0 + day as f64 / 10.0))))
+                    .collect()),
+                None => Err(RequestError::ConfigurationError(format!("no prices for {}", symbol))),
+            }
+        }
+    }
+
+    // a mean of 0.005 and squared deviations summing to 0.0005, so a sample standard
+    // deviation of sqrt(0.0005 / 3) = 0.0129099.
+    const RETURNS: [f64; 4] = [0.01, -0.01, 0.02, 0.0];
+
+    fn date(day: u32) -> Date {
+        Date::from_ymd_opt(2020, 3, day).unwrap()
+    }
+
+    fn assert_near(actual: Option<f64>, expected: f64) {
+        match actual {
+            Some(actual) => assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected),
+            None => panic!("expected {}, not None", expected),
+        }
+    }
+
+    #[test]
+    fn annualizes_volatility() {
+        // 0.0129099 * sqrt(252)
+        assert_near(volatility(&RETURNS), 0.204939015);
+        assert_eq!(volatility(&[0.01]), None);
+        assert_near(volatility(&[0.01, 0.01, 0.01]), 0.0);
+    }
+
+    #[test]
+    fn measures_beta_on_shared_dates() {
+        let benchmark = vec![(date(2), 0.01), (date(3), -0.02), (date(4), 0.01), (date(5), 0.0)];
+        // twice the benchmark's returns, and a date the benchmark doesn't have.
+        let mut returns: Vec<(Date, f64)> = benchmark.iter().map(|(d, b)| (*d, b * 2.0)).collect();
+        returns.push((date(6), 0.5));
+        assert_near(beta(&returns, &benchmark), 2.0);
+
+        let flat = vec![(date(2), 0.01), (date(3), 0.01), (date(4), 0.01)];
+        assert_eq!(beta(&returns, &flat), None);
+        assert_eq!(beta(&returns[..1], &benchmark), None);
+    }
+
+    #[test]
+    fn measures_sharpe_ratio() {
+        // 0.005 * 252 = 1.26 a year, over the volatility.
+        assert_near(sharpe_ratio(&RETURNS, 0.0), 6.148170460);
+        assert_near(sharpe_ratio(&RETURNS, 0.252), 4.918536368);
+        assert_eq!(sharpe_ratio(&[0.01, 0.01], 0.0), None);
+    }
+
+    #[test]
+    fn measures_sortino_ratio() {
+        // only the -0.01 return is downside, sqrt(0.0001 / 4) * sqrt(252) = 0.0793725.
+        assert_near(sortino_ratio(&RETURNS, 0.0), 15.874507866);
+        assert_eq!(sortino_ratio(&[0.01, 0.02], 0.0), None);
+        assert_eq!(sortino_ratio(&[-0.01], 0.0), None);
+    }
+
+    #[test]
+    fn finds_largest_drawdown() {
+        // 1.1, then 0.55, half the peak, recovering to 0.66.
+        assert_near(max_drawdown(&[0.1, -0.5, 0.2]), 0.5);
+        // a later, smaller, fall from a new peak of 2.0 to 1.5.
+        assert_near(max_drawdown(&[-0.2, 1.5, -0.25]), 0.25);
+        assert_near(max_drawdown(&[0.01, 0.02]), 0.0);
+        assert_eq!(max_drawdown(&[]), None);
+    }
+
+    #[test]
+    fn weighs_symbols_in_one_currency() {
+        let usd = with_code("USD").unwrap();
+        let eur = with_code("EUR").unwrap();
+        let mut history = Closes { first: HashMap::new() };
+        history.first.insert("AAPL".to_string(), Money::of_major(usd, 100));
+        history.first.insert("VTI".to_string(), Money::of_major(usd, 200));
+        let from = Date::from_ymd_opt(2020, 1, 1).unwrap();
+        let to = from + Duration::days(2);
+        let options = RiskOptions { risk_free_rate: 0.0, confidence: DEFAULT_CONFIDENCE, benchmark: None };
+
+        // a symbol held in lower case is priced, and weighed, as any other.
+        let portfolio = holdings(&[("aapl", "USD"), ("VTI", "USD")]);
+        let results = portfolio_risk(&portfolio, &history, from, to, &options);
+        let weights: Vec<(Option<&str>, Option<f64>)> = results.iter().map(|m| (m.symbol.as_deref(), m.weight)).collect();
+        assert_eq!(weights, vec![(Some("AAPL"), Some(1.0 / 3.0)), (Some("VTI"), Some(2.0 / 3.0)), (None, Some(1.0))]);
+        assert_eq!(results[2].market_value, Some(Money::of_major(usd, 3_600)));
+        assert_eq!(results[2].error, None);
+
+        // values in another currency can't be weighed with the others.
+        history.first.insert("SAP".to_string(), Money::of_major(eur, 100));
+        let portfolio = holdings(&[("AAPL", "USD"), ("SAP", "EUR")]);
+        let results = portfolio_risk(&portfolio, &history, from, to, &options);
+        assert!(results[..2].iter().all(|m| m.weight.is_none() && m.market_value.is_some()));
+        assert_eq!(results[2].error, Some("values are in USD, EUR, the symbols can't be weighted".to_string()));
+        assert_eq!(results[2].volatility, None);
+    }
+
+    #[test]
+    fn takes_value_at_risk_from_history() {
+        let mut returns = vec![0.01; 18];
+        returns.push(-0.05);
+        returns.push(-0.03);
+        // at 95% the worst of 20 days, 5% of them, is excluded; at 99% none are.
+        assert_near(value_at_risk(&returns, 0.95), 0.03);
+        assert_near(value_at_risk(&returns, 0.99), 0.05);
+        assert_near(value_at_risk(&[0.01, 0.02], 0.95), 0.0);
+        assert_eq!(value_at_risk(&returns, 1.0), None);
+        assert_eq!(value_at_risk(&[], 0.95), None);
+    }
+
+    fn holdings(held: &[(&str, &str)]) -> Portfolio {
+        let source: String = held
+            .iter()
+            .map(|(symbol, code)| format!(
+                "[[holdings]]\nsymbol = \"{}\"\nwatch_only = false\nquantity = 10\ncurrency_major = 1\ncurrency_minor = 0\ncurrency_code = \"{}\"\n\n",
+                symbol, code))
+            .collect();
+        read_str(&format!("version = 2\n\n{}", source)).unwrap()
+    }
+}
@@ -1,68 +1,214 @@
-use std::collections::HashMap;
-
+use chrono::Utc;
 use fin_model::prelude::*;
 use fin_model::quote::{FetchPriceQuote, Quote};
-use num_format::SystemLocale;
-use prettytable::{Attr, Table};
 
+use crate::accounts::account_label;
+use crate::cache::QuoteAge;
+use crate::cash::{cash_balances, cash_symbol};
 use crate::display::*;
+use crate::fx::Converter;
 use crate::model::{Item, Portfolio};
+use crate::quotes::{fetch_quotes, partition, DEFAULT_WORKERS};
+use crate::report::{OutputFormat, Report, Value};
+
+/// Show the portfolio, returning the number of symbols whose quote could not be retrieved.
+/// If `by_account` is set holdings are grouped, and subtotaled, by account.
+pub fn show_portfolio<T: FetchPriceQuote + Sync>(portfolio: Portfolio, provider: T, format: OutputFormat, converter: Option<&Converter>, by_account: bool) -> usize {
+    let (report, failures) = portfolio_report(&portfolio, &provider, converter);
+    let report = if by_account { group_by_account(report, &portfolio) } else { report };
+    report.print(format);
+    print_rates(converter, format);
+    failures.len()
+}
+
+/// As `show_portfolio`, with an additional column showing how long ago each quote was
+/// retrieved; used when showing cached quotes.
+pub fn show_portfolio_with_age<T: FetchPriceQuote + Sync + QuoteAge>(portfolio: Portfolio, provider: T, format: OutputFormat, converter: Option<&Converter>, by_account: bool) -> usize {
+    let (report, failures) = portfolio_report(&portfolio, &provider, converter);
+    let mut report = if by_account { group_by_account(report, &portfolio) } else { report };
+    let now = Utc::now();
+    report.titles.push("Age".to_string());
+    for row in report.rows.iter_mut() {
+        let age = match row.first() {
+            Some(Value::Text(symbol)) => match provider.fetched(symbol) {
+                Some(fetched) => Value::Text(age_string(now.signed_duration_since(fetched))),
+                None => Value::Empty,
+            },
+            _ => Value::Empty,
+        };
+        row.push(age);
+    }
+    report.print(format);
+    print_rates(converter, format);
+    failures.len()
+}
 
-pub fn show_portfolio<T: FetchPriceQuote>(portfolio: Portfolio, provider: T) {
-    let locale: SystemLocale = SystemLocale::default().unwrap();
-    let mut table = Table::new();
-    let mut quote_cache: HashMap<Symbol, Quote> = HashMap::new();
-    table.set_titles(row!["Symbol", "Price", "Change", "Open", "Low", "High", "Close", "Volume", "Purchased", "Quantity", "Value"]);
-    for item in portfolio.items {
-        let symbol = item_symbol(&item).to_string();
-        if !quote_cache.contains_key(&symbol) {
-            let quote = provider.real_time(symbol.to_string());
-            match quote {
-                Ok(quote) => {
-                    quote_cache.insert(symbol.to_string(), quote);
-                },
-                Err(err) => {
-                    println!("Error retrieving quote for {}: {:?}", symbol, err);
-                    return;
-                }
+/// Build the portfolio report, a symbol whose quote could not be retrieved is shown as
+/// an error row and is also returned with its error. Each cash balance follows the items
+/// as a row with only its value. If a converter is provided all money values are
+/// converted into its reporting currency.
+pub fn portfolio_report<T: FetchPriceQuote + Sync>(portfolio: &Portfolio, provider: &T, converter: Option<&Converter>) -> (Report, Vec<(Symbol, RequestError)>) {
+    let mut report = Report::new(&["Symbol", "Price", "Change", "Open", "Low", "High", "Close", "Volume", "Purchased", "Quantity", "Value"]);
+    let symbols: Vec<Symbol> = portfolio.items.iter().map(item_symbol).collect();
+    let (quotes, failures) = partition(fetch_quotes(provider, &symbols, DEFAULT_WORKERS));
+    for item in &portfolio.items {
+        let symbol = item_symbol(item);
+        match quotes.get(&symbol) {
+            Some(quote) => report.add_row(item_row(item, quote, converter)),
+            None => {
+                let reason = match failures.iter().find(|(s, _)| *s == symbol) {
+                    Some((_, err)) => format!("{:?}", err),
+                    None => "no quote".to_string(),
+                };
+                let mut row = vec![Value::Text(symbol), Value::Error(reason)];
+                row.resize(report.titles.len(), Value::Empty);
+                report.add_row(row);
+            }
+        }
+    }
+    for cash in cash_balances(portfolio) {
+        let mut row = vec![Value::Text(cash_symbol(&cash.balance))];
+        row.resize(report.titles.len() - 1, Value::Empty);
+        row.push(Value::bold(money_value(cash.balance, converter)));
+        report.add_row(row);
+    }
+    (report, failures)
+}
+
+/// Group the rows of a portfolio report, one for each of the portfolio's items and cash
+/// balances, by account. An Account column follows the symbol and each account's rows are followed by
+/// a subtotal of their value; accounts are in the order they first appear.
+pub fn group_by_account(report: Report, portfolio: &Portfolio) -> Report {
+    let value_column = report.titles.len();
+    let mut grouped = Report {
+        titles: report.titles.clone(),
+        rows: Vec::new(),
+    };
+    grouped.titles.insert(1, "Account".to_string());
+
+    let mut order: Vec<String> = Vec::new();
+    let mut groups: Vec<Vec<Vec<Value>>> = Vec::new();
+    let accounts = portfolio
+        .items
+        .iter()
+        .map(|item| match item {
+            Item::Price(_, holding) => holding.classification.account.clone(),
+            Item::Watch(_) => None,
+        })
+        .chain(cash_balances(portfolio).into_iter().map(|cash| cash.account));
+    for (account, row) in accounts.zip(report.rows) {
+        let label = account_label(portfolio, account.as_deref());
+        let index = match order.iter().position(|l| *l == label) {
+            Some(index) => index,
+            None => {
+                order.push(label.to_string());
+                groups.push(Vec::new());
+                order.len() - 1
             }
         };
-        add_item(&mut table, &item, &quote_cache.get(&symbol).unwrap(), &locale);
+        let mut row = row;
+        row.insert(1, Value::Text(label));
+        groups[index].push(row);
+    }
+
+    for (label, rows) in order.iter().zip(groups) {
+        let mut subtotal: Option<Money> = None;
+        for row in &rows {
+            if let Some(value) = row.last().and_then(money_of) {
+                subtotal = match subtotal {
+                    Some(total) if total.currency == value.currency => Some(total + value),
+                    Some(total) => {
+                        warn!("Ignoring {:?} in subtotal, expected currency {}, use --report-currency", value, total.currency.code());
+                        Some(total)
+                    }
+                    None => Some(value),
+                };
+            }
+        }
+        grouped.rows.extend(rows);
+        let mut row = vec![Value::bold(Value::text(SUBTOTAL)), Value::bold(Value::text(label))];
+        row.resize(value_column, Value::Empty);
+        row.push(Value::bold(Value::money_or(subtotal)));
+        grouped.add_row(row);
+    }
+    grouped
+}
+
+/// Print the exchange rates used in a report.
+pub fn print_rates(converter: Option<&Converter>, format: OutputFormat) {
+    if let Some(converter) = converter {
+        converter.print_used(format == OutputFormat::Table);
+    }
+}
+
+const SUBTOTAL: &str = "Subtotal";
+
+fn money_of(value: &Value) -> Option<Money> {
+    match value {
+        Value::Money(money) => Some(*money),
+        Value::Bold(value) => money_of(value),
+        _ => None,
     }
-    table.printstd();
 }
 
+fn convert(value: Money, converter: Option<&Converter>) -> Result<Money, String> {
+    match converter {
+        Some(converter) => match converter.convert(value) {
+            Ok(value) => Ok(value),
+            Err(err) => Err(err.to_string()),
+        },
+        None => Ok(value),
+    }
+}
+
+fn money_value(value: Money, converter: Option<&Converter>) -> Value {
+    match convert(value, converter) {
+        Ok(value) => Value::Money(value),
+        Err(err) => Value::Error(err),
+    }
+}
 
-fn add_item(table: &mut Table, item: &Item, quote: &Quote, locale: &SystemLocale) {
+fn item_row(item: &Item, quote: &Quote, converter: Option<&Converter>) -> Vec<Value> {
+    let symbol = item_symbol(item);
+    // The following from Quote
+    let mut row = vec![
+        Value::Text(symbol),
+        money_value(quote.data.latest.price, converter),
+        match (quote.data.latest.change, quote.data.latest.percentage) {
+            (Some(change), Some(percentage)) => match convert(change, converter) {
+                Ok(change) => Value::Change(change, percentage),
+                Err(err) => Value::Error(err),
+            },
+            (_, _) => Value::Empty,
+        },
+        if let Some(range) = &quote.data.range { money_value(range.open, converter) } else { Value::Empty },
+        if let Some(range) = &quote.data.range { money_value(range.low, converter) } else { Value::Empty },
+        if let Some(range) = &quote.data.range { money_value(range.high, converter) } else { Value::Empty },
+        if let Some(range) = &quote.data.range { money_value(range.close, converter) } else { Value::Empty },
+        if let Some(range) = &quote.data.range { Value::integer_or(range.volume) } else { Value::Empty },
+    ];
+    // The following from Holding
     match item {
-        Item::Watch(s) =>
-            table.add_row(row![
-            s,
-            // The following from Quote
-            price_cell(quote.data.latest.price),
-            change_cell(quote),
-            if let Some(range) = &quote.data.range { price_cell(range.open) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.low) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.high) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.close) } else { default_cell() },
-            if let Some(range) = &quote.data.range { number_cell_or(range.volume, &locale, default_cell()) } else { default_cell() },
-            // The following from Holding
-            default_cell(), default_cell(), default_cell()]),
-        Item::Price(s, h) =>
-            table.add_row(row![
-            s,
-            // The following from Quote
-            price_cell(quote.data.latest.price),
-            change_cell(quote),
-            if let Some(range) = &quote.data.range { price_cell(range.open) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.low) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.high) } else { default_cell() },
-            if let Some(range) = &quote.data.range { price_cell(range.close) } else { default_cell() },
-            if let Some(range) = &quote.data.range { number_cell_or(range.volume, &locale, default_cell()) } else { default_cell() },
-            // The following from Holding
-            price_cell(h.purchase_price).with_style(Attr::Bold),
-            number_cell(h.quantity as i64, &locale).with_style(Attr::Bold),
-            // (quote.data.latest.price - h.purchase_price) * h.quantity
-            price_cell((quote.data.latest.price - h.purchase_price) * h.quantity as i32).with_style(Attr::Bold)]),
+        Item::Watch(_) =>
+            row.extend(vec![Value::Empty, Value::Empty, Value::Empty]),
+        Item::Price(_, h) => {
+            let price = convert(quote.data.latest.price, converter);
+            let purchase_price = convert(h.purchase_price, converter);
+            row.extend(vec![
+                Value::bold(money_value(h.purchase_price, converter)),
+                Value::bold(Value::Integer(h.quantity as i64)),
+                // (quote.data.latest.price - h.purchase_price) * h.quantity
+                Value::bold(match (price, purchase_price) {
+                    (Ok(price), Ok(purchase_price)) if price.currency == purchase_price.currency =>
+                        Value::Money(money_change_times(purchase_price, price, h.quantity)),
+                    (Ok(price), Ok(purchase_price)) => Value::Error(format!(
+                        "{} quote for a {} holding, use --report-currency",
+                        price.currency.code(),
+                        purchase_price.currency.code())),
+                    (Err(err), _) | (_, Err(err)) => Value::Error(err),
+                }),
+            ])
+        },
     };
+    row
 }
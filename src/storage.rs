@@ -0,0 +1,281 @@
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDateTime};
+use fs2::FileExt;
+use serde::de::DeserializeOwned;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum StorageError {
+    FileError(io::Error),
+    /// Another command held the lock on the file for longer than `LOCK_TIMEOUT`.
+    Locked(String),
+    UnknownBackup(String),
+}
+
+#[derive(Debug)]
+pub enum DataFileError {
+    PathError(String),
+    FileError(io::Error),
+    FormatError(String),
+}
+
+/// An advisory lock on a file, held by commands that read, change and then write it so
+/// that two commands can't both change the same version. The lock is released when
+/// this is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+/// A copy of a file taken before it was last replaced.
+#[derive(Clone, Debug)]
+pub struct Backup {
+    pub file_name: String,
+    pub taken: NaiveDateTime,
+}
+
+/// The number of backups kept of each file, the oldest are removed first.
+pub const BACKUPS_KEPT: usize = 10;
+
+/// How long to wait for another command to release a lock.
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Read a data file, such as prices or quotes, as TOML, JSON or CSV chosen by its
+/// extension. A CSV file has one `R` per row, and `from_rows` makes the rows into a `T`.
+pub fn read_data_file<T, R, F>(file_name: &str, from_rows: F) -> Result<T, DataFileError>
+where
+    T: DeserializeOwned,
+    R: DeserializeOwned,
+    F: FnOnce(Vec<R>) -> T,
+{
+    let mut f = match File::open(file_name) {
+        Ok(handle) => handle,
+        Err(_) => return Err(DataFileError::PathError(file_name.to_string())),
+    };
+
+    let mut buffer = String::new();
+    match f.read_to_string(&mut buffer) {
+        Ok(_) => (),
+        Err(err) => return Err(DataFileError::FileError(err)),
+    };
+
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "toml" => match toml::from_str::<T>(&buffer) {
+            Ok(data) => Ok(data),
+            Err(err) => Err(DataFileError::FormatError(err.to_string())),
+        },
+        "json" => match serde_json::from_str::<T>(&buffer) {
+            Ok(data) => Ok(data),
+            Err(err) => Err(DataFileError::FormatError(err.to_string())),
+        },
+        "csv" => {
+            let mut reader = csv::Reader::from_reader(buffer.as_bytes());
+            let rows: Result<Vec<R>, csv::Error> = reader.deserialize().collect();
+            match rows {
+                Ok(rows) => Ok(from_rows(rows)),
+                Err(err) => Err(DataFileError::FormatError(err.to_string())),
+            }
+        }
+        other => Err(DataFileError::FormatError(
+            format!("unsupported file type '{}', expecting toml, json, or csv", other))),
+    }
+}
+
+/// Take the advisory lock for `file_name`, waiting for up to `LOCK_TIMEOUT` for another
+/// command to release it. The lock is on a separate file, `FILE.lock`, as the file
+/// itself is replaced on every write.
+pub fn lock(file_name: &str) -> Result<FileLock, StorageError> {
+    let lock_name = format!("{}.lock", file_name);
+    info!("storage::lock {}", lock_name);
+    let file = match OpenOptions::new().write(true).create(true).truncate(false).open(&lock_name) {
+        Ok(file) => file,
+        Err(err) => return Err(StorageError::FileError(err)),
+    };
+    let started = Instant::now();
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(FileLock { file }),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                if started.elapsed() > LOCK_TIMEOUT {
+                    return Err(StorageError::Locked(file_name.to_string()));
+                }
+                debug!("storage::lock waiting for {}", lock_name);
+                thread::sleep(LOCK_RETRY);
+            }
+            Err(err) => return Err(StorageError::FileError(err)),
+        }
+    }
+}
+
+/// Replace the contents of `file_name` so that it is never left partly written, as
+/// `replace`, after any existing file is backed up.
+pub fn write_atomic(file_name: &str, contents: &[u8]) -> io::Result<()> {
+    info!("storage::write_atomic {}", file_name);
+    if Path::new(file_name).exists() {
+        backup(file_name)?;
+    }
+    replace(file_name, contents)
+}
+
+/// Replace the contents of `file_name` so that it is never left partly written: the new
+/// contents are written, and flushed to disk, in a temporary file in the same directory
+/// which is then renamed over the original. The file's permissions are kept; if it is a
+/// symbolic link the file it links to is replaced, not the link.
+pub fn replace(file_name: &str, contents: &[u8]) -> io::Result<()> {
+    let (target, permissions) = if Path::new(file_name).exists() {
+        let target = fs::canonicalize(file_name)?;
+        let permissions = fs::metadata(&target)?.permissions();
+        (target.to_string_lossy().to_string(), Some(permissions))
+    } else {
+        (file_name.to_string(), None)
+    };
+    let temp_name = format!("{}.{}.tmp", target, process::id());
+    let written = File::create(&temp_name).and_then(|mut f| {
+        // before anything is written, so the contents are never readable by more people.
+        if let Some(permissions) = permissions {
+            f.set_permissions(permissions)?;
+        }
+        f.write_all(contents)?;
+        f.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&temp_name, &target)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_name);
+            Err(err)
+        }
+    }
+}
+
+/// Copy `file_name` into its backup directory, `FILE.backups`, with the current time as
+/// its name, removing the oldest backups so that only `BACKUPS_KEPT` remain.
+pub fn backup(file_name: &str) -> io::Result<String> {
+    let directory = backup_directory(file_name);
+    fs::create_dir_all(&directory)?;
+    let backup_name = format!("{}/{}.{}", directory, Local::now().format(TIMESTAMP_FMT), BACKUP_EXTENSION);
+    debug!("storage::backup {} to {}", file_name, backup_name);
+    fs::copy(file_name, &backup_name)?;
+
+    for old in backups(file_name).iter().skip(BACKUPS_KEPT) {
+        debug!("storage::backup removing {}", old.file_name);
+        fs::remove_file(&old.file_name)?;
+    }
+    Ok(backup_name)
+}
+
+/// The backups of `file_name`, newest first.
+pub fn backups(file_name: &str) -> Vec<Backup> {
+    let entries = match fs::read_dir(backup_directory(file_name)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut backups: Vec<Backup> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let taken = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(extension)) if extension == BACKUP_EXTENSION =>
+                    NaiveDateTime::parse_from_str(&stem.to_string_lossy(), TIMESTAMP_FMT).ok(),
+                _ => None,
+            };
+            taken.map(|taken| Backup {
+                file_name: path.to_string_lossy().to_string(),
+                taken,
+            })
+        })
+        .collect();
+    backups.sort_by_key(|backup| Reverse(backup.taken));
+    backups
+}
+
+/// Find a backup of `file_name` by its position in `backups`, counting from 1 for the
+/// newest, or by its file name.
+pub fn find_backup(file_name: &str, name: &str) -> Result<Backup, StorageError> {
+    let backups = backups(file_name);
+    let found = match name.parse::<usize>() {
+        Ok(position) if position > 0 => backups.get(position - 1).cloned(),
+        _ => backups.into_iter().find(|backup| {
+            backup.file_name == name
+                || Path::new(&backup.file_name).file_name().map(|f| f.to_string_lossy() == name).unwrap_or(false)
+        }),
+    };
+    match found {
+        Some(backup) => Ok(backup),
+        None => Err(StorageError::UnknownBackup(name.to_string())),
+    }
+}
+
+/// Replace `file_name` with the contents of `backup`; the file being replaced is itself
+/// backed up, so a restore may be undone.
+pub fn restore(file_name: &str, backup: &Backup) -> Result<(), StorageError> {
+    info!("storage::restore {} from {}", file_name, backup.file_name);
+    let mut contents: Vec<u8> = Vec::new();
+    match File::open(&backup.file_name).and_then(|mut f| f.read_to_end(&mut contents)) {
+        Ok(_) => (),
+        Err(err) => return Err(StorageError::FileError(err)),
+    };
+    match write_atomic(file_name, &contents) {
+        Ok(()) => Ok(()),
+        Err(err) => Err(StorageError::FileError(err)),
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Types
+// ------------------------------------------------------------------------------------------------
+
+const LOCK_RETRY: Duration = Duration::from_millis(100);
+
+/// Backup names sort in the order they were taken.
+const TIMESTAMP_FMT: &str = "%Y-%m-%dT%H-%M-%S%.6f";
+
+const BACKUP_EXTENSION: &str = "toml";
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::FileError(err) => write!(f, "file error: {}", err),
+            StorageError::Locked(name) => write!(f, "{} is in use by another folio command, try again", name),
+            StorageError::UnknownBackup(name) => write!(f, "no backup {}, run `folio restore` to list them", name),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn backup_directory(file_name: &str) -> String {
+    format!("{}.backups", file_name)
+}
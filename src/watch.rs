@@ -1,3 +1,223 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use fin_model::quote::FetchPriceQuote;
+
+use crate::fx::Converter;
 use crate::model::Portfolio;
+use crate::show::portfolio_report;
+
+// ------------------------------------------------------------------------------------------------
+// Public Types
+// ------------------------------------------------------------------------------------------------
+
+pub const DEFAULT_REFRESH_DELAY: u64 = 60;
+
+/// Abstracts the passage of time so that the refresh loop can be driven by
+/// something other than the wall clock.
+pub trait Clock {
+    fn now(&self) -> DateTime<Local>;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+pub fn watch_portfolio<T: FetchPriceQuote + Sync>(portfolio: Portfolio, provider: T, delay: Duration, converter: Option<Converter>) {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_flag = running.clone();
+    match ctrlc::set_handler(move || handler_flag.store(false, Ordering::SeqCst)) {
+        Ok(_) => (),
+        Err(err) => warn!("Could not install Ctrl-C handler: {:?}", err),
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match watch_loop(&portfolio, &provider, converter.as_ref(), &SystemClock, delay, &running, &mut out) {
+        Ok(_) => (),
+        Err(err) => println!("Error writing to terminal: {:?}", err),
+    }
+}
+
+/// The refresh loop behind `watch_portfolio`; fetches quotes, redraws the table and
+/// then waits for `delay`, until `running` is cleared. A symbol whose quote could not be
+/// retrieved is an error row, and the number of them is written below the table. Returns
+/// the number of refreshes drawn.
+pub fn watch_loop<T: FetchPriceQuote + Sync, C: Clock, W: Write>(
+    portfolio: &Portfolio,
+    provider: &T,
+    converter: Option<&Converter>,
+    clock: &C,
+    delay: Duration,
+    running: &AtomicBool,
+    out: &mut W,
+) -> io::Result<u32> {
+    info!("watch::watch_loop every {:?}", delay);
+    let mut refreshes = 0;
+    while running.load(Ordering::SeqCst) {
+        write!(out, "{}", CLEAR_SCREEN)?;
+        let (report, failures) = portfolio_report(portfolio, provider, converter);
+        report.table().print(out)?;
+        if !failures.is_empty() {
+            writeln!(out, "Could not retrieve quotes for {} symbol(s)", failures.len())?;
+        }
+        if let Some(converter) = converter {
+            for rate in converter.used() {
+                writeln!(out, "Converted at {}", rate)?;
+            }
+        }
+        refreshes += 1;
+        writeln!(
+            out,
+            "Last updated {}, refreshing every {}s (Ctrl-C to exit)",
+            clock.now().format("%Y-%m-%d %H:%M:%S"),
+            delay.as_secs()
+        )?;
+        out.flush()?;
+        wait(clock, delay, running);
+    }
+    writeln!(out)?;
+    Ok(refreshes)
+}
+
+// ------------------------------------------------------------------------------------------------
+// Implementations
+// ------------------------------------------------------------------------------------------------
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+const CLEAR_SCREEN: &str = "\x1B[2J\x1B[H";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+fn wait<C: Clock>(clock: &C, delay: Duration, running: &AtomicBool) {
+    // sleep in short steps so that Ctrl-C doesn't have to wait for the whole delay.
+    let mut remaining = delay;
+    while running.load(Ordering::SeqCst) && remaining > Duration::from_secs(0) {
+        let step = if remaining < POLL_INTERVAL { remaining } else { POLL_INTERVAL };
+        clock.sleep(step);
+        remaining -= step;
+    }
+}
+
+// ------------------------------------------------------------------------------------------------
+// Unit Tests
+// ------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::sync::atomic::AtomicU32;
+
+    use chrono::TimeZone;
+    use fin_model::prelude::*;
+    use fin_model::provider::Provider;
+    use fin_model::quote::{LatestPrice, Quote, QuoteData};
+    use steel_cent::currency::with_code;
+
+    use crate::model::Item;
+
+    /// Quotes a price one dollar higher on each request.
+    struct RisingProvider {
+        requests: AtomicU32,
+    }
+
+    /// Starts at a fixed time, advances only when slept, and stops the loop once `limit`
+    /// has passed.
+    struct FakeClock<'a> {
+        elapsed: Cell<Duration>,
+        limit: Duration,
+        running: &'a AtomicBool,
+    }
+
+    impl Provider for RisingProvider {
+        fn name(&self) -> &str {
+            "rising"
+        }
+    }
+
+    impl FetchPriceQuote for RisingProvider {
+        fn real_time(&self, for_symbol: Symbol) -> RequestResult<Quote> {
+            let requests = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Quote {
+                symbol: for_symbol,
+                data: QuoteData {
+                    latest: LatestPrice {
+                        price: Money::of_major_minor(with_code("USD").unwrap(), 100 + requests as i32, 0),
+                        change: None,
+                        percentage: None,
+                    },
+                    range: None,
+                },
+            })
+        }
+    }
+
+    impl<'a> Clock for FakeClock<'a> {
+        fn now(&self) -> chrono::DateTime<Local> {
+            Local.with_ymd_and_hms(2020, 3, 2, 9, 30, 0).unwrap() + chrono::Duration::from_std(self.elapsed.get()).unwrap()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+            if self.elapsed.get() >= self.limit {
+                self.running.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn redraws_on_each_tick() {
+        let portfolio = Portfolio {
+            items: vec![Item::Watch("AAPL".to_string())],
+            ..Default::default()
+        };
+        let provider = RisingProvider { requests: AtomicU32::new(0) };
+        let running = AtomicBool::new(true);
+        let delay = Duration::from_secs(1);
+        let clock = FakeClock {
+            elapsed: Cell::new(Duration::from_secs(0)),
+            limit: delay * 3,
+            running: &running,
+        };
+        let mut out: Vec<u8> = Vec::new();
+
+        let refreshes = watch_loop(&portfolio, &provider, None, &clock, delay, &running, &mut out).unwrap();
+        assert_eq!(refreshes, 3);
+        assert_eq!(provider.requests.load(Ordering::SeqCst), 3);
 
-pub fn watch_portfolio(_portfolio: Portfolio) {}
+        let out = String::from_utf8(out).unwrap();
+        let screens: Vec<&str> = out.split(CLEAR_SCREEN).skip(1).collect();
+        assert_eq!(screens.len(), 3);
+        for (tick, screen) in screens.iter().enumerate() {
+            assert!(screen.contains("AAPL"), "{}", screen);
+            assert!(screen.contains(&format!("{}.00", 101 + tick)), "{}", screen);
+            assert!(
+                screen.contains(&format!("Last updated 2020-03-02 09:30:0{}, refreshing every 1s", tick)),
+                "{}",
+                screen
+            );
+        }
+    }
+}
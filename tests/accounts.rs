@@ -0,0 +1,252 @@
+use fin_model::prelude::*;
+use steel_cent::currency::with_code;
+
+use portfolio::accounts::{account_portfolio, merge};
+use portfolio::model::{read_str, write_string, Cash, Item, Portfolio, RealizedGain};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn merges_an_unchanged_account_without_change() {
+    let stored = stored();
+    for name in &["ira", "IRA", "taxable"] {
+        let view = account_portfolio(&stored, name).unwrap();
+        let merged = merge(&stored, name, &view);
+        assert_eq!(write_string(&merged).unwrap(), write_string(&stored).unwrap(), "account {}", name);
+    }
+}
+
+#[test]
+fn keeps_the_order_of_the_file() {
+    let stored = stored();
+    let mut view = account_portfolio(&stored, "ira").unwrap();
+    assert_eq!(symbols(&view.items), vec!["AAPL", "VTI", "TSLA"]);
+
+    // AAPL is sold and VOO bought, VTI stays in place and VOO follows it.
+    view.items.remove(0);
+    let voo = match &view.items[0] {
+        Item::Price(_, holding) => Item::Price("VOO".to_string(), holding.clone()),
+        Item::Watch(_) => panic!("expected the VTI holding"),
+    };
+    view.items.insert(1, voo);
+    let merged = merge(&stored, "ira", &view);
+    assert_eq!(symbols(&merged.items), vec!["MSFT", "VTI", "VOO", "TSLA"]);
+
+    // an account without holdings in the file has them added at the end.
+    let mut view = account_portfolio(&stored, "taxable").unwrap();
+    view.items.push(view_holding(&stored, "MSFT", "taxable"));
+    let merged = merge(&stored, "taxable", &view);
+    assert_eq!(symbols(&merged.items), vec!["AAPL", "MSFT", "VTI", "TSLA", "MSFT"]);
+}
+
+#[test]
+fn replaces_the_lots_and_cash_of_the_account() {
+    let stored = stored();
+    let usd = with_code("USD").unwrap();
+    let eur = with_code("EUR").unwrap();
+    let mut view = account_portfolio(&stored, "ira").unwrap();
+    assert_eq!(view.lots.iter().map(|lot| lot.id).collect::<Vec<u32>>(), vec![1, 3]);
+
+    view.lots.remove(0);
+    view.lots[0].quantity = 5;
+    view.cash[0].balance = Money::of_major(usd, 80);
+    view.cash.push(Cash {
+        balance: Money::of_major(eur, 10),
+        account: Some("ira".to_string()),
+    });
+    let merged = merge(&stored, "ira", &view);
+
+    // the changed lot takes the place of the last of the account's lots.
+    let lots: Vec<(u32, u32)> = merged.lots.iter().map(|lot| (lot.id, lot.quantity)).collect();
+    assert_eq!(lots, vec![(2, 20), (3, 5)]);
+    let cash: Vec<(Money, Option<&str>)> = merged.cash.iter().map(|cash| (cash.balance, cash.account.as_deref())).collect();
+    assert_eq!(
+        cash,
+        vec![
+            (Money::of_major(usd, 50), None),
+            (Money::of_major(usd, 80), Some("ira")),
+            (Money::of_major(eur, 10), Some("ira")),
+        ]
+    );
+}
+
+#[test]
+fn appends_new_realized_gains() {
+    let stored = stored();
+    let mut view = account_portfolio(&stored, "ira").unwrap();
+    assert_eq!(view.realized.len(), 1);
+
+    let sold = RealizedGain {
+        lot: 3,
+        quantity: 2,
+        sale_date: Date::from_ymd_opt(2021, 2, 1).unwrap(),
+        ..view.realized[0].clone()
+    };
+    view.realized.push(sold);
+    let merged = merge(&stored, "ira", &view);
+
+    // the stored gains are kept, in the other account too, and only the new one added.
+    let gains: Vec<(u32, Option<&str>)> = merged.realized.iter().map(|gain| (gain.lot, gain.account.as_deref())).collect();
+    assert_eq!(gains, vec![(1, Some("ira")), (2, None), (3, Some("ira"))]);
+}
+
+#[test]
+fn removes_watches_removed_from_the_account() {
+    let stored = stored();
+    let mut view = account_portfolio(&stored, "ira").unwrap();
+    view.items.retain(|item| match item {
+        Item::Watch(_) => false,
+        Item::Price(_, _) => true,
+    });
+    let merged = merge(&stored, "ira", &view);
+    assert_eq!(symbols(&merged.items), vec!["AAPL", "MSFT", "VTI"]);
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// Holdings, lots, cash and realized gains both in the "ira" account and outside any
+/// account, interleaved in the file.
+fn stored() -> Portfolio {
+    let source = r#"version = 2
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = false
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+account = "ira"
+
+[[holdings]]
+symbol = "MSFT"
+watch_only = false
+quantity = 5
+currency_major = 150
+currency_minor = 0
+currency_code = "USD"
+
+[[holdings]]
+symbol = "VTI"
+watch_only = false
+quantity = 20
+currency_major = 160
+currency_minor = 0
+currency_code = "USD"
+account = "ira"
+
+[[holdings]]
+symbol = "TSLA"
+watch_only = true
+
+[[lots]]
+id = 1
+symbol = "BND"
+quantity = 10
+currency_major = 80
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-02"
+account = "ira"
+
+[[lots]]
+id = 2
+symbol = "BND"
+quantity = 20
+currency_major = 81
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-03"
+
+[[lots]]
+id = 3
+symbol = "VTI"
+quantity = 8
+currency_major = 150
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-04"
+account = "ira"
+
+[[cash]]
+currency_major = 50
+currency_minor = 0
+currency_code = "USD"
+
+[[cash]]
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+account = "ira"
+
+[[realized]]
+lot = 1
+symbol = "BND"
+quantity = 2
+purchase_date = "2020-01-02"
+sale_date = "2020-06-01"
+account = "ira"
+
+[realized.purchase_price]
+currency_major = 80
+currency_minor = 0
+currency_code = "USD"
+
+[realized.sale_price]
+currency_major = 85
+currency_minor = 0
+currency_code = "USD"
+
+[[realized]]
+lot = 2
+symbol = "BND"
+quantity = 1
+purchase_date = "2020-01-03"
+sale_date = "2020-07-01"
+
+[realized.purchase_price]
+currency_major = 81
+currency_minor = 0
+currency_code = "USD"
+
+[realized.sale_price]
+currency_major = 84
+currency_minor = 0
+currency_code = "USD"
+
+[[accounts]]
+name = "ira"
+type = "tax-deferred"
+
+[[accounts]]
+name = "taxable"
+"#;
+    read_str(source).unwrap()
+}
+
+fn symbols(items: &[Item]) -> Vec<&str> {
+    items
+        .iter()
+        .map(|item| match item {
+            Item::Price(symbol, _) | Item::Watch(symbol) => symbol.as_str(),
+        })
+        .collect()
+}
+
+/// A holding of `symbol` as in the stored portfolio, moved to `account`.
+fn view_holding(stored: &Portfolio, symbol: &str, account: &str) -> Item {
+    for item in &stored.items {
+        if let Item::Price(s, holding) = item {
+            if s == symbol {
+                let mut holding = holding.clone();
+                holding.classification.account = Some(account.to_string());
+                return Item::Price(s.to_string(), holding);
+            }
+        }
+    }
+    panic!("no holding of {}", symbol)
+}
@@ -0,0 +1,39 @@
+// Helpers shared by the integration tests, each test file uses only some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// ------------------------------------------------------------------------------------------------
+// Public Functions
+// ------------------------------------------------------------------------------------------------
+
+/// The path of a file in tests/fixtures.
+pub fn fixture(name: &str) -> String {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("fixtures");
+    path.push(name);
+    path.to_string_lossy().to_string()
+}
+
+/// A path in the temporary directory, unique to this test process.
+pub fn scratch(name: &str) -> String {
+    let mut path = std::env::temp_dir();
+    path.push(format!("folio-{}-{}", process::id(), name));
+    path.to_string_lossy().to_string()
+}
+
+/// Copy a fixture to the temporary directory, so it may be changed.
+pub fn scratch_copy(name: &str) -> String {
+    let file_name = scratch(name);
+    fs::copy(fixture(name), &file_name).unwrap();
+    file_name
+}
+
+/// Remove a scratch file, and the backups made when it was written.
+pub fn remove_scratch(file_name: &str) {
+    fs::remove_dir_all(format!("{}.backups", file_name)).ok();
+    fs::remove_file(file_name).unwrap();
+}
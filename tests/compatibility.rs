@@ -0,0 +1,179 @@
+mod common;
+
+use std::fs;
+
+use fin_model::prelude::*;
+use steel_cent::currency::with_code;
+
+use common::{fixture, remove_scratch, scratch_copy};
+use portfolio::model::{
+    file_version, migrate_file, read_file, write_file, AccountType, CorporateAction, Item, ModelError, Portfolio,
+    Transaction, CURRENT_VERSION,
+};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn detects_each_version() {
+    assert_eq!(file_version(Some(fixture("v0-readme.toml"))).unwrap(), 0);
+    assert_eq!(file_version(Some(fixture("v1-holdings.toml"))).unwrap(), 1);
+    assert_eq!(file_version(Some(fixture("v1-full.toml"))).unwrap(), 1);
+    assert_eq!(file_version(Some(fixture("v2.toml"))).unwrap(), CURRENT_VERSION);
+}
+
+#[test]
+fn reads_version_0() {
+    let portfolio = read_file(Some(fixture("v0-readme.toml"))).unwrap();
+    assert_eq!(symbols(&portfolio), vec!["AAPL", "MSFT", "AMZN"]);
+    match &portfolio.items[2] {
+        Item::Price(_, holding) => {
+            assert_eq!(holding.quantity, 104);
+            assert_eq!(holding.purchase_price, usd(1786, 0));
+            assert_eq!(holding.purchase_date, None);
+        }
+        Item::Watch(_) => panic!("AMZN should be a holding"),
+    }
+}
+
+#[test]
+fn reads_version_1() {
+    let portfolio = read_file(Some(fixture("v1-holdings.toml"))).unwrap();
+    assert_eq!(symbols(&portfolio), vec!["AAPL", "MSFT", "AMZN"]);
+    match &portfolio.items[2] {
+        Item::Price(_, holding) => {
+            assert_eq!(holding.quantity, 104);
+            assert_eq!(holding.purchase_price, usd(1786, 0));
+            assert_eq!(holding.purchase_date, Some(Date::from_ymd_opt(2019, 8, 1).unwrap()));
+        }
+        Item::Watch(_) => panic!("AMZN should be a holding"),
+    }
+}
+
+#[test]
+fn reads_version_1_with_every_section() {
+    let portfolio = read_file(Some(fixture("v1-full.toml"))).unwrap();
+    assert_eq!(portfolio.benchmark, Some("SPY".to_string()));
+    match &portfolio.items[1] {
+        Item::Price(symbol, holding) => {
+            assert_eq!(symbol, "VTI");
+            assert_eq!(holding.purchase_price, usd(150, 25));
+            assert_eq!(holding.classification.tags, vec!["core", "index"]);
+            assert_eq!(holding.classification.account, Some("brokerage".to_string()));
+        }
+        Item::Watch(_) => panic!("VTI should be a holding"),
+    }
+    assert_eq!(portfolio.transactions.len(), 3);
+    match &portfolio.transactions[1] {
+        Transaction::Dividend(_, symbol, amount) => {
+            assert_eq!(symbol, "MSFT");
+            assert_eq!(*amount, usd(4, 60));
+        }
+        other => panic!("expected a dividend, not {:?}", other),
+    }
+    assert_eq!(portfolio.lots.len(), 1);
+    assert_eq!(portfolio.lots[0].quantity, 4);
+    assert_eq!(portfolio.realized.len(), 1);
+    assert_eq!(portfolio.realized[0].sale_price, usd(1789, 84));
+    assert_eq!(portfolio.cash.len(), 1);
+    assert_eq!(portfolio.cash[0].balance, usd(2500, 0));
+    match &portfolio.actions[0] {
+        CorporateAction::Split(_, symbol, ratio) => {
+            assert_eq!(symbol, "AAPL");
+            assert_eq!((ratio.to, ratio.from), (4, 1));
+        }
+        other => panic!("expected a split, not {:?}", other),
+    }
+    assert_eq!(portfolio.allocation.as_ref().unwrap().targets.len(), 2);
+    assert_eq!(portfolio.accounts.len(), 1);
+    assert_eq!(portfolio.accounts[0].account_type, AccountType::TaxDeferred);
+    assert_eq!(portfolio.accounts[0].transactions.len(), 1);
+}
+
+#[test]
+fn reads_current_version() {
+    let portfolio = read_file(Some(fixture("v2.toml"))).unwrap();
+    assert_eq!(symbols(&portfolio), vec!["AAPL", "AMZN"]);
+}
+
+#[test]
+fn rejects_newer_version() {
+    match read_file(Some(fixture("v99-future.toml"))) {
+        Err(ModelError::VersionError(_)) => (),
+        Err(err) => panic!("expected a version error, not {:?}", err),
+        Ok(_) => panic!("a newer version should not be read"),
+    }
+}
+
+#[test]
+fn migrates_every_version() {
+    for name in &["v0-readme.toml", "v1-holdings.toml", "v1-full.toml"] {
+        let original = read_file(Some(fixture(name))).unwrap();
+        let file_name = scratch_copy(name);
+
+        let (version, backup) = migrate_file(Some(file_name.to_string())).unwrap();
+        assert!(version < CURRENT_VERSION);
+        let backup = backup.expect("a backup should be made");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), fs::read_to_string(fixture(name)).unwrap());
+        assert_eq!(file_version(Some(file_name.to_string())).unwrap(), CURRENT_VERSION);
+
+        let migrated = read_file(Some(file_name.to_string())).unwrap();
+        assert_same(&original, &migrated);
+
+        // a current file is left alone.
+        let (version, backup) = migrate_file(Some(file_name.to_string())).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+        assert!(backup.is_none());
+
+        fs::remove_file(backup_name(&file_name, 0)).ok();
+        fs::remove_file(backup_name(&file_name, 1)).ok();
+        remove_scratch(&file_name);
+    }
+}
+
+#[test]
+fn writes_current_version() {
+    let portfolio = read_file(Some(fixture("v1-full.toml"))).unwrap();
+    let file_name = scratch_copy("v1-full.toml");
+    write_file(Some(file_name.to_string()), &portfolio).unwrap();
+    assert_eq!(file_version(Some(file_name.to_string())).unwrap(), CURRENT_VERSION);
+    assert_same(&portfolio, &read_file(Some(file_name.to_string())).unwrap());
+    remove_scratch(&file_name);
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn backup_name(file_name: &str, version: u32) -> String {
+    format!("{}.v{}.bak", file_name, version)
+}
+
+fn usd(major: i32, minor: i32) -> Money {
+    Money::of_major_minor(with_code("USD").unwrap(), major, minor)
+}
+
+fn symbols(portfolio: &Portfolio) -> Vec<Symbol> {
+    portfolio
+        .items
+        .iter()
+        .map(|item| match item {
+            Item::Watch(symbol) | Item::Price(symbol, _) => symbol.to_string(),
+        })
+        .collect()
+}
+
+/// The model types are not comparable, but their debug output is.
+fn assert_same(expected: &Portfolio, actual: &Portfolio) {
+    assert_eq!(expected.default_currency, actual.default_currency);
+    assert_eq!(expected.benchmark, actual.benchmark);
+    assert_eq!(format!("{:?}", expected.items), format!("{:?}", actual.items));
+    assert_eq!(format!("{:?}", expected.transactions), format!("{:?}", actual.transactions));
+    assert_eq!(format!("{:?}", expected.lots), format!("{:?}", actual.lots));
+    assert_eq!(format!("{:?}", expected.realized), format!("{:?}", actual.realized));
+    assert_eq!(format!("{:?}", expected.cash), format!("{:?}", actual.cash));
+    assert_eq!(format!("{:?}", expected.actions), format!("{:?}", actual.actions));
+    assert_eq!(format!("{:?}", expected.allocation), format!("{:?}", actual.allocation));
+    assert_eq!(format!("{:?}", expected.accounts), format!("{:?}", actual.accounts));
+}
@@ -0,0 +1,205 @@
+use fin_model::prelude::*;
+use steel_cent::currency::with_code;
+
+use portfolio::cash::cash_balances;
+use portfolio::holdings::{add_holding, update_holding, HoldingUpdate, UpdateError};
+use portfolio::lots::sell;
+use portfolio::model::{read_str, Classification, Holding, Item};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn pays_for_shares_added_and_sold() {
+    let source = r#"version = 2
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = false
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-02"
+
+[[cash]]
+currency_major = 1000
+currency_minor = 0
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let update = HoldingUpdate {
+        add_quantity: Some(10),
+        purchase_price: Some(Money::of_major_minor(usd, 110, 0)),
+        purchase_date: Some(Date::from_ymd_opt(2020, 6, 1).unwrap()),
+        ..Default::default()
+    };
+    let portfolio = update_holding(&portfolio, &"AAPL".to_string(), &update, usd).unwrap();
+    let balances = cash_balances(&portfolio);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].balance, Money::of_major_minor(usd, -100, 0));
+
+    let date = Date::from_ymd_opt(2021, 1, 4).unwrap();
+    let (portfolio, realized) = sell(portfolio, &"AAPL".to_string(), 20, Money::of_major_minor(usd, 120, 0), date, None).unwrap();
+    assert_eq!(realized.len(), 2);
+    let balances = cash_balances(&portfolio);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].balance, Money::of_major_minor(usd, 2300, 0));
+    assert_eq!(balances[0].account, None);
+}
+
+#[test]
+fn keeps_no_cash_when_none_is_tracked() {
+    let source = r#"version = 2
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = false
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-02"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let holding = Holding {
+        quantity: 5,
+        purchase_price: Money::of_major_minor(usd, 300, 0),
+        purchase_date: Some(Date::from_ymd_opt(2020, 6, 1).unwrap()),
+        classification: Classification::default(),
+    };
+    let portfolio = add_holding(&portfolio, &"MSFT".to_string(), holding);
+    assert_eq!(portfolio.items.len(), 2);
+    assert!(cash_balances(&portfolio).is_empty());
+
+    let update = HoldingUpdate {
+        add_quantity: Some(10),
+        purchase_price: Some(Money::of_major_minor(usd, 110, 0)),
+        purchase_date: Some(Date::from_ymd_opt(2020, 6, 1).unwrap()),
+        ..Default::default()
+    };
+    let portfolio = update_holding(&portfolio, &"AAPL".to_string(), &update, usd).unwrap();
+    assert!(cash_balances(&portfolio).is_empty());
+}
+
+#[test]
+fn limits_amounts_too_large_to_hold() {
+    let source = r#"version = 2
+
+[[cash]]
+currency_major = 1000
+currency_minor = 0
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let holding = Holding {
+        quantity: 3_000_000_000,
+        purchase_price: Money::of_major_minor(usd, 1000, 0),
+        purchase_date: Some(Date::from_ymd_opt(2020, 6, 1).unwrap()),
+        classification: Classification::default(),
+    };
+    // the cost is beyond the range of `Money`, so it is limited to the largest amount.
+    let portfolio = add_holding(&portfolio, &"BRK.A".to_string(), holding);
+    assert_eq!(cash_balances(&portfolio)[0].balance, Money::of_major_minor(usd, 1000, 0) - Money::max(usd));
+}
+
+#[test]
+fn chooses_an_entry_held_more_than_once() {
+    let source = r#"version = 2
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = false
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-02"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let symbol = "AAPL".to_string();
+    let added = HoldingUpdate {
+        add_quantity: Some(5),
+        purchase_date: Some(Date::from_ymd_opt(2020, 6, 1).unwrap()),
+        ..Default::default()
+    };
+    let portfolio = update_holding(&portfolio, &symbol, &added, usd).unwrap();
+    assert_eq!(portfolio.items.len(), 2);
+
+    let update = HoldingUpdate { quantity: Some(12), ..Default::default() };
+    match update_holding(&portfolio, &symbol, &update, usd) {
+        Err(UpdateError::Ambiguous(_, 2)) => (),
+        other => panic!("expected an ambiguous update, not {:?}", other.map(|p| p.items)),
+    }
+    match update_holding(&portfolio, &symbol, &added, usd) {
+        Err(UpdateError::Ambiguous(_, 2)) => (),
+        other => panic!("expected an ambiguous update, not {:?}", other.map(|p| p.items)),
+    }
+    match update_holding(&portfolio, &symbol, &HoldingUpdate { entry: Some(3), ..update.clone() }, usd) {
+        Err(UpdateError::UnknownEntry(_, 3, 2)) => (),
+        other => panic!("expected an unknown entry, not {:?}", other.map(|p| p.items)),
+    }
+
+    let portfolio = update_holding(&portfolio, &symbol, &HoldingUpdate { entry: Some(1), ..update }, usd).unwrap();
+    let quantities: Vec<u32> = portfolio
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Price(_, holding) => Some(holding.quantity),
+            Item::Watch(_) => None,
+        })
+        .collect();
+    assert_eq!(quantities, vec![12, 5]);
+}
+
+#[test]
+fn keeps_the_exact_cost_of_shares_added_on_the_same_date() {
+    let source = r#"version = 2
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = false
+quantity = 2
+currency_major = 10
+currency_minor = 0
+currency_code = "USD"
+purchase_date = "2020-01-02"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let update = HoldingUpdate {
+        add_quantity: Some(1),
+        purchase_price: Some(Money::of_major_minor(usd, 10, 2)),
+        purchase_date: Some(Date::from_ymd_opt(2020, 1, 2).unwrap()),
+        ..Default::default()
+    };
+    // an average of $10.00666… can't be held exactly, so the shares are kept apart.
+    let portfolio = update_holding(&portfolio, &"AAPL".to_string(), &update, usd).unwrap();
+    match &portfolio.items[..] {
+        [Item::Price(_, first), Item::Price(_, added)] => {
+            assert_eq!((first.quantity, first.purchase_price), (2, Money::of_major_minor(usd, 10, 0)));
+            assert_eq!((added.quantity, added.purchase_price), (1, Money::of_major_minor(usd, 10, 2)));
+        }
+        other => panic!("expected two holdings, not {:?}", other),
+    }
+
+    // two more make an average of $10.01, which is exact, so the shares are merged.
+    let update = HoldingUpdate {
+        entry: Some(1),
+        add_quantity: Some(2),
+        ..update
+    };
+    let portfolio = update_holding(&portfolio, &"AAPL".to_string(), &update, usd).unwrap();
+    match &portfolio.items[..] {
+        [Item::Price(_, merged), Item::Price(_, _)] => {
+            assert_eq!(merged.quantity, 4);
+            assert_eq!(merged.purchase_price, Money::of_major_minor(usd, 10, 1));
+        }
+        other => panic!("expected the first holding merged, not {:?}", other),
+    }
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::fs;
+
+use common::scratch;
+use portfolio::journal::{
+    apply, changes, describe, read_journal, record, redoable, undoable, EntryKind, JournalError,
+};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+const ORIGINAL: &str = r#"version = 2
+default_currency = "USD"
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = true
+"#;
+
+const CHANGED: &str = r#"version = 2
+default_currency = "USD"
+
+[[holdings]]
+symbol = "AAPL"
+watch_only = true
+
+[[holdings]]
+symbol = "MSFT"
+watch_only = true
+"#;
+
+#[test]
+fn records_only_sections_changed() {
+    let found = changes(ORIGINAL, CHANGED).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].section, "holdings");
+    assert_eq!(describe(&found[0]), "holdings +MSFT");
+
+    assert!(changes(ORIGINAL, &ORIGINAL.replace("version = 2", "version = 1")).unwrap().is_empty());
+}
+
+#[test]
+fn records_each_entry_changed() {
+    let quantities = |aapl: u32, msft: u32| {
+        format!(
+            "version = 2\n\n[[holdings]]\nsymbol = \"AAPL\"\nwatch_only = false\nquantity = {}\n\n\
+             [[holdings]]\nsymbol = \"GOOG\"\nwatch_only = true\n\n\
+             [[holdings]]\nsymbol = \"MSFT\"\nwatch_only = false\nquantity = {}\n",
+            aapl, msft
+        )
+    };
+    let before = quantities(10, 5);
+    let after = quantities(15, 5).replace("GOOG", "AMZN");
+
+    let found = changes(&before, &after).unwrap();
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert_eq!((found[0].section.as_str(), found[0].index), ("holdings", Some(0)));
+    assert_eq!(describe(&found[0]), "holdings AAPL quantity 10 -> 15");
+    assert_eq!(found[1].index, Some(1));
+    assert_eq!(describe(&found[1]), "holdings GOOG symbol \"GOOG\" -> \"AMZN\"");
+
+    let file_name = scratch("entries.toml");
+    let edit = record(&file_name, "update AAPL", EntryKind::Edit, None, found).unwrap().unwrap();
+    // another entry, changed by hand since, doesn't stop the change being reversed.
+    let edited = after.replace("quantity = 5", "quantity = 7");
+    let undone = apply(&edited, &edit, true).unwrap();
+    assert!(changes(&quantities(10, 7), &undone).unwrap().is_empty());
+
+    fs::remove_file(format!("{}.journal", file_name)).unwrap();
+}
+
+#[test]
+fn undoes_and_redoes_changes() {
+    let file_name = scratch("undo.toml");
+    let edit = record(&file_name, "add MSFT", EntryKind::Edit, None, changes(ORIGINAL, CHANGED).unwrap())
+        .unwrap()
+        .expect("the change should be recorded");
+    assert!(record(&file_name, "add AAPL", EntryKind::Edit, None, Vec::new()).unwrap().is_none());
+
+    let entries = read_journal(&file_name).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(undoable(&entries).map(|entry| entry.id), Some(edit.id));
+    assert!(redoable(&entries).is_none());
+
+    let undone = apply(CHANGED, &edit, true).unwrap();
+    assert!(changes(ORIGINAL, &undone).unwrap().is_empty());
+    record(&file_name, "undo", EntryKind::Undo, Some(edit.id), changes(CHANGED, &undone).unwrap()).unwrap();
+
+    let entries = read_journal(&file_name).unwrap();
+    assert!(undoable(&entries).is_none());
+    assert_eq!(redoable(&entries).map(|entry| entry.id), Some(edit.id));
+
+    let redone = apply(&undone, &edit, false).unwrap();
+    assert!(changes(CHANGED, &redone).unwrap().is_empty());
+
+    fs::remove_file(format!("{}.journal", file_name)).unwrap();
+}
+
+#[test]
+fn refuses_to_overwrite_other_changes() {
+    let found = changes(ORIGINAL, CHANGED).unwrap();
+    let file_name = scratch("conflict.toml");
+    let edit = record(&file_name, "add MSFT", EntryKind::Edit, None, found).unwrap().unwrap();
+
+    let edited = CHANGED.replace("MSFT", "AMZN");
+    match apply(&edited, &edit, true) {
+        Err(JournalError::Conflict(id, section)) => {
+            assert_eq!(id, edit.id);
+            assert_eq!(section, "holdings");
+        }
+        other => panic!("expected a conflict, not {:?}", other),
+    }
+
+    fs::remove_file(format!("{}.journal", file_name)).unwrap();
+}
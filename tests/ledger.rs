@@ -0,0 +1,329 @@
+mod common;
+
+use fin_model::prelude::*;
+use steel_cent::currency::with_code;
+
+use common::fixture;
+use portfolio::actions::has_split;
+use portfolio::cash::cash_balances;
+use portfolio::fixture::FileProvider;
+use portfolio::gains::portfolio_gains;
+use portfolio::history::PriceFile;
+use portfolio::income::{income, IncomeSource};
+use portfolio::ledger::reinvested_trades;
+use portfolio::model::read_str;
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn applies_actions_to_cash_and_gains() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "AAPL"
+quantity = 10
+currency_major = 400
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-09-01"
+action = "sell"
+symbol = "AAPL"
+quantity = 40
+currency_major = 120
+currency_minor = 0
+currency_code = "USD"
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+
+    // all 40 shares held after the split are sold, not only the 10 bought.
+    let balances = cash_balances(&portfolio);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].balance, Money::of_major_minor(usd, 800, 0));
+    assert_eq!(balances[0].account, None);
+
+    let provider = FileProvider::open(&fixture("quotes.toml")).unwrap();
+    let (gains, failures) = portfolio_gains(&portfolio, &provider, None);
+    assert!(failures.is_empty(), "{:?}", failures);
+    assert_eq!(gains.len(), 1);
+    assert_eq!(gains[0].symbol.as_deref(), Some("AAPL"));
+    assert_eq!(gains[0].quantity, 0);
+    assert_eq!(gains[0].realized, Money::of_major_minor(usd, 800, 0));
+    assert_eq!(gains[0].error, None);
+}
+
+#[test]
+fn finds_splits_already_recorded() {
+    let source = r#"version = 2
+
+[[accounts]]
+name = "ira"
+
+[[accounts.transactions]]
+date = "2022-07-18"
+action = "split"
+symbol = "GOOGL"
+ratio = "20:1"
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let date = |s: &str| Date::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+    assert!(has_split(&portfolio, &"aapl".to_string(), date("2020-08-31")));
+    assert!(has_split(&portfolio, &"GOOGL".to_string(), date("2022-07-18")));
+    assert!(!has_split(&portfolio, &"AAPL".to_string(), date("2020-09-01")));
+    assert!(!has_split(&portfolio, &"TSLA".to_string(), date("2020-08-31")));
+}
+
+#[test]
+fn totals_recorded_dividends_by_year() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2019-01-02"
+action = "buy"
+symbol = "VTI"
+quantity = 10
+currency_major = 150
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2019-01-02"
+action = "buy"
+symbol = "BND"
+quantity = 20
+currency_major = 80
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2019-06-03"
+action = "dividend"
+symbol = "VTI"
+currency_major = 5
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-02-03"
+action = "dividend"
+symbol = "BND"
+currency_major = 3
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-03-02"
+action = "dividend"
+symbol = "VTI"
+currency_major = 7
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-05-01"
+action = "sell"
+symbol = "BND"
+quantity = 20
+currency_major = 82
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-06-01"
+action = "dividend"
+symbol = "VTI"
+currency_major = 8
+currency_minor = 50
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+
+    let incomes = income(&portfolio, None::<&PriceFile>, 2019).unwrap();
+    assert_eq!(incomes.len(), 2);
+    assert_eq!(incomes[0].symbol, "VTI");
+    assert_eq!(incomes[0].cash, Money::of_major_minor(usd, 5, 0));
+    assert_eq!(incomes[1].symbol, "BND");
+    assert_eq!(incomes[1].total(), Some(Money::zero(usd)));
+
+    // BND was sold during 2020, its dividend is still income for the year.
+    let incomes = income(&portfolio, None::<&PriceFile>, 2020).unwrap();
+    let found: Vec<(&str, u32, Option<Money>, IncomeSource)> = incomes
+        .iter()
+        .map(|income| (income.symbol.as_str(), income.quantity, income.total(), income.source))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("VTI", 10, Some(Money::of_major_minor(usd, 15, 50)), IncomeSource::Recorded),
+            ("BND", 0, Some(Money::of_major_minor(usd, 3, 0)), IncomeSource::Recorded),
+        ]
+    );
+    assert_eq!(incomes[0].reinvested, Money::zero(usd));
+    assert_eq!(incomes[0].cost_basis, Money::of_major_minor(usd, 1500, 0));
+}
+
+#[test]
+fn counts_reinvested_dividends_as_income() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "VTI"
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-03-02"
+action = "dividend"
+symbol = "VTI"
+currency_major = 20
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-06-01"
+action = "reinvest"
+symbol = "VTI"
+quantity = 2
+currency_major = 15
+currency_minor = 0
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+    let incomes = income(&portfolio, None::<&PriceFile>, 2020).unwrap();
+    assert_eq!(incomes.len(), 1);
+    let vti = &incomes[0];
+    assert_eq!(vti.quantity, 12);
+    assert_eq!(vti.cash, Money::of_major_minor(usd, 20, 0));
+    assert_eq!(vti.reinvested, Money::of_major_minor(usd, 30, 0));
+    assert_eq!(vti.total(), Some(Money::of_major_minor(usd, 50, 0)));
+    // the reinvested shares add to the cost basis, and their dividend to the yield.
+    assert_eq!(vti.cost_basis, Money::of_major_minor(usd, 1030, 0));
+    assert_eq!(vti.yield_on_cost().map(|percent| (percent * 100.0).round() / 100.0), Some(4.85));
+
+    // a reinvested dividend is paid and spent without changing cash.
+    let balances = cash_balances(&portfolio);
+    assert_eq!(balances.len(), 1);
+    assert_eq!(balances[0].balance, Money::of_major_minor(usd, -980, 0));
+}
+
+#[test]
+fn has_no_income_in_more_than_one_currency() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "SAP"
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "EUR"
+
+[[transactions]]
+date = "2020-03-02"
+action = "dividend"
+symbol = "SAP"
+currency_major = 20
+currency_minor = 0
+currency_code = "EUR"
+
+[[transactions]]
+date = "2020-06-01"
+action = "dividend"
+symbol = "SAP"
+currency_major = 22
+currency_minor = 0
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let incomes = income(&portfolio, None::<&PriceFile>, 2020).unwrap();
+    assert_eq!(incomes.len(), 1);
+    // neither dividend is left out, the income is an error instead.
+    assert_eq!(incomes[0].error, Some("amounts in both EUR and USD".to_string()));
+}
+
+#[test]
+fn realizes_gains_within_each_account() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "MSFT"
+quantity = 10
+currency_major = 100
+currency_minor = 0
+currency_code = "USD"
+
+[[accounts]]
+name = "IRA"
+
+[[accounts.transactions]]
+date = "2020-02-03"
+action = "buy"
+symbol = "MSFT"
+quantity = 10
+currency_major = 200
+currency_minor = 0
+currency_code = "USD"
+
+[[accounts.transactions]]
+date = "2020-03-02"
+action = "sell"
+symbol = "MSFT"
+quantity = 10
+currency_major = 210
+currency_minor = 0
+currency_code = "USD"
+"#;
+    let portfolio = read_str(source).unwrap();
+    let usd = with_code("USD").unwrap();
+
+    // the sale is from the account's own shares, not an average with the portfolio's.
+    let provider = FileProvider::open(&fixture("quotes.toml")).unwrap();
+    let (gains, _) = portfolio_gains(&portfolio, &provider, None);
+    let gain = gains.iter().find(|gain| gain.symbol.as_deref() == Some("MSFT")).unwrap();
+    assert_eq!(gain.quantity, 10);
+    assert_eq!(gain.cost_basis, Money::of_major_minor(usd, 1000, 0));
+    assert_eq!(gain.realized, Money::of_major_minor(usd, 100, 0));
+}
+
+#[test]
+fn reinvests_the_exact_amount() {
+    let usd = with_code("USD").unwrap();
+    let trades = reinvested_trades(Money::of_major_minor(usd, 100, 0), 3);
+    let quantities: Vec<u32> = trades.iter().map(|trade| trade.quantity).collect();
+    let prices: Vec<Money> = trades.iter().map(|trade| trade.price).collect();
+    assert_eq!(quantities, vec![1, 2]);
+    assert_eq!(prices, vec![Money::of_major_minor(usd, 33, 34), Money::of_major_minor(usd, 33, 33)]);
+
+    let trades = reinvested_trades(Money::of_major_minor(usd, 90, 0), 3);
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].quantity, 3);
+    assert_eq!(trades[0].price, Money::of_major_minor(usd, 30, 0));
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+
+use common::fixture;
+use portfolio::fixture::FileProvider;
+use portfolio::fx::{read_rates_file, FxError};
+use portfolio::model::{read_file, Item};
+use portfolio::report::Value;
+use portfolio::show::portfolio_report;
+use portfolio::watch::{watch_loop, Clock};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn shows_quotes_from_a_file() {
+    let mut portfolio = read_file(Some(fixture("v2.toml"))).unwrap();
+    portfolio.items.push(Item::Watch("MSFT".to_string()));
+    let provider = FileProvider::open(&fixture("quotes.toml")).unwrap();
+    let (report, failures) = portfolio_report(&portfolio, &provider, None);
+
+    let row = |symbol: &str| {
+        report
+            .rows
+            .iter()
+            .find(|row| match row.first() {
+                Some(Value::Text(s)) => s == symbol,
+                _ => false,
+            })
+            .unwrap_or_else(|| panic!("no row for {}", symbol))
+    };
+    match &row("AAPL")[1] {
+        Value::Money(price) => assert_eq!(price.minor_amount(), 15025),
+        other => panic!("expected the AAPL price, not {:?}", other),
+    }
+    match &row("AMZN")[1] {
+        Value::Money(price) => assert_eq!(price.minor_amount(), 180050),
+        other => panic!("expected the AMZN price, not {:?}", other),
+    }
+
+    // a symbol without a quote in the file is still shown.
+    assert_eq!(report.rows.len(), 3);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "MSFT");
+    match &row("MSFT")[1] {
+        Value::Error(reason) => assert!(reason.contains("no quote for MSFT"), "{}", reason),
+        other => panic!("expected an error for MSFT, not {:?}", other),
+    }
+}
+
+#[test]
+fn rejects_rates_that_are_not_positive() {
+    match read_rates_file(&fixture("invalid-rates.toml")) {
+        Err(FxError::FormatError(reason)) => assert!(reason.contains("from EUR to USD is not positive"), "{}", reason),
+        other => panic!("expected a format error, not {:?}", other),
+    }
+}
+
+#[test]
+fn watches_quotes_from_a_file() {
+    let mut portfolio = read_file(Some(fixture("v2.toml"))).unwrap();
+    portfolio.items.push(Item::Watch("MSFT".to_string()));
+    let provider = FileProvider::open(&fixture("quotes.toml")).unwrap();
+    let running = AtomicBool::new(true);
+    let clock = StoppingClock {
+        slept: Cell::new(Duration::from_secs(0)),
+        running: &running,
+    };
+    let mut out: Vec<u8> = Vec::new();
+
+    let refreshes = watch_loop(&portfolio, &provider, None, &clock, Duration::from_secs(5), &running, &mut out).unwrap();
+    assert_eq!(refreshes, 1);
+    assert_eq!(clock.slept.get(), Duration::from_secs(5));
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("$150.25"), "{}", out);
+    // a symbol without a quote is an error row, and counted below the table.
+    assert!(out.contains("no quote for MSFT"), "{}", out);
+    assert!(out.contains("Could not retrieve quotes for 1 symbol(s)"), "{}", out);
+    assert!(out.contains("Last updated 2020-03-02 09:30:00, refreshing every 5s"), "{}", out);
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+/// A clock fixed at one time, that stops the watch loop after its first full delay.
+struct StoppingClock<'a> {
+    slept: Cell<Duration>,
+    running: &'a AtomicBool,
+}
+
+impl<'a> Clock for StoppingClock<'a> {
+    fn now(&self) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2020, 3, 2, 9, 30, 0).unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.slept.set(self.slept.get() + duration);
+        if self.slept.get() >= Duration::from_secs(5) {
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::fs;
+use std::process;
+
+use common::{remove_scratch, scratch};
+use portfolio::storage::{backups, find_backup, lock, restore, write_atomic, BACKUPS_KEPT};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn keeps_rotating_backups() {
+    let file_name = scratch("rotate.toml");
+    for version in 0..BACKUPS_KEPT + 3 {
+        write_atomic(&file_name, format!("version = {}\n", version).as_bytes()).unwrap();
+    }
+    assert_eq!(fs::read_to_string(&file_name).unwrap(), format!("version = {}\n", BACKUPS_KEPT + 2));
+
+    let kept = backups(&file_name);
+    assert_eq!(kept.len(), BACKUPS_KEPT);
+    // the newest backup is the version replaced by the last write.
+    assert_eq!(fs::read_to_string(&kept[0].file_name).unwrap(), format!("version = {}\n", BACKUPS_KEPT + 1));
+    assert!(kept.windows(2).all(|pair| pair[0].taken >= pair[1].taken));
+    assert!(!fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().ends_with(&format!("{}.tmp", process::id()))));
+
+    remove_scratch(&file_name);
+}
+
+#[test]
+fn restores_a_backup() {
+    let file_name = scratch("restore.toml");
+    write_atomic(&file_name, b"version = 1\n").unwrap();
+    write_atomic(&file_name, b"version = 2\n").unwrap();
+
+    let backup = find_backup(&file_name, "1").unwrap();
+    restore(&file_name, &backup).unwrap();
+    assert_eq!(fs::read_to_string(&file_name).unwrap(), "version = 1\n");
+
+    // the restored-over version is itself kept, so the restore may be undone.
+    let newest = find_backup(&file_name, "1").unwrap();
+    assert_eq!(fs::read_to_string(&newest.file_name).unwrap(), "version = 2\n");
+    assert!(find_backup(&file_name, "no-such-backup").is_err());
+
+    remove_scratch(&file_name);
+}
+
+#[cfg(unix)]
+#[test]
+fn keeps_permissions_and_links() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let file_name = scratch("private.toml");
+    write_atomic(&file_name, b"version = 1\n").unwrap();
+    fs::set_permissions(&file_name, fs::Permissions::from_mode(0o600)).unwrap();
+    let link_name = scratch("link.toml");
+    symlink(&file_name, &link_name).unwrap();
+
+    write_atomic(&link_name, b"version = 2\n").unwrap();
+    assert!(fs::symlink_metadata(&link_name).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_to_string(&file_name).unwrap(), "version = 2\n");
+    assert_eq!(fs::metadata(&file_name).unwrap().permissions().mode() & 0o777, 0o600);
+
+    // the backup is kept beside the name written to, the link.
+    remove_scratch(&link_name);
+    fs::remove_file(&file_name).unwrap();
+}
+
+#[test]
+fn lock_is_released_when_dropped() {
+    let file_name = scratch("lock.toml");
+    {
+        let _held = lock(&file_name).unwrap();
+    }
+    let _again = lock(&file_name).unwrap();
+    drop(_again);
+    fs::remove_file(format!("{}.lock", file_name)).unwrap();
+}
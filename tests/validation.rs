@@ -0,0 +1,262 @@
+mod common;
+
+use common::{fixture, scratch};
+use portfolio::model::{read_file, read_str, Location, ModelError};
+
+// ------------------------------------------------------------------------------------------------
+// Tests
+// ------------------------------------------------------------------------------------------------
+
+#[test]
+fn reports_every_problem() {
+    let problems = match read_file(Some(fixture("invalid.toml"))) {
+        Err(ModelError::Invalid(problems)) => problems,
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("an invalid file should not be read"),
+    };
+    assert_eq!(problems.len(), 7, "{:?}", problems);
+
+    match &problems[0] {
+        ModelError::UnknownCurrency(code, location) => {
+            assert_eq!(code, "USX");
+            assert_at(location, 3, 20);
+        }
+        other => panic!("expected an unknown currency, not {:?}", other),
+    }
+    match &problems[1] {
+        ModelError::DuplicateSymbol(symbol, location) => {
+            assert_eq!(symbol, "AAPL");
+            assert_at(location, 10, 10);
+            assert_eq!(location.entry, "holding AAPL");
+        }
+        other => panic!("expected a duplicate symbol, not {:?}", other),
+    }
+    match &problems[2] {
+        ModelError::InvalidDate(date, location) => {
+            assert_eq!(date, "2019-13-01");
+            assert_at(location, 20, 17);
+            assert_eq!(location.entry, "holding AMZN");
+        }
+        other => panic!("expected an invalid date, not {:?}", other),
+    }
+    match (&problems[3], &problems[4]) {
+        (ModelError::MissingPrice(price_symbol, price_at), ModelError::MissingQuantity(quantity_symbol, quantity_at)) => {
+            assert_eq!(price_symbol, "MSFT");
+            assert_eq!(quantity_symbol, "MSFT");
+            assert_at(price_at, 22, 1);
+            assert_at(quantity_at, 22, 1);
+        }
+        other => panic!("expected a missing price and quantity, not {:?}", other),
+    }
+    match &problems[5] {
+        ModelError::InvalidEntry(message, location) => {
+            assert!(message.contains("bought"), "{}", message);
+            assert_at(location, 26, 1);
+            assert_eq!(location.entry, "transaction 1");
+        }
+        other => panic!("expected an invalid transaction, not {:?}", other),
+    }
+    match &problems[6] {
+        ModelError::UnknownCurrency(code, location) => {
+            assert_eq!(code, "ZZZ");
+            assert_at(location, 49, 17);
+            assert_eq!(location.entry, "realized gain on lot 2");
+        }
+        other => panic!("expected an unknown currency, not {:?}", other),
+    }
+}
+
+#[test]
+fn reports_trades_in_another_currency() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "AAPL"
+quantity = 10
+currency_major = 300
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-02-03"
+action = "buy"
+symbol = "AAPL"
+quantity = 5
+currency_major = 280
+currency_minor = 0
+currency_code = "EUR"
+"#;
+    match read_str(source) {
+        Err(ModelError::Invalid(problems)) => match &problems[..] {
+            [ModelError::InvalidEntry(message, location)] => {
+                assert!(message.contains("first bought in USD"), "{}", message);
+                assert_at(location, 19, 17);
+                assert_eq!(location.entry, "transaction 2");
+            }
+            other => panic!("expected one invalid transaction, not {:?}", other),
+        },
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("a purchase in another currency should not be read"),
+    }
+}
+
+#[test]
+fn reports_selling_more_than_held() {
+    let source = r#"version = 2
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+
+[[transactions]]
+date = "2020-01-02"
+action = "buy"
+symbol = "AAPL"
+quantity = 10
+currency_major = 300
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-10-01"
+action = "sell"
+symbol = "AAPL"
+quantity = 1
+currency_major = 110
+currency_minor = 0
+currency_code = "USD"
+
+[[transactions]]
+date = "2020-09-01"
+action = "sell"
+symbol = "AAPL"
+quantity = 40
+currency_major = 120
+currency_minor = 0
+currency_code = "USD"
+"#;
+    // the 40 shares held after the split are sold first, the later sale is one too many.
+    match read_str(source) {
+        Err(ModelError::Invalid(problems)) => match &problems[..] {
+            [ModelError::InvalidEntry(message, location)] => {
+                assert!(message.contains("more than the 0 held"), "{}", message);
+                assert_at(location, 22, 12);
+                assert_eq!(location.entry, "transaction 2");
+            }
+            other => panic!("expected one invalid transaction, not {:?}", other),
+        },
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("a sale of more shares than held should not be read"),
+    }
+}
+
+#[test]
+fn reports_splits_recorded_twice() {
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "aapl"
+ratio = "4:1"
+"#;
+    match read_str(source) {
+        Err(ModelError::Invalid(problems)) => match &problems[..] {
+            [ModelError::InvalidEntry(message, location)] => {
+                assert!(message.contains("applied twice"), "{}", message);
+                assert_at(location, 5, 10);
+                assert_eq!(location.entry, "transaction 1");
+            }
+            other => panic!("expected one invalid transaction, not {:?}", other),
+        },
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("a split recorded twice should not be read"),
+    }
+}
+
+#[test]
+fn reports_split_actions_recorded_twice() {
+    let source = r#"version = 2
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "aapl"
+ratio = "4:1"
+
+[[actions]]
+date = "2020-08-31"
+action = "split"
+symbol = "TSLA"
+ratio = "5:1"
+"#;
+    match read_str(source) {
+        Err(ModelError::Invalid(problems)) => match &problems[..] {
+            [ModelError::InvalidEntry(message, location)] => {
+                assert!(message.contains("more than once"), "{}", message);
+                assert_at(location, 11, 10);
+                assert_eq!(location.entry, "action 2 on aapl");
+            }
+            other => panic!("expected one invalid action, not {:?}", other),
+        },
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("a split recorded twice should not be read"),
+    }
+
+    // nor may a transaction log record the same split twice.
+    let source = r#"version = 2
+
+[[transactions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+
+[[transactions]]
+date = "2020-08-31"
+action = "split"
+symbol = "AAPL"
+ratio = "4:1"
+"#;
+    match read_str(source) {
+        Err(ModelError::Invalid(problems)) => assert_eq!(problems.len(), 1, "{:?}", problems),
+        Err(err) => panic!("expected validation problems, not {:?}", err),
+        Ok(_) => panic!("a split recorded twice should not be read"),
+    }
+}
+
+#[test]
+fn reports_syntax_errors() {
+    let file_name = scratch("syntax.toml");
+    std::fs::write(&file_name, "[[holdings]\nsymbol = \"AAPL\"\n").unwrap();
+    match read_file(Some(file_name.clone())) {
+        Err(ModelError::ParseError(err)) => assert!(err.line_col().is_some(), "{}", err),
+        Err(err) => panic!("expected a parse error, not {:?}", err),
+        Ok(_) => panic!("an invalid file should not be read"),
+    }
+    std::fs::remove_file(&file_name).unwrap();
+}
+
+// ------------------------------------------------------------------------------------------------
+// Private Functions
+// ------------------------------------------------------------------------------------------------
+
+fn assert_at(location: &Location, line: usize, column: usize) {
+    assert_eq!((location.line, location.column), (line, column), "{}", location);
+}